@@ -0,0 +1,434 @@
+//! # The `audit` module keeps an append-only trail of what callers change and run
+//!
+//! This module contains the following main components:
+//!
+//! - [`AuditEntry`]: Who did what to which module, and how it went.
+//! - [`AuditLog`]: Appends entries to the [`Family::Audit`] records, and to a JSON-lines
+//!   file when `audit.path` is set, and reads them back by time range.
+//! - [`AuditQuery`]: The query string of `GET /v1/admin/audit`.
+//!
+//! Entries are stored under the microseconds since the Unix epoch at which they were
+//! recorded, zero padded, so the records of the family are in time order and a time
+//! range is a range of keys. Entries are never updated nor deleted.
+//!
+//! Recording an entry never fails the operation it describes, an entry that can't be
+//! stored is logged instead.
+
+use crate::{
+    auth::Identity,
+    database::{models::WasmModule, schema::Family, RecordStore},
+    errors::{ErrorCode, WessError},
+    workers::{
+        reader::models::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE},
+        writer::models::WriteOps,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The number of microseconds in a second.
+const MICROS: u64 = 1_000_000;
+
+/// # The operations recorded in the audit trail.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Operation {
+    Create,
+    Put,
+    Update,
+    Patch,
+    Delete,
+    Reencrypt,
+    CreateKey,
+    RevokeKey,
+    Run,
+}
+
+impl From<&WriteOps> for Operation {
+    fn from(write_op: &WriteOps) -> Self {
+        match write_op {
+            WriteOps::Create => Operation::Create,
+            WriteOps::Put => Operation::Put,
+            WriteOps::Update => Operation::Update,
+            WriteOps::Patch => Operation::Patch,
+            WriteOps::Delete => Operation::Delete,
+            WriteOps::Reencrypt => Operation::Reencrypt,
+        }
+    }
+}
+
+/// # Whether an audited operation went through.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+/// # An entry of the audit trail.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    /// The key of the entry, set when it is recorded.
+    #[serde(default)]
+    pub id: String,
+    /// Seconds since the Unix epoch, set when the entry is recorded.
+    #[serde(default)]
+    pub timestamp: u64,
+    /// The ID of the API key or the `sub` claim of the caller.
+    pub caller: String,
+    /// The tenant of the caller.
+    pub tenant: String,
+    pub operation: Operation,
+    /// The ID of the module, without its tenant.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    /// The ID of the API key a key operation applies to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// The revision of the module written or deleted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u64>,
+    /// The SHA-256 of the bytecode of the module written or deleted, hex encoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    pub outcome: Outcome,
+    /// The HTTP status of a failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// The [`ErrorCode`] of a failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+}
+
+impl AuditEntry {
+    /// # Creates a new, successful [`AuditEntry`].
+    ///
+    /// ## Arguments
+    ///
+    /// * `caller` - The caller of the request.
+    /// * `operation` - The audited [`Operation`].
+    /// * `module` - The ID of the module, without its tenant, if any.
+    pub fn new(caller: &Identity, operation: Operation, module: Option<&str>) -> Self {
+        Self {
+            id: String::new(),
+            timestamp: 0,
+            caller: caller.id.clone(),
+            tenant: caller.tenant_name().to_string(),
+            operation,
+            module: module.map(str::to_string),
+            key: None,
+            revision: None,
+            hash: None,
+            outcome: Outcome::Success,
+            status: None,
+            code: None,
+        }
+    }
+
+    /// # Sets the API key a key operation applies to.
+    pub fn key(mut self, key: Option<&str>) -> Self {
+        self.key = key.map(str::to_string);
+        self
+    }
+
+    /// # Sets the revision and hash of the module the operation applied to.
+    pub fn stored(&mut self, wasm_module: &WasmModule) {
+        self.revision = Some(wasm_module.metadata.revision);
+        self.hash = Some(sha256::digest(wasm_module.to_binary()));
+    }
+
+    /// # Marks the operation as failed with the given error.
+    pub fn failed(mut self, e: &WessError) -> Self {
+        self.outcome = Outcome::Failure;
+        self.status = Some(e.status.into());
+        self.code = Some(e.code);
+        self
+    }
+
+    /// # Sets the outcome of the operation from its result.
+    pub fn outcome<T>(self, r: &Result<T, WessError>) -> Self {
+        match r {
+            Ok(_) => self,
+            Err(e) => self.failed(e),
+        }
+    }
+}
+
+/// # The query string of a request reading the audit trail.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct AuditQuery {
+    /// Only reads entries recorded at or after this time, in seconds since the Unix epoch.
+    pub from: Option<u64>,
+    /// Only reads entries recorded before this time, in seconds since the Unix epoch.
+    pub to: Option<u64>,
+    /// The ID of the last entry of the previous page.
+    pub cursor: Option<String>,
+    /// The maximum number of entries in the page.
+    pub limit: Option<usize>,
+    /// Only reads the entries of the callers of this tenant.
+    pub tenant: Option<String>,
+}
+
+impl AuditQuery {
+    /// # Returns the page size, between 1 and [`MAX_PAGE_SIZE`].
+    pub fn page_size(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+/// # A page of the audit trail, oldest entries first.
+#[derive(Serialize, Debug)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    /// The cursor of the next page, `None` on the last page.
+    pub next: Option<String>,
+}
+
+/// # Appends entries to the audit trail and reads them back.
+#[derive(Clone)]
+pub struct AuditLog {
+    store: Arc<dyn RecordStore>,
+    file: Option<Arc<Mutex<File>>>,
+    last: Arc<AtomicU64>,
+}
+
+impl AuditLog {
+    /// # Creates a new instance of the [`AuditLog`] struct.
+    ///
+    /// ## Arguments
+    ///
+    /// * `store` - The [`RecordStore`] holding the entries.
+    /// * `path` - A JSON-lines file every entry is also appended to, see `audit.path`.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the [`AuditLog`], or a `WessError` object with status 500
+    ///   if the file can't be opened.
+    pub fn new(store: Arc<dyn RecordStore>, path: Option<&str>) -> Result<Self, WessError> {
+        let file = path
+            .map(|path| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| log_error!(format!("Audit File: {}: {}", path, e), 500))
+            })
+            .transpose()?;
+        // Entries are keyed by time, starting after the newest one keeps a clock that
+        // went back since the last run from overwriting entries
+        let last = store
+            .last_record(Family::Audit)?
+            .and_then(|(key, _)| key.parse::<u64>().ok())
+            .unwrap_or_default();
+
+        Ok(Self {
+            store,
+            file: file.map(|file| Arc::new(Mutex::new(file))),
+            last: Arc::new(AtomicU64::new(last)),
+        })
+    }
+
+    /// # Records an entry, stamped with the current time.
+    ///
+    /// ## Returns
+    ///
+    /// * The ID of the entry.
+    pub fn record(&self, mut entry: AuditEntry) -> String {
+        let micros = self.next_micros();
+        entry.id = format!("{:020}", micros);
+        entry.timestamp = micros / MICROS;
+
+        let line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                log_error!(format!("Audit Error: {}", e), 500);
+                return entry.id;
+            }
+        };
+        if let Err(e) = self
+            .store
+            .put_record(Family::Audit, &entry.id, line.as_bytes())
+        {
+            log_error!(format!("Audit Error: {}", e.msg), 500);
+        }
+        if let Some(file) = &self.file {
+            if let Err(e) = writeln!(file.lock().unwrap(), "{}", line) {
+                log_error!(format!("Audit Error: {}", e), 500);
+            }
+        }
+        entry.id
+    }
+
+    /// # Reads the entries recorded in a time range, oldest first.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with an [`AuditPage`], or a `WessError` object with status 400
+    ///   if the cursor isn't the ID of an entry.
+    pub fn query(&self, query: &AuditQuery) -> Result<AuditPage, WessError> {
+        let limit = query.page_size();
+        let mut from = query.from.map_or(0, |from| from.saturating_mul(MICROS));
+        if let Some(cursor) = &query.cursor {
+            let after = cursor.parse::<u64>().map_err(|_| {
+                log_error!(format!("Invalid Cursor: {}", cursor), 400).code(ErrorCode::BadRequest)
+            })?;
+            from = from.max(after.saturating_add(1));
+        }
+        let to = format!(
+            "{:020}",
+            query.to.map_or(u64::MAX, |to| to.saturating_mul(MICROS))
+        );
+
+        // Entries of other tenants are skipped, so reading a page may take several scans
+        let mut entries: Vec<AuditEntry> = vec![];
+        loop {
+            let records =
+                self.store
+                    .range_records(Family::Audit, &format!("{:020}", from), &to, limit)?;
+            let scanned = records.len();
+            if let Some((key, _)) = records.last() {
+                from = key
+                    .parse::<u64>()
+                    .map_or(u64::MAX, |key| key.saturating_add(1));
+            }
+            entries.extend(
+                records
+                    .iter()
+                    .filter_map(|(_, record)| serde_json::from_slice::<AuditEntry>(record).ok())
+                    .filter(|entry| {
+                        query
+                            .tenant
+                            .as_ref()
+                            .is_none_or(|tenant| entry.tenant == *tenant)
+                    }),
+            );
+            if entries.len() >= limit || scanned < limit {
+                break;
+            }
+        }
+        entries.truncate(limit);
+
+        let next = match entries.last() {
+            Some(entry) if entries.len() == limit => Some(entry.id.clone()),
+            _ => None,
+        };
+        Ok(AuditPage { entries, next })
+    }
+
+    /// # Returns the current time in microseconds, always after the previous entry.
+    fn next_micros(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or_default();
+        let last = self
+            .last
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last.saturating_add(1)))
+            })
+            .unwrap();
+        now.max(last.saturating_add(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::MemoryDB;
+    use std::{env, fs};
+
+    #[test]
+    fn test_record_and_query() {
+        let path = env::temp_dir().join(format!("wess-audit-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let audit = AuditLog::new(Arc::new(MemoryDB::new()), path.to_str()).unwrap();
+        let caller = Identity::anonymous();
+
+        let mut entry = AuditEntry::new(&caller, Operation::Create, Some("sum"));
+        entry.stored(&WasmModule::default());
+        let first = audit.record(entry);
+        let e = log_error!("Not found".to_string(), 404).code(ErrorCode::ModuleNotFound);
+        let second =
+            audit.record(AuditEntry::new(&caller, Operation::Delete, Some("sum")).failed(&e));
+        assert!(second > first);
+
+        let page = audit.query(&AuditQuery::default()).unwrap();
+        assert_eq!(page.entries.len(), 2);
+        assert_eq!(page.next, None);
+        assert_eq!(page.entries[0].caller, "anonymous");
+        assert_eq!(page.entries[0].revision, Some(0));
+        assert_eq!(page.entries[1].outcome, Outcome::Failure);
+        assert_eq!(page.entries[1].code, Some(ErrorCode::ModuleNotFound));
+
+        let query = AuditQuery {
+            limit: Some(1),
+            ..Default::default()
+        };
+        let page = audit.query(&query).unwrap();
+        assert_eq!(page.next, Some(first.clone()));
+        let query = AuditQuery {
+            cursor: page.next,
+            ..query
+        };
+        assert_eq!(audit.query(&query).unwrap().entries[0].id, second);
+
+        let now = page.entries[0].timestamp;
+        let query = |from, to| AuditQuery {
+            from: Some(from),
+            to: Some(to),
+            ..Default::default()
+        };
+        assert_eq!(audit.query(&query(now, now + 2)).unwrap().entries.len(), 2);
+        assert!(audit.query(&query(0, now - 1)).unwrap().entries.is_empty());
+        let other = Identity {
+            tenant: Some("other".to_string()),
+            ..Identity::root()
+        };
+        let third = audit.record(AuditEntry::new(&other, Operation::Delete, Some("sum")));
+        let query = AuditQuery {
+            limit: Some(1),
+            tenant: Some("other".to_string()),
+            ..Default::default()
+        };
+        let page = audit.query(&query).unwrap();
+        assert_eq!(page.entries[0].id, third);
+        assert_eq!(page.next, Some(third));
+
+        assert!(audit
+            .query(&AuditQuery {
+                cursor: Some("x".to_string()),
+                ..Default::default()
+            })
+            .is_err());
+
+        let lines = fs::read_to_string(&path).unwrap();
+        assert_eq!(lines.lines().count(), 3);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_ids_follow_the_newest_entry() {
+        let store = Arc::new(MemoryDB::new());
+        let ahead = format!("{:020}", u64::MAX / 2);
+        store.put_record(Family::Audit, &ahead, b"{}").unwrap();
+
+        let audit = AuditLog::new(store, None).unwrap();
+        let id = audit.record(AuditEntry::new(
+            &Identity::anonymous(),
+            Operation::Create,
+            None,
+        ));
+        assert!(id > ahead);
+    }
+}
@@ -0,0 +1,176 @@
+//! # The `acl` module checks what a caller may do with a module
+//!
+//! - Admins may do anything.
+//! - Publishers may create modules. The owner of a module may do anything with it.
+//! - Everyone else needs an entry in the [`Acl`] of the module: `run` to read and
+//!   run it, `manage` to also update, patch and delete it.
+//!
+//! Modules without an owner, stored before authentication was enabled, can be run
+//! by anyone and managed by admins only.
+
+use super::{Identity, Role};
+use crate::{
+    database::models::{Acl, WasmMetadata},
+    errors::{ErrorCode, WessError},
+};
+
+/// The [`Acl`] entry granting a permission to every caller.
+pub const EVERYONE: &str = "*";
+
+/// # What a caller asks to do with a module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Read the module and run its function.
+    Run,
+    /// Update, patch or delete the module.
+    Manage,
+}
+
+/// # Checks that a caller may create modules.
+///
+/// ## Returns
+///
+/// * A `Result` object, or a `WessError` object with status 403 for invokers.
+pub fn authorize_create(identity: &Identity) -> Result<(), WessError> {
+    match identity.role {
+        Role::Admin | Role::Publisher => Ok(()),
+        Role::Invoker => Err(forbidden(identity, "create modules")),
+    }
+}
+
+/// # Checks that a caller may use a module.
+///
+/// ## Arguments
+///
+/// * `identity` - The caller of the request.
+/// * `metadata` - The [`WasmMetadata`] of the stored module.
+/// * `permission` - What the caller asks to do.
+///
+/// ## Returns
+///
+/// * A `Result` object, or a `WessError` object with status 403 if the caller
+///   isn't allowed.
+pub fn authorize(
+    identity: &Identity,
+    metadata: &WasmMetadata,
+    permission: Permission,
+) -> Result<(), WessError> {
+    if is_allowed(identity, metadata, permission) {
+        Ok(())
+    } else {
+        let action = match permission {
+            Permission::Run => "run this module",
+            Permission::Manage => "manage this module",
+        };
+        Err(forbidden(identity, action))
+    }
+}
+
+/// # Whether a caller may use a module, see [`authorize`].
+///
+/// Used to filter lists of modules without logging an error for every module
+/// the caller can't see.
+pub fn is_allowed(identity: &Identity, metadata: &WasmMetadata, permission: Permission) -> bool {
+    if identity.is_admin() {
+        return true;
+    }
+
+    match &metadata.owner {
+        Some(owner) if *owner == identity.id => true,
+        Some(_) => {
+            let Acl { run, manage } = &metadata.acl;
+            let granted = |entries: &Vec<String>| {
+                entries
+                    .iter()
+                    .any(|entry| entry == EVERYONE || *entry == identity.id)
+            };
+            match permission {
+                Permission::Run => granted(run) || granted(manage),
+                Permission::Manage => granted(manage),
+            }
+        }
+        None => permission == Permission::Run,
+    }
+}
+
+fn forbidden(identity: &Identity, action: &str) -> WessError {
+    log_error!(format!("Forbidden: {} can't {}", identity.id, action), 403)
+        .code(ErrorCode::Forbidden)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(id: &str, role: Role) -> Identity {
+        Identity {
+            id: id.to_string(),
+            name: id.to_string(),
+            role,
+            tenant: None,
+        }
+    }
+
+    fn owned_by(owner: &str, acl: Acl) -> WasmMetadata {
+        WasmMetadata {
+            owner: Some(owner.to_string()),
+            acl,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_owner_and_admin() {
+        let metadata = owned_by("alice", Acl::default());
+
+        assert!(authorize(
+            &identity("alice", Role::Invoker),
+            &metadata,
+            Permission::Manage
+        )
+        .is_ok());
+        assert!(authorize(
+            &identity("root", Role::Admin),
+            &metadata,
+            Permission::Manage
+        )
+        .is_ok());
+
+        let err = authorize(
+            &identity("bob", Role::Publisher),
+            &metadata,
+            Permission::Run,
+        )
+        .unwrap_err();
+        assert_eq!(err.status, 403);
+        assert_eq!(err.code, ErrorCode::Forbidden);
+    }
+
+    #[test]
+    fn test_acl_entries() {
+        let metadata = owned_by(
+            "alice",
+            Acl {
+                run: vec![EVERYONE.to_string()],
+                manage: vec!["bob".to_string()],
+            },
+        );
+        let bob = identity("bob", Role::Invoker);
+        let carol = identity("carol", Role::Invoker);
+
+        assert!(authorize(&bob, &metadata, Permission::Manage).is_ok());
+        assert!(authorize(&carol, &metadata, Permission::Run).is_ok());
+        assert!(authorize(&carol, &metadata, Permission::Manage).is_err());
+    }
+
+    #[test]
+    fn test_unowned_module_and_create() {
+        let metadata = WasmMetadata::default();
+        let carol = identity("carol", Role::Invoker);
+
+        assert!(authorize(&carol, &metadata, Permission::Run).is_ok());
+        assert!(authorize(&carol, &metadata, Permission::Manage).is_err());
+        assert!(authorize_create(&carol).is_err());
+        assert!(authorize_create(&identity("bob", Role::Publisher)).is_ok());
+    }
+}
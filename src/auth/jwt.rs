@@ -0,0 +1,374 @@
+//! # The `jwt` module verifies JSON Web Tokens issued by an identity provider
+//!
+//! This module contains the following main components:
+//!
+//! - [`JwtVerifier`]: Checks the signature and claims of a token against the keys of
+//!   a JWKS file, configured in `[auth.jwt]` of `wess.toml`.
+//!
+//! Tokens must be signed with one of the [`ALGORITHMS`] and carry `sub` and `exp`.
+//! `nbf` is checked when present, `aud` and `iss` when configured. The JWKS file is
+//! read again whenever it changes, so keys can be rotated without a restart.
+
+use super::{Identity, Role, Source};
+use crate::{
+    config::JwtConfig,
+    errors::{ErrorCode, WessError},
+    tenants,
+};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use log::info;
+use serde_json::{Map, Value};
+use std::{fs, sync::RwLock, time::SystemTime};
+
+/// The algorithms tokens may be signed with.
+pub const ALGORITHMS: [Algorithm; 3] = [Algorithm::RS256, Algorithm::ES256, Algorithm::EdDSA];
+
+/// # Verifies JSON Web Tokens against the keys of a JWKS file.
+pub struct JwtVerifier {
+    config: JwtConfig,
+    jwks: RwLock<Jwks>,
+}
+
+/// The keys of the JWKS file, and when it was last modified.
+struct Jwks {
+    modified: Option<SystemTime>,
+    keys: Vec<Jwk>,
+}
+
+impl JwtVerifier {
+    /// # Creates a new instance of the [`JwtVerifier`] struct.
+    ///
+    /// ## Arguments
+    ///
+    /// * `config` - The [`JwtConfig`] read from `[auth.jwt]`.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the verifier, or a `WessError` object if the JWKS file
+    ///   can't be read.
+    pub fn new(config: JwtConfig) -> Result<Self, WessError> {
+        let jwks = Jwks {
+            modified: modified(&config.jwks_path),
+            keys: load(&config.jwks_path)?,
+        };
+
+        Ok(Self {
+            config,
+            jwks: RwLock::new(jwks),
+        })
+    }
+
+    /// # Verifies a token and finds the caller it was issued to.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the [`Identity`] built from the claims of the token,
+    ///   or a `WessError` object with status 401 saying why the token was refused.
+    pub fn verify(&self, token: &str) -> Result<Identity, WessError> {
+        let header = decode_header(token).map_err(|e| unauthorized(e.to_string()))?;
+        if !ALGORITHMS.contains(&header.alg) {
+            return Err(unauthorized(format!(
+                "Unsupported Algorithm: {:?}",
+                header.alg
+            )));
+        }
+
+        let jwk = self.key(header.kid.as_deref())?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(|e| unauthorized(e.to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.config.leeway;
+        validation.validate_nbf = true;
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        if self.config.audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.config.audience);
+        }
+        if let Some(issuer) = &self.config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+
+        let claims = decode::<Map<String, Value>>(token, &key, &validation)
+            .map_err(|e| unauthorized(e.to_string()))?
+            .claims;
+
+        self.identity(&claims)
+    }
+
+    /// # Finds the key a token was signed with, reloading the JWKS file if it changed.
+    ///
+    /// Tokens without `kid` are accepted when the file holds a single key.
+    fn key(&self, kid: Option<&str>) -> Result<Jwk, WessError> {
+        self.reload();
+
+        let jwks = self.jwks.read().unwrap();
+        let key = match kid {
+            Some(kid) => jwks
+                .keys
+                .iter()
+                .find(|jwk| jwk.common.key_id.as_deref() == Some(kid)),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        };
+
+        key.cloned()
+            .ok_or_else(|| unauthorized(format!("Unknown Key: {}", kid.unwrap_or("no kid"))))
+    }
+
+    /// # Reads the JWKS file again if it was modified since it was last read.
+    ///
+    /// The current keys are kept if the new file can't be read.
+    fn reload(&self) {
+        let modified = modified(&self.config.jwks_path);
+        if modified == self.jwks.read().unwrap().modified {
+            return;
+        }
+
+        let mut jwks = self.jwks.write().unwrap();
+        jwks.modified = modified;
+        if let Ok(keys) = load(&self.config.jwks_path) {
+            info!(
+                "Reloaded {} keys from {}",
+                keys.len(),
+                self.config.jwks_path
+            );
+            jwks.keys = keys;
+        }
+    }
+
+    /// # Maps the claims of a token to an [`Identity`].
+    ///
+    /// The caller gets the highest role listed in the roles claim, or
+    /// [`Role::Invoker`] when it lists none. Tokens whose tenant claim isn't a valid
+    /// tenant name are refused.
+    fn identity(&self, claims: &Map<String, Value>) -> Result<Identity, WessError> {
+        let string = |claim: &str| claims.get(claim).and_then(|v| v.as_str()).map(String::from);
+        let id = string("sub").unwrap_or_default();
+
+        let roles: Vec<&str> = match claims.get(&self.config.roles_claim) {
+            Some(Value::Array(roles)) => roles.iter().filter_map(|role| role.as_str()).collect(),
+            Some(Value::String(roles)) => roles.split_whitespace().collect(),
+            _ => vec![],
+        };
+        let role = [Role::Admin, Role::Publisher]
+            .iter()
+            .find(|role| roles.contains(&role.name()))
+            .copied()
+            .unwrap_or_default();
+
+        let tenant = string(&self.config.tenant_claim);
+        if let Some(tenant) = tenant.as_deref().filter(|t| !tenants::is_valid(t)) {
+            return Err(unauthorized(format!("Invalid Tenant: {}", tenant)));
+        }
+
+        Ok(Identity {
+            name: string("name").unwrap_or_else(|| id.clone()),
+            id: Source::Jwt.id(&id),
+            role,
+            tenant,
+        })
+    }
+}
+
+/// # Reads the keys of a JWKS file.
+fn load(path: &str) -> Result<Vec<Jwk>, WessError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| log_error!(format!("Invalid JWKS {}: {}", path, e), 500))?;
+    let jwks: JwkSet = serde_json::from_str(&contents)
+        .map_err(|e| log_error!(format!("Invalid JWKS {}: {}", path, e), 500))?;
+
+    Ok(jwks.keys)
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn unauthorized(msg: String) -> WessError {
+    log_error!(format!("Invalid Token: {}", msg), 401).code(ErrorCode::Unauthorized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use ring::{
+        rand::SystemRandom,
+        rsa::PublicKeyComponents,
+        signature::{
+            EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING,
+        },
+    };
+    use serde_json::json;
+    use std::{env, fs::File, path::PathBuf, time::Duration};
+
+    const RSA_PEM: &str = include_str!("testdata/rsa.pem");
+
+    /// A locally generated key, signing tokens and listed in the JWKS file.
+    struct TestKey {
+        alg: Algorithm,
+        encoding: EncodingKey,
+        jwk: Value,
+    }
+
+    fn ed25519(kid: &str) -> TestKey {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+
+        TestKey {
+            alg: Algorithm::EdDSA,
+            encoding: EncodingKey::from_ed_der(pkcs8.as_ref()),
+            jwk: json!({
+                "kty": "OKP", "crv": "Ed25519", "kid": kid,
+                "x": URL_SAFE_NO_PAD.encode(pair.public_key().as_ref()),
+            }),
+        }
+    }
+
+    fn es256(kid: &str) -> TestKey {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        // An uncompressed point: 0x04, then x and y
+        let point = pair.public_key().as_ref();
+
+        TestKey {
+            alg: Algorithm::ES256,
+            encoding: EncodingKey::from_ec_der(pkcs8.as_ref()),
+            jwk: json!({
+                "kty": "EC", "crv": "P-256", "kid": kid,
+                "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+            }),
+        }
+    }
+
+    fn rs256(kid: &str) -> TestKey {
+        let body: String = RSA_PEM
+            .lines()
+            .filter(|l| !l.starts_with("-----"))
+            .collect();
+        let der = base64::engine::general_purpose::STANDARD
+            .decode(body)
+            .unwrap();
+        let pair = RsaKeyPair::from_der(&der).unwrap();
+        let public = PublicKeyComponents::<Vec<u8>>::from(pair.public());
+
+        TestKey {
+            alg: Algorithm::RS256,
+            encoding: EncodingKey::from_rsa_der(&der),
+            jwk: json!({
+                "kty": "RSA", "kid": kid,
+                "n": URL_SAFE_NO_PAD.encode(&public.n),
+                "e": URL_SAFE_NO_PAD.encode(&public.e),
+            }),
+        }
+    }
+
+    fn write_jwks(path: &PathBuf, keys: &[&TestKey]) {
+        let jwks = json!({ "keys": keys.iter().map(|key| &key.jwk).collect::<Vec<_>>() });
+        fs::write(path, jwks.to_string()).unwrap();
+    }
+
+    fn verifier(name: &str, keys: &[&TestKey]) -> (JwtVerifier, PathBuf) {
+        let path = env::temp_dir().join(format!("wess-{}-{}.json", name, std::process::id()));
+        write_jwks(&path, keys);
+
+        let config = JwtConfig {
+            jwks_path: path.to_string_lossy().to_string(),
+            audience: vec!["wess".to_string()],
+            issuer: None,
+            tenant_claim: "tenant".to_string(),
+            roles_claim: "roles".to_string(),
+            leeway: 0,
+        };
+        (JwtVerifier::new(config).unwrap(), path)
+    }
+
+    fn sign(key: &TestKey, claims: Value) -> String {
+        let mut header = Header::new(key.alg);
+        header.kid = key.jwk["kid"].as_str().map(String::from);
+        encode(&header, &claims, &key.encoding).unwrap()
+    }
+
+    fn claims() -> Value {
+        json!({
+            "sub": "alice",
+            "aud": "wess",
+            "exp": get_current_timestamp() + 600,
+            "tenant": "acme",
+            "roles": ["invoker", "publisher"],
+        })
+    }
+
+    #[test]
+    fn test_verify_each_algorithm() {
+        let keys = [ed25519("ed"), es256("ec"), rs256("rsa")];
+        let (verifier, path) = verifier("algorithms", &[&keys[0], &keys[1], &keys[2]]);
+
+        for key in keys.iter() {
+            let identity = verifier.verify(&sign(key, claims())).unwrap();
+
+            assert_eq!(identity.id, "jwt:alice");
+            assert_eq!(identity.tenant, Some("acme".to_string()));
+            assert_eq!(identity.role, Role::Publisher);
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reject_claims() {
+        let key = ed25519("ed");
+        let (verifier, path) = verifier("claims", &[&key]);
+        let now = get_current_timestamp();
+
+        let mut expired = claims();
+        expired["exp"] = json!(now - 10);
+        let mut early = claims();
+        early["nbf"] = json!(now + 600);
+        let mut other_audience = claims();
+        other_audience["aud"] = json!("other");
+        let mut anonymous = claims();
+        anonymous.as_object_mut().unwrap().remove("sub");
+        let mut bad_tenant = claims();
+        bad_tenant["tenant"] = json!("acme/other");
+
+        for claims in [expired, early, other_audience, anonymous, bad_tenant].iter() {
+            let err = verifier.verify(&sign(&key, claims.clone())).unwrap_err();
+            assert_eq!(err.status, 401);
+            assert_eq!(err.code, ErrorCode::Unauthorized);
+        }
+
+        let stranger = ed25519("ed");
+        assert!(verifier.verify(&sign(&stranger, claims())).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reload_on_change() {
+        let old = ed25519("old");
+        let new = es256("new");
+        let (verifier, path) = verifier("reload", &[&old]);
+        assert!(verifier.verify(&sign(&new, claims())).is_err());
+
+        write_jwks(&path, &[&new]);
+        let later = SystemTime::now() + Duration::from_secs(5);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+
+        assert!(verifier.verify(&sign(&new, claims())).is_ok());
+        assert!(verifier.verify(&sign(&old, claims())).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
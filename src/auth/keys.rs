@@ -0,0 +1,304 @@
+//! # The `keys` module manages API keys
+//!
+//! This module contains the following main components:
+//!
+//! - [`ApiKey`]: The stored record of an API key, holding the hash of its secret.
+//! - [`KeyRing`]: Creates, lists, revokes and verifies API keys.
+//!
+//! A token has the form `wess_{id}_{secret}`. Only the SHA-256 of the secret is stored,
+//! in the [`Family::Keys`] records of the database, so a token is shown once, when
+//! its key is created, and can't be recovered afterwards.
+
+use super::{Identity, Role, Source};
+use crate::{
+    database::{schema::Family, RecordStore},
+    errors::{ErrorCode, WessError},
+    tenants,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+/// The prefix of every token, so leaked tokens are easy to scan for.
+pub const TOKEN_PREFIX: &str = "wess";
+
+/// # The stored record of an API key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub role: Role,
+    /// The tenant of the key, the default tenant when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// The SHA-256 of the secret, hex encoded.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hash: String,
+    pub created_at: u64,
+}
+
+/// # The body of a request creating an API key.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NewKey {
+    pub name: String,
+    /// The [`Role`] of the key, [`Role::Invoker`] by default.
+    #[serde(default)]
+    pub role: Role,
+    /// The tenant of the key, see [`crate::tenants`].
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+/// # Manages the API keys stored in a [`RecordStore`].
+#[derive(Clone)]
+pub struct KeyRing {
+    store: Arc<dyn RecordStore>,
+    /// The SHA-256 of the root key, hex encoded.
+    root_hash: Option<String>,
+}
+
+impl KeyRing {
+    /// # Creates a new instance of the [`KeyRing`] struct.
+    ///
+    /// ## Arguments
+    ///
+    /// * `store` - The [`RecordStore`] holding the keys.
+    /// * `root_key` - A token with admin rights that isn't stored, see `auth.admin_key`.
+    pub fn new(store: Arc<dyn RecordStore>, root_key: Option<String>) -> Self {
+        Self {
+            store,
+            root_hash: root_key.filter(|key| !key.is_empty()).map(sha256::digest),
+        }
+    }
+
+    /// # Creates and stores a new API key.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the stored [`ApiKey`], without its hash, and its token,
+    ///   or a `WessError` object with status 400 if its tenant isn't valid, or if the key
+    ///   can't be stored.
+    pub fn create(&self, new_key: NewKey) -> Result<(ApiKey, String), WessError> {
+        if let Some(tenant) = new_key.tenant.as_deref().filter(|t| !tenants::is_valid(t)) {
+            return Err(
+                log_error!(format!("Invalid Tenant: {}", tenant), 400).code(ErrorCode::InvalidBody)
+            );
+        }
+        let id = Uuid::new_v4().simple().to_string();
+        let secret = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let key = ApiKey {
+            id: id.clone(),
+            name: new_key.name,
+            role: new_key.role,
+            tenant: new_key.tenant,
+            hash: sha256::digest(secret.as_str()),
+            created_at: now(),
+        };
+
+        let record = serde_json::to_vec(&key).map_err(|e| log_error!(e.to_string(), 500))?;
+        self.store.put_record(Family::Keys, &id, &record)?;
+
+        let token = format!("{}_{}_{}", TOKEN_PREFIX, id, secret);
+        Ok((
+            ApiKey {
+                hash: String::new(),
+                ..key
+            },
+            token,
+        ))
+    }
+
+    /// # Lists the stored API keys, without their hashes.
+    ///
+    /// ## Arguments
+    ///
+    /// * `tenant` - Only lists the keys of this tenant, the tenant of the admin asking.
+    pub fn list(&self, tenant: Option<&str>) -> Result<Vec<ApiKey>, WessError> {
+        Ok(self
+            .store
+            .records(Family::Keys)?
+            .iter()
+            .filter_map(|(_, record)| serde_json::from_slice::<ApiKey>(record).ok())
+            .filter(|key| in_tenant(key, tenant))
+            .map(|key| ApiKey {
+                hash: String::new(),
+                ..key
+            })
+            .collect())
+    }
+
+    /// # Revokes an API key, its token is refused from then on.
+    ///
+    /// ## Arguments
+    ///
+    /// * `id` - The ID of the key.
+    /// * `tenant` - Only revokes a key of this tenant, the tenant of the admin asking.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the ID of the key, or a `WessError` object with
+    ///   status 404 if the key doesn't exist or belongs to another tenant.
+    pub fn revoke(&self, id: &str, tenant: Option<&str>) -> Result<String, WessError> {
+        let key = self
+            .store
+            .get_record(Family::Keys, id)?
+            .and_then(|record| serde_json::from_slice::<ApiKey>(&record).ok());
+        if !key.is_some_and(|key| in_tenant(&key, tenant)) {
+            return Err(
+                log_error!(format!("Unknown Key: {}", id), 404).code(ErrorCode::KeyNotFound)
+            );
+        }
+        self.store.del_record(Family::Keys, id)?;
+
+        Ok(id.to_string())
+    }
+
+    /// # Finds the caller a token belongs to.
+    ///
+    /// ## Returns
+    ///
+    /// * The [`Identity`] of the key, or `None` if the token is malformed, unknown or revoked.
+    pub fn verify(&self, token: &str) -> Option<Identity> {
+        // Digests have the same length whatever the token, so comparing them doesn't
+        // leak the length of the root key either
+        if let Some(root_hash) = &self.root_hash {
+            if secrets_match(root_hash, &sha256::digest(token)) {
+                return Some(Identity::root());
+            }
+        }
+
+        let mut parts = token.splitn(3, '_');
+        let (prefix, id, secret) = (parts.next()?, parts.next()?, parts.next()?);
+        if prefix != TOKEN_PREFIX {
+            return None;
+        }
+
+        let record = self.store.get_record(Family::Keys, id).ok()??;
+        let key = serde_json::from_slice::<ApiKey>(&record).ok()?;
+
+        if !secrets_match(&key.hash, &sha256::digest(secret)) {
+            return None;
+        }
+
+        Some(Identity {
+            id: Source::Key.id(&key.id),
+            name: key.name,
+            role: key.role,
+            tenant: key.tenant,
+        })
+    }
+}
+
+/// # Compares two hashes in constant time.
+///
+/// A comparison stopping at the first differing byte would tell by its timing how
+/// much of a credential matched.
+#[allow(deprecated)] // ring marks it as internal, but still exports it
+fn secrets_match(a: &str, b: &str) -> bool {
+    ring::constant_time::verify_slices_are_equal(a.as_bytes(), b.as_bytes()).is_ok()
+}
+
+/// # Whether a key belongs to a tenant, every key does when the tenant is unset.
+///
+/// Keys without a tenant only belong to the admins of every tenant, though they
+/// work with the default tenant.
+fn in_tenant(key: &ApiKey, tenant: Option<&str>) -> bool {
+    tenant.is_none_or(|tenant| key.tenant.as_deref() == Some(tenant))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{schema::DEFAULT_TENANT, MemoryDB};
+
+    fn key_ring() -> KeyRing {
+        KeyRing::new(Arc::new(MemoryDB::new()), Some("root-token".to_string()))
+    }
+
+    #[test]
+    fn test_create_and_verify() {
+        let keys = key_ring();
+        let (key, token) = keys
+            .create(NewKey {
+                name: "ci".to_string(),
+                role: Role::Invoker,
+                tenant: Some("search".to_string()),
+            })
+            .unwrap();
+
+        let identity = keys.verify(&token).unwrap();
+
+        assert_eq!(identity.id, format!("key:{}", key.id));
+        assert_eq!(identity.name, "ci");
+        assert_eq!(identity.role, Role::Invoker);
+        assert_eq!(identity.tenant_name(), "search");
+        assert!(key.hash.is_empty());
+        assert_eq!(keys.list(None).unwrap(), vec![key.clone()]);
+        assert_eq!(keys.list(Some("search")).unwrap(), vec![key]);
+        assert!(keys.list(Some("other")).unwrap().is_empty());
+        assert_eq!(keys.verify("root-token"), Some(Identity::root()));
+        assert_eq!(keys.verify("root-tokem"), None);
+    }
+
+    #[test]
+    fn test_verify_rejects_bad_tokens() {
+        let keys = key_ring();
+        let (key, token) = keys
+            .create(NewKey {
+                name: "ci".to_string(),
+                role: Role::Invoker,
+                tenant: None,
+            })
+            .unwrap();
+
+        assert_eq!(keys.verify(&format!("wess_{}_wrong", key.id)), None);
+        assert_eq!(keys.verify(&token.replacen("wess", "other", 1)), None);
+        assert_eq!(keys.verify("wess_"), None);
+        assert_eq!(keys.verify(""), None);
+
+        let err = keys
+            .create(NewKey {
+                name: "ci".to_string(),
+                role: Role::Invoker,
+                tenant: Some("search/a".to_string()),
+            })
+            .unwrap_err();
+        assert_eq!(err.status, 400);
+    }
+
+    #[test]
+    fn test_revoke() {
+        let keys = key_ring();
+        let (key, token) = keys
+            .create(NewKey {
+                name: "ci".to_string(),
+                role: Role::Admin,
+                tenant: None,
+            })
+            .unwrap();
+
+        assert_eq!(
+            keys.revoke(&key.id, Some(DEFAULT_TENANT))
+                .unwrap_err()
+                .status,
+            404
+        );
+        keys.revoke(&key.id, None).unwrap();
+
+        assert_eq!(keys.verify(&token), None);
+        assert_eq!(keys.revoke(&key.id, None).unwrap_err().status, 404);
+    }
+}
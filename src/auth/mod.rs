@@ -0,0 +1,164 @@
+//! # The `auth` module identifies the callers of the API
+//!
+//! This module contains the following main components:
+//!
+//! - [`Identity`]: The caller of a request, attached to it by the auth middleware.
+//! - [`Authenticator`]: Finds the caller a bearer token belongs to.
+//! - [`Role`]: What a caller may do regardless of the module it acts on.
+//! - [`keys`]: API keys, stored hashed in the database and checked on every request.
+//! - [`jwt`]: JSON Web Tokens issued by an identity provider, checked against its JWKS.
+//! - [`acl`]: Checks what a caller may do with a given module.
+//! - [`signing`]: Checks the Ed25519 signatures of uploaded modules.
+//!
+//! Authentication is enabled by `auth.enabled` in `wess.toml`. When it is disabled,
+//! every request is made by [`Identity::anonymous`].
+//!
+//! Every caller belongs to a tenant, the one of its API key or token, and only sees
+//! the modules of that tenant, see [`crate::tenants`].
+
+pub mod acl;
+pub mod jwt;
+pub mod keys;
+pub mod signing;
+
+use self::{jwt::JwtVerifier, keys::KeyRing};
+use crate::{
+    database::schema::DEFAULT_TENANT,
+    errors::{ErrorCode, WessError},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// # The role of an API key.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Manages API keys and every module.
+    Admin,
+    /// Uploads modules and manages the modules it owns.
+    Publisher,
+    /// Runs the modules it is granted.
+    #[default]
+    Invoker,
+}
+
+impl Role {
+    /// # The name of the role, as written in API keys and token claims.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Publisher => "publisher",
+            Role::Invoker => "invoker",
+        }
+    }
+}
+
+/// # The caller of a request.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Identity {
+    /// The ID of the caller, prefixed by its [`Source`]: `key:` and the ID of the API
+    /// key, `jwt:` and the `sub` claim of the token, or `cert:` and the subject of the
+    /// client certificate.
+    pub id: String,
+    /// The name given to the API key.
+    pub name: String,
+    /// The [`Role`] of the API key.
+    pub role: Role,
+    /// The tenant the caller belongs to, [`DEFAULT_TENANT`] when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+impl Identity {
+    /// # The caller of every request when authentication is disabled.
+    pub fn anonymous() -> Self {
+        Identity {
+            id: "anonymous".to_string(),
+            name: "anonymous".to_string(),
+            role: Role::Admin,
+            tenant: None,
+        }
+    }
+
+    /// # The caller of requests made with `auth.admin_key`.
+    pub fn root() -> Self {
+        Identity {
+            id: "root".to_string(),
+            name: "root".to_string(),
+            role: Role::Admin,
+            tenant: None,
+        }
+    }
+
+    /// # The tenant whose modules the caller works with.
+    pub fn tenant_name(&self) -> &str {
+        self.tenant.as_deref().unwrap_or(DEFAULT_TENANT)
+    }
+
+    /// # Whether the caller can manage API keys and every module.
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+}
+
+/// # Where a caller was authenticated, the prefix of its [`Identity`] ID.
+///
+/// Prefixing keeps an API key, a token subject and a certificate subject that
+/// happen to share a value from owning each other's modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// An API key.
+    Key,
+    /// A JSON Web Token.
+    Jwt,
+    /// A TLS client certificate.
+    Cert,
+}
+
+impl Source {
+    /// # Prefixes the ID of a caller with the source, e.g. `key:7d1b...`.
+    pub fn id(self, id: &str) -> String {
+        let prefix = match self {
+            Source::Key => "key",
+            Source::Jwt => "jwt",
+            Source::Cert => "cert",
+        };
+        format!("{}:{}", prefix, id)
+    }
+}
+
+/// # Finds the caller a bearer token belongs to.
+#[derive(Clone)]
+pub struct Authenticator {
+    /// The API keys, also managed through the admin endpoints.
+    pub keys: KeyRing,
+    /// The verifier of JSON Web Tokens, when `[auth.jwt]` is set.
+    pub jwt: Option<Arc<JwtVerifier>>,
+}
+
+impl Authenticator {
+    /// # Creates a new instance of the [`Authenticator`] struct.
+    pub fn new(keys: KeyRing, jwt: Option<JwtVerifier>) -> Self {
+        Self {
+            keys,
+            jwt: jwt.map(Arc::new),
+        }
+    }
+
+    /// # Finds the caller a token belongs to, an API key or a JSON Web Token.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the [`Identity`] of the caller, or a `WessError` object
+    ///   with status 401 if the token isn't valid.
+    pub fn verify(&self, token: &str) -> Result<Identity, WessError> {
+        if let Some(identity) = self.keys.verify(token) {
+            return Ok(identity);
+        }
+
+        match &self.jwt {
+            Some(jwt) if token.matches('.').count() == 2 => jwt.verify(token),
+            _ => Err(log_error!("Invalid API key".to_string(), 401).code(ErrorCode::Unauthorized)),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! # The `signing` module checks the Ed25519 signatures of modules
+//!
+//! Publishers sign the WebAssembly bytecode of a module with an Ed25519 key and upload
+//! it with the signature, base64 encoded, and the ID of their key. Wess verifies it
+//! against the `[signing.trusted_keys]` of `wess.toml` and stores both with the module.
+//! Modules uploaded as text are signed over the bytecode they compile to.
+//!
+//! With `signing.require_signed`, modules that aren't signed by a key that is still
+//! trusted are refused at run time.
+
+use crate::{
+    config::SigningConfig,
+    database::models::WasmModule,
+    errors::{ErrorCode, WessError},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::signature::{UnparsedPublicKey, ED25519};
+
+/// # Verifies the signature of an upload.
+///
+/// ## Arguments
+///
+/// * `config` - The [`SigningConfig`] holding the trusted keys.
+/// * `wasm` - The bytecode of the module.
+/// * `signer` - The ID of the key the bytecode was signed with.
+/// * `signature` - The signature, base64 encoded.
+///
+/// ## Returns
+///
+/// * A `Result` object with the decoded signature, or a `WessError` object with
+///   status 400 if it is malformed, made with an untrusted key or doesn't match.
+pub fn verify(
+    config: &SigningConfig,
+    wasm: &[u8],
+    signer: &str,
+    signature: &str,
+) -> Result<Vec<u8>, WessError> {
+    let signature = STANDARD
+        .decode(signature.trim())
+        .map_err(|e| invalid(format!("Invalid Signature: {}", e)))?;
+    let key = config
+        .trusted_keys
+        .get(signer)
+        .ok_or_else(|| invalid(format!("Untrusted Key: {}", signer)))?;
+
+    UnparsedPublicKey::new(&ED25519, key)
+        .verify(wasm, &signature)
+        .map_err(|_| invalid(format!("Invalid Signature: doesn't match key {}", signer)))?;
+
+    Ok(signature)
+}
+
+/// # Checks that a module may run when `signing.require_signed` is set.
+///
+/// The signature is verified again, so a module whose key is no longer trusted
+/// stops running.
+///
+/// ## Returns
+///
+/// * A `Result` object, or a `WessError` object with status 403 if the module
+///   isn't signed by a trusted key.
+pub fn check_signed(config: &SigningConfig, wasm_module: &WasmModule) -> Result<(), WessError> {
+    if !config.require_signed {
+        return Ok(());
+    }
+
+    let metadata = &wasm_module.metadata;
+    let signed = metadata.signer.as_ref().is_some_and(|signer| {
+        config.trusted_keys.get(signer).is_some_and(|key| {
+            UnparsedPublicKey::new(&ED25519, key)
+                .verify(wasm_module.to_binary(), &metadata.signature)
+                .is_ok()
+        })
+    });
+
+    if signed {
+        Ok(())
+    } else {
+        Err(log_error!("Unsigned Module".to_string(), 403).code(ErrorCode::UnsignedModule))
+    }
+}
+
+fn invalid(msg: String) -> WessError {
+    log_error!(msg, 400).code(ErrorCode::InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+
+    const WASM: &[u8] = &[0, 97, 115, 109, 1, 0, 0, 0];
+
+    fn signer() -> (Ed25519KeyPair, SigningConfig) {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+
+        let mut config = SigningConfig::default();
+        config
+            .trusted_keys
+            .insert("ci".to_string(), pair.public_key().as_ref().to_vec());
+        (pair, config)
+    }
+
+    #[test]
+    fn test_verify() {
+        let (pair, config) = signer();
+        let signature = STANDARD.encode(pair.sign(WASM));
+
+        assert_eq!(
+            verify(&config, WASM, "ci", &signature).unwrap(),
+            pair.sign(WASM).as_ref()
+        );
+
+        for (wasm, signer, signature) in [
+            (&WASM[1..], "ci", signature.as_str()),
+            (WASM, "other", signature.as_str()),
+            (WASM, "ci", "not base64!"),
+        ]
+        .iter()
+        {
+            let err = verify(&config, wasm, signer, signature).unwrap_err();
+            assert_eq!(err.status, 400);
+            assert_eq!(err.code, ErrorCode::InvalidSignature);
+        }
+    }
+
+    #[test]
+    fn test_check_signed() {
+        let (pair, mut config) = signer();
+        let mut wasm_module = WasmModule::new(WASM.to_vec(), Default::default());
+        assert!(check_signed(&config, &wasm_module).is_ok());
+
+        config.require_signed = true;
+        assert_eq!(check_signed(&config, &wasm_module).unwrap_err().status, 403);
+
+        wasm_module.metadata.signature = pair.sign(WASM).as_ref().to_vec();
+        wasm_module.metadata.signer = Some("ci".to_string());
+        assert!(check_signed(&config, &wasm_module).is_ok());
+
+        config.trusted_keys.clear();
+        assert!(check_signed(&config, &wasm_module).is_err());
+    }
+}
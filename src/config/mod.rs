@@ -1,5 +1,7 @@
+use crate::{auth::Role, tenants};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use lazy_static::lazy_static;
-use std::{fs, sync::Arc};
+use std::{collections::HashMap, env, fs, sync::Arc, time::Duration};
 use toml::{from_str, Value};
 
 lazy_static! {
@@ -15,30 +17,177 @@ pub struct Config {
     pub reader: ReaderConfig,
     pub writer: WriterConfig,
     pub runner: RunnerConfig,
+    pub auth: AuthConfig,
+    pub signing: SigningConfig,
+    pub tenants: TenantsConfig,
+    pub limits: LimitsConfig,
+    pub audit: AuditConfig,
 }
 
 pub struct ServerConfig {
     pub port: u16,
     pub address: String,
+    /// Serves HTTPS instead of HTTP when `[server.tls]` is set.
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    /// PEM file holding the certificate chain of the server, reloaded when it changes.
+    pub cert_path: String,
+    /// PEM file holding the private key of the server, reloaded when it changes.
+    pub key_path: String,
+    /// PEM file holding the CAs client certificates are checked against, enables mTLS.
+    pub client_ca_path: Option<String>,
+    /// Whether connections without a client certificate are refused.
+    pub require_client_cert: bool,
+    /// The callers client certificates stand for, by the subject of the certificate.
+    pub clients: HashMap<String, TlsClient>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsClient {
+    /// The name of the caller, the subject of the certificate when unset.
+    pub name: String,
+    pub role: Role,
+    pub tenant: Option<String>,
 }
 
 pub struct DatabaseConfig {
     pub db: String,
+    #[cfg(any(feature = "rocksdb", feature = "sqlite"))]
     pub stage: String,
+    /// Directory holding one database per stage, opened at `{path}/{stage}`. When unset,
+    /// RocksDB stays at `./rocksdb/prod`, where Wess kept it before this setting existed.
+    #[cfg(any(feature = "rocksdb", feature = "sqlite"))]
+    pub path: Option<String>,
+    #[cfg(any(feature = "rocksdb", feature = "sqlite"))]
+    pub create_if_missing: bool,
+    /// Size in bytes of the LRU block cache, RocksDB's default when unset.
+    #[cfg(feature = "rocksdb")]
+    pub block_cache_size: Option<usize>,
+    /// One of `none`, `snappy`, `zlib`, `bz2`, `lz4`, `lz4hc` or `zstd`.
+    #[cfg(feature = "rocksdb")]
+    pub compression: String,
+    /// Size in bytes of a memtable, RocksDB's default when unset.
+    #[cfg(feature = "rocksdb")]
+    pub write_buffer_size: Option<usize>,
+    /// Maximum number of open files, `-1` keeps every file open.
+    #[cfg(feature = "rocksdb")]
+    pub max_open_files: i32,
+    /// Encrypts module records at rest when `[database.encryption]` is set.
+    pub encryption: Option<EncryptionConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncryptionConfig {
+    /// The ID of the key new records are encrypted with.
+    pub key_id: String,
+    /// Every key records may be encrypted with, by key ID.
+    pub keys: HashMap<String, KeySource>,
+}
+
+/// # Where an encryption key is read from, 32 bytes base64 encoded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeySource {
+    /// A file holding the key.
+    Path(String),
+    /// An environment variable holding the key.
+    Env(String),
 }
 
 pub struct ReaderConfig {
     pub cache_size: usize,
     pub channel_size: usize,
+    /// How long a job may wait for the Reader before the request answers 503.
+    pub queue_timeout: Duration,
 }
 
 pub struct WriterConfig {
     pub channel_size: usize,
+    /// How long a job may wait for the Writer before the request answers 503.
+    pub queue_timeout: Duration,
 }
 
 pub struct RunnerConfig {
     pub cache_size: usize,
     pub channel_size: usize,
+    /// How long a job may wait for the Runner to start it before the request answers 503.
+    pub queue_timeout: Duration,
+}
+
+pub struct AuthConfig {
+    /// Whether requests must carry an API key, `false` when `[auth]` is missing.
+    pub enabled: bool,
+    /// A key with admin rights that isn't stored in the database, used to create the
+    /// first API keys. The `WESS_ADMIN_KEY` environment variable takes precedence.
+    pub admin_key: Option<String>,
+    /// Accepts JSON Web Tokens besides API keys when `[auth.jwt]` is set.
+    pub jwt: Option<JwtConfig>,
+}
+
+#[derive(Default)]
+pub struct SigningConfig {
+    /// The Ed25519 public keys uploads may be signed with, by key ID.
+    pub trusted_keys: HashMap<String, Vec<u8>>,
+    /// Whether modules not signed by a trusted key are refused at run time.
+    pub require_signed: bool,
+}
+
+#[derive(Default)]
+pub struct TenantsConfig {
+    /// The quota of every tenant without its own.
+    pub quota: Quota,
+    /// The quotas of given tenants, from `[tenants.quotas.{tenant}]`.
+    pub quotas: HashMap<String, Quota>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Quota {
+    /// Maximum number of modules of a tenant, unlimited when unset.
+    pub max_modules: Option<usize>,
+    /// Maximum size in bytes of the bytecode of every module of a tenant, unlimited when unset.
+    pub max_bytes: Option<u64>,
+    /// Seconds a tenant may spend running functions each day (UTC), unlimited when unset.
+    pub cpu_seconds_per_day: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct LimitsConfig {
+    /// The rate of runs of each API key, unlimited when `[limits.per_key]` is missing.
+    pub per_key: Option<RateConfig>,
+    /// The rate of runs of each module, unlimited when `[limits.per_module]` is missing.
+    pub per_module: Option<RateConfig>,
+}
+
+#[derive(Default)]
+pub struct AuditConfig {
+    /// JSON-lines file every audit entry is also appended to, when set.
+    pub path: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateConfig {
+    /// Requests per second, the rate the bucket refills at.
+    pub rate: f64,
+    /// Requests allowed at once when the bucket is full.
+    pub burst: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct JwtConfig {
+    /// JWKS file holding the keys tokens are signed with, reloaded when it changes.
+    pub jwks_path: String,
+    /// The accepted `aud` claims, the audience isn't checked when empty.
+    pub audience: Vec<String>,
+    /// The expected `iss` claim, the issuer isn't checked when unset.
+    pub issuer: Option<String>,
+    /// The claim holding the tenant of the caller.
+    pub tenant_claim: String,
+    /// The claim holding the roles of the caller, a list or a space separated string.
+    pub roles_claim: String,
+    /// Seconds of clock skew allowed when checking `exp` and `nbf`.
+    pub leeway: u64,
 }
 
 impl Config {
@@ -54,6 +203,7 @@ impl Config {
                 .as_str()
                 .expect("missing 'server.address'")
                 .to_owned(),
+            tls: value["server"].get("tls").map(TlsConfig::from_value),
         };
 
         let database = DatabaseConfig {
@@ -61,10 +211,43 @@ impl Config {
                 .as_str()
                 .expect("missing 'database.db'")
                 .to_owned(),
+            #[cfg(any(feature = "rocksdb", feature = "sqlite"))]
             stage: value["database"]["stage"]
                 .as_str()
                 .expect("missing 'database.stage'")
                 .to_owned(),
+            #[cfg(any(feature = "rocksdb", feature = "sqlite"))]
+            path: value["database"]
+                .get("path")
+                .map(|v| v.as_str().expect("invalid 'database.path'").to_owned()),
+            #[cfg(any(feature = "rocksdb", feature = "sqlite"))]
+            create_if_missing: value["database"]
+                .get("create_if_missing")
+                .map(|v| v.as_bool().expect("invalid 'database.create_if_missing'"))
+                .unwrap_or(true),
+            #[cfg(feature = "rocksdb")]
+            block_cache_size: value["database"]
+                .get("block_cache_size")
+                .map(|v| v.as_integer().expect("invalid 'database.block_cache_size'") as usize),
+            #[cfg(feature = "rocksdb")]
+            compression: value["database"]
+                .get("compression")
+                .map(|v| v.as_str().expect("invalid 'database.compression'"))
+                .unwrap_or("snappy")
+                .to_owned(),
+            #[cfg(feature = "rocksdb")]
+            write_buffer_size: value["database"].get("write_buffer_size").map(|v| {
+                v.as_integer()
+                    .expect("invalid 'database.write_buffer_size'") as usize
+            }),
+            #[cfg(feature = "rocksdb")]
+            max_open_files: value["database"]
+                .get("max_open_files")
+                .map(|v| v.as_integer().expect("invalid 'database.max_open_files'") as i32)
+                .unwrap_or(-1),
+            encryption: value["database"]
+                .get("encryption")
+                .map(EncryptionConfig::from_value),
         };
 
         let reader = ReaderConfig {
@@ -74,12 +257,14 @@ impl Config {
             channel_size: value["reader"]["channel_size"]
                 .as_integer()
                 .expect("missing 'reader.channel_size'") as usize,
+            queue_timeout: queue_timeout(&value, "reader"),
         };
 
         let writer = WriterConfig {
             channel_size: value["writer"]["channel_size"]
                 .as_integer()
                 .expect("missing 'writer.channel_size'") as usize,
+            queue_timeout: queue_timeout(&value, "writer"),
         };
 
         let runner = RunnerConfig {
@@ -89,6 +274,88 @@ impl Config {
             channel_size: value["runner"]["channel_size"]
                 .as_integer()
                 .expect("missing 'runner.channel_size'") as usize,
+            queue_timeout: queue_timeout(&value, "runner"),
+        };
+
+        let auth = AuthConfig {
+            enabled: value
+                .get("auth")
+                .and_then(|auth| auth.get("enabled"))
+                .map(|v| v.as_bool().expect("invalid 'auth.enabled'"))
+                .unwrap_or(false),
+            admin_key: env::var("WESS_ADMIN_KEY").ok().or_else(|| {
+                value
+                    .get("auth")
+                    .and_then(|auth| auth.get("admin_key"))
+                    .map(|v| v.as_str().expect("invalid 'auth.admin_key'").to_owned())
+            }),
+            jwt: value
+                .get("auth")
+                .and_then(|auth| auth.get("jwt"))
+                .map(JwtConfig::from_value),
+        };
+
+        let signing = SigningConfig {
+            trusted_keys: value
+                .get("signing")
+                .and_then(|signing| signing.get("trusted_keys"))
+                .and_then(|keys| keys.as_table())
+                .map(|keys| {
+                    keys.iter()
+                        .map(|(id, key)| {
+                            let key = key
+                                .as_str()
+                                .and_then(|key| STANDARD.decode(key).ok())
+                                .filter(|key| key.len() == 32)
+                                .unwrap_or_else(|| panic!("invalid 'signing.trusted_keys.{}'", id));
+                            (id.to_owned(), key)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            require_signed: value
+                .get("signing")
+                .and_then(|signing| signing.get("require_signed"))
+                .map(|v| v.as_bool().expect("invalid 'signing.require_signed'"))
+                .unwrap_or(false),
+        };
+
+        let tenants = match value.get("tenants") {
+            Some(tenants) => TenantsConfig {
+                quota: Quota::from_value(tenants, "tenants"),
+                quotas: tenants
+                    .get("quotas")
+                    .and_then(|quotas| quotas.as_table())
+                    .map(|quotas| {
+                        quotas
+                            .iter()
+                            .map(|(tenant, quota)| {
+                                let path = format!("tenants.quotas.{}", tenant);
+                                (tenant.to_owned(), Quota::from_value(quota, &path))
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            },
+            None => TenantsConfig::default(),
+        };
+
+        let limits = LimitsConfig {
+            per_key: value
+                .get("limits")
+                .and_then(|limits| limits.get("per_key"))
+                .map(|rate| RateConfig::from_value(rate, "limits.per_key")),
+            per_module: value
+                .get("limits")
+                .and_then(|limits| limits.get("per_module"))
+                .map(|rate| RateConfig::from_value(rate, "limits.per_module")),
+        };
+
+        let audit = AuditConfig {
+            path: value
+                .get("audit")
+                .and_then(|audit| audit.get("path"))
+                .map(|v| v.as_str().expect("invalid 'audit.path'").to_owned()),
         };
 
         Ok(Self {
@@ -97,6 +364,224 @@ impl Config {
             reader,
             writer,
             runner,
+            auth,
+            signing,
+            tenants,
+            limits,
+            audit,
+        })
+    }
+}
+
+/// Milliseconds a job waits for a worker when `queue_timeout_ms` is unset.
+const DEFAULT_QUEUE_TIMEOUT_MS: u64 = 5000;
+
+fn queue_timeout(value: &Value, worker: &str) -> Duration {
+    let ms = value[worker]
+        .get("queue_timeout_ms")
+        .map(|v| {
+            v.as_integer()
+                .filter(|ms| *ms > 0)
+                .unwrap_or_else(|| panic!("invalid '{}.queue_timeout_ms'", worker))
+                as u64
         })
+        .unwrap_or(DEFAULT_QUEUE_TIMEOUT_MS);
+    Duration::from_millis(ms)
+}
+
+impl TenantsConfig {
+    /// # Returns the quota of a tenant.
+    pub fn quota(&self, tenant: &str) -> &Quota {
+        self.quotas.get(tenant).unwrap_or(&self.quota)
+    }
+}
+
+impl Quota {
+    fn from_value(quota: &Value, path: &str) -> Self {
+        let limit = |key: &str| {
+            quota.get(key).map(|v| {
+                v.as_integer()
+                    .filter(|limit| *limit >= 0)
+                    .unwrap_or_else(|| panic!("invalid '{}.{}'", path, key)) as u64
+            })
+        };
+
+        Self {
+            max_modules: limit("max_modules").map(|limit| limit as usize),
+            max_bytes: limit("max_bytes"),
+            cpu_seconds_per_day: limit("cpu_seconds_per_day"),
+        }
+    }
+}
+
+impl RateConfig {
+    fn from_value(rate: &Value, path: &str) -> Self {
+        Self {
+            rate: rate
+                .get("rate")
+                .and_then(|v| v.as_float().or_else(|| v.as_integer().map(|v| v as f64)))
+                .filter(|rate| *rate > 0.0)
+                .unwrap_or_else(|| panic!("invalid '{}.rate'", path)),
+            burst: rate
+                .get("burst")
+                .map(|v| {
+                    v.as_integer()
+                        .filter(|burst| *burst > 0)
+                        .unwrap_or_else(|| panic!("invalid '{}.burst'", path))
+                        as u32
+                })
+                .unwrap_or(1),
+        }
+    }
+}
+
+impl EncryptionConfig {
+    fn from_value(encryption: &Value) -> Self {
+        let key_id = encryption
+            .get("key_id")
+            .map(|v| v.as_str().expect("invalid 'database.encryption.key_id'"))
+            .expect("missing 'database.encryption.key_id'")
+            .to_owned();
+        let keys: HashMap<String, KeySource> = encryption
+            .get("keys")
+            .and_then(|keys| keys.as_table())
+            .map(|keys| {
+                keys.iter()
+                    .map(|(id, source)| {
+                        let invalid =
+                            || -> ! { panic!("invalid 'database.encryption.keys.{}'", id) };
+                        if id.is_empty() || id.len() > u8::MAX as usize {
+                            invalid();
+                        }
+                        let source = match (source.get("path"), source.get("env")) {
+                            (Some(path), None) => KeySource::Path(
+                                path.as_str().unwrap_or_else(|| invalid()).to_owned(),
+                            ),
+                            (None, Some(var)) => {
+                                KeySource::Env(var.as_str().unwrap_or_else(|| invalid()).to_owned())
+                            }
+                            _ => invalid(),
+                        };
+                        (id.to_owned(), source)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if !keys.contains_key(&key_id) {
+            panic!("invalid 'database.encryption.key_id': no key {}", key_id);
+        }
+
+        Self { key_id, keys }
+    }
+}
+
+impl TlsConfig {
+    fn from_value(tls: &Value) -> Self {
+        let path = |key: &str| {
+            tls.get(key).map(|v| {
+                v.as_str()
+                    .unwrap_or_else(|| panic!("invalid 'server.tls.{}'", key))
+                    .to_owned()
+            })
+        };
+
+        Self {
+            cert_path: path("cert_path").expect("missing 'server.tls.cert_path'"),
+            key_path: path("key_path").expect("missing 'server.tls.key_path'"),
+            client_ca_path: path("client_ca_path"),
+            require_client_cert: tls
+                .get("require_client_cert")
+                .map(|v| {
+                    v.as_bool()
+                        .expect("invalid 'server.tls.require_client_cert'")
+                })
+                .unwrap_or(false),
+            clients: tls
+                .get("clients")
+                .and_then(|clients| clients.as_table())
+                .map(|clients| {
+                    clients
+                        .iter()
+                        .map(|(subject, client)| {
+                            (subject.to_owned(), TlsClient::from_value(subject, client))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl TlsClient {
+    fn from_value(subject: &str, client: &Value) -> Self {
+        let invalid =
+            |key: &str| -> ! { panic!("invalid 'server.tls.clients.\"{}\".{}'", subject, key) };
+        let string = |key: &str| {
+            client
+                .get(key)
+                .map(|v| v.as_str().unwrap_or_else(|| invalid(key)).to_owned())
+        };
+
+        let tenant = string("tenant");
+        if tenant
+            .as_deref()
+            .is_some_and(|tenant| !tenants::is_valid(tenant))
+        {
+            invalid("tenant");
+        }
+
+        Self {
+            name: string("name").unwrap_or_else(|| subject.to_owned()),
+            role: match string("role") {
+                Some(role) => [Role::Admin, Role::Publisher, Role::Invoker]
+                    .iter()
+                    .copied()
+                    .find(|r| r.name() == role)
+                    .unwrap_or_else(|| invalid("role")),
+                None => Role::default(),
+            },
+            tenant,
+        }
+    }
+}
+
+impl JwtConfig {
+    fn from_value(jwt: &Value) -> Self {
+        let string = |key: &str, default: &str| {
+            jwt.get(key)
+                .map(|v| {
+                    v.as_str()
+                        .unwrap_or_else(|| panic!("invalid 'auth.jwt.{}'", key))
+                })
+                .unwrap_or(default)
+                .to_owned()
+        };
+
+        Self {
+            jwks_path: jwt
+                .get("jwks_path")
+                .and_then(|v| v.as_str())
+                .expect("missing 'auth.jwt.jwks_path'")
+                .to_owned(),
+            audience: match jwt.get("audience") {
+                Some(Value::String(audience)) => vec![audience.to_owned()],
+                Some(Value::Array(audience)) => audience
+                    .iter()
+                    .map(|v| v.as_str().expect("invalid 'auth.jwt.audience'").to_owned())
+                    .collect(),
+                Some(_) => panic!("invalid 'auth.jwt.audience'"),
+                None => vec![],
+            },
+            issuer: jwt
+                .get("issuer")
+                .map(|v| v.as_str().expect("invalid 'auth.jwt.issuer'").to_owned()),
+            tenant_claim: string("tenant_claim", "tenant"),
+            roles_claim: string("roles_claim", "roles"),
+            leeway: jwt
+                .get("leeway")
+                .map(|v| v.as_integer().expect("invalid 'auth.jwt.leeway'") as u64)
+                .unwrap_or(60),
+        }
     }
 }
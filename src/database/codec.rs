@@ -0,0 +1,310 @@
+//! # The `codec` module provides the binary record format used to persist modules
+//!
+//! Each record is laid out as follows:
+//!
+//! ```text
+//! +---------+-------------------+-----------------+-----------------+
+//! | version | header length     | header          | wasm bytecode   |
+//! | 1 byte  | 4 bytes (u32, LE) | `len` bytes     | remaining bytes |
+//! +---------+-------------------+-----------------+-----------------+
+//! ```
+//!
+//! The header holds the [`WasmMetadata`] serialized as JSON, which is small and
+//! keeps the format easy to extend, while the bytecode is stored raw instead of
+//! as a JSON array of numbers. Modules uploaded as WebAssembly text also keep
+//! the original text in the header.
+//!
+//! Records written before this format existed are plain JSON documents. They are
+//! recognized by their first byte (`{`) and can still be decoded, see [`is_legacy`].
+//!
+//! When `[database.encryption]` is set, the whole record, bytecode and header, is
+//! encrypted with AES-256-GCM and wrapped as follows:
+//!
+//! ```text
+//! +---------+---------------+-------------+----------+----------------------------+
+//! | version | key ID length | key ID      | nonce    | encrypted record and tag   |
+//! | 1 byte  | 1 byte        | `len` bytes | 12 bytes | remaining bytes            |
+//! +---------+---------------+-------------+----------+----------------------------+
+//! ```
+//!
+//! The version, the key ID and the key the record is stored under in the database are
+//! authenticated along with the record, see [`crypto`], so a record copied under the
+//! key of another module doesn't decrypt.
+
+use super::{
+    crypto::{Cipher, CIPHER},
+    models::{WasmMetadata, WasmModule},
+};
+use crate::errors::WessError;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+/// The current version of the record format.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// The version of records encrypted at rest.
+pub const ENCRYPTED_VERSION: u8 = 2;
+
+/// Size in bytes of the fixed part of a record: version byte plus header length.
+const PREFIX_LEN: usize = 5;
+
+/// The first byte of a legacy JSON record.
+const LEGACY_MARKER: u8 = b'{';
+
+/// # The header of a binary record.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    metadata: WasmMetadata,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wat: Option<String>,
+}
+
+/// # Encodes a [`WasmModule`] into a binary record, encrypted if `[database.encryption]` is set.
+///
+/// ## Arguments
+///
+/// * `key` - The key the record is stored under.
+/// * `wasm` - The [`WasmModule`] to be encoded.
+///
+/// ## Returns
+///
+/// * A [`Vec<u8>`] containing the encoded record.
+pub fn encode(key: &str, wasm: &WasmModule) -> Vec<u8> {
+    encode_with(key, wasm, CIPHER.as_ref())
+}
+
+/// # Encodes a [`WasmModule`], encrypted with the current key of `cipher` if any.
+pub fn encode_with(key: &str, wasm: &WasmModule, cipher: Option<&Cipher>) -> Vec<u8> {
+    let record = encode_plain(wasm);
+    match cipher {
+        Some(cipher) => {
+            let mut envelope = envelope(cipher.key_id());
+            let sealed = cipher.seal(&aad(&envelope, key), &record);
+            envelope.extend_from_slice(&sealed);
+            envelope
+        }
+        None => record,
+    }
+}
+
+fn encode_plain(wasm: &WasmModule) -> Vec<u8> {
+    let header = serde_json::to_vec(&Header {
+        metadata: wasm.metadata.clone(),
+        wat: wasm.wat.clone(),
+    })
+    .unwrap();
+
+    let mut record = Vec::with_capacity(PREFIX_LEN + header.len() + wasm.wasm.len());
+    record.push(FORMAT_VERSION);
+    record.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    record.extend_from_slice(&header);
+    record.extend_from_slice(&wasm.wasm);
+    record
+}
+
+/// # Decodes a record into a [`WasmModule`].
+///
+/// Binary records, encrypted or not, and legacy JSON records are accepted.
+///
+/// ## Arguments
+///
+/// * `key` - The key the record is stored under.
+/// * `record` - The raw bytes read from the database.
+///
+/// ## Returns
+///
+/// * A [`Result`] containing the decoded [`WasmModule`], or a [`WessError`] if the
+///   record is malformed, has an unknown version or can't be decrypted.
+pub fn decode(key: &str, record: &[u8]) -> Result<WasmModule, WessError> {
+    decode_with(key, record, CIPHER.as_ref())
+}
+
+/// # Decodes a record, decrypting it with the keys of `cipher` if it is encrypted.
+pub fn decode_with(
+    key: &str,
+    record: &[u8],
+    cipher: Option<&Cipher>,
+) -> Result<WasmModule, WessError> {
+    match record.first() {
+        Some(&ENCRYPTED_VERSION) => {
+            let key_id =
+                key_id(record).ok_or_else(|| log_error!("Truncated Record".to_string(), 500))?;
+            let cipher = cipher.ok_or_else(|| {
+                log_error!(
+                    format!(
+                        "Encrypted Record: key {} but encryption is disabled",
+                        key_id
+                    ),
+                    500
+                )
+            })?;
+            let start = 2 + key_id.len();
+            let plain = cipher.open(key_id, &aad(&record[..start], key), &record[start..])?;
+            match plain.first() {
+                Some(&FORMAT_VERSION) => decode_with(key, &plain, None),
+                _ => Err(log_error!("Invalid Encrypted Record".to_string(), 500)),
+            }
+        }
+        Some(&LEGACY_MARKER) => serde_json::from_slice::<WasmModule>(record)
+            .map_err(|e| log_error!(format!("Invalid Legacy Record: {}", e), 500)),
+        Some(&FORMAT_VERSION) => {
+            let (header, body) = split(record)?;
+            Ok(WasmModule {
+                wasm: body.to_vec(),
+                metadata: header.metadata,
+                wat: header.wat,
+            })
+        }
+        Some(v) => Err(log_error!(format!("Unknown Record Version: {}", v), 500)),
+        None => Err(log_error!("Empty Record".to_string(), 500)),
+    }
+}
+
+/// # Checks whether a record was written in the legacy JSON format.
+///
+/// ## Arguments
+///
+/// * `record` - The raw bytes read from the database.
+///
+/// ## Returns
+///
+/// * `true` if the record must be migrated to the binary format.
+pub fn is_legacy(record: &[u8]) -> bool {
+    record.first() == Some(&LEGACY_MARKER)
+}
+
+/// # Gets the ID of the key a record is encrypted with.
+///
+/// ## Returns
+///
+/// * The key ID, or `None` if the record isn't encrypted.
+pub fn key_id(record: &[u8]) -> Option<&str> {
+    match record {
+        [ENCRYPTED_VERSION, len, rest @ ..] if rest.len() >= *len as usize => {
+            std::str::from_utf8(&rest[..*len as usize]).ok()
+        }
+        _ => None,
+    }
+}
+
+/// # Checks whether a record is written the way [`encode_with`] would write it now.
+///
+/// ## Returns
+///
+/// * `true` if the record is encrypted with the current key of `cipher`, or
+///   isn't encrypted when there is no `cipher`.
+pub fn is_current(record: &[u8], cipher: Option<&Cipher>) -> bool {
+    !is_legacy(record) && key_id(record) == cipher.map(Cipher::key_id)
+}
+
+/// # The unencrypted start of an encrypted record: its version and key ID.
+fn envelope(key_id: &str) -> Vec<u8> {
+    let mut envelope = Vec::with_capacity(2 + key_id.len());
+    envelope.push(ENCRYPTED_VERSION);
+    envelope.push(key_id.len() as u8);
+    envelope.extend_from_slice(key_id.as_bytes());
+    envelope
+}
+
+/// # The bytes authenticated with an encrypted record: its envelope and its key.
+fn aad(envelope: &[u8], key: &str) -> Vec<u8> {
+    [envelope, key.as_bytes()].concat()
+}
+
+fn split(record: &[u8]) -> Result<(Header, &[u8]), WessError> {
+    if record.len() < PREFIX_LEN {
+        return Err(log_error!("Truncated Record".to_string(), 500));
+    }
+    let header_len = u32::from_le_bytes(record[1..PREFIX_LEN].try_into().unwrap()) as usize;
+    let body_start = PREFIX_LEN + header_len;
+    if record.len() < body_start {
+        return Err(log_error!("Truncated Record".to_string(), 500));
+    }
+
+    let header = serde_json::from_slice::<Header>(&record[PREFIX_LEN..body_start])
+        .map_err(|e| log_error!(format!("Invalid Record Header: {}", e), 500))?;
+
+    Ok((header, &record[body_start..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "default/sum";
+
+    fn module() -> WasmModule {
+        WasmModule::new(
+            vec![0, 97, 115, 109, 1, 0, 0, 0],
+            WasmMetadata::new(
+                "sum".to_string(),
+                vec![Some(wasmer::Type::I32)],
+                vec![Some(wasmer::Type::I32), Some(wasmer::Type::I32)],
+            ),
+        )
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        let wasm = module();
+        let record = encode(KEY, &wasm);
+
+        assert_eq!(record[0], FORMAT_VERSION);
+        assert!(record.ends_with(&wasm.wasm));
+        assert_eq!(decode(KEY, &record).unwrap(), wasm);
+    }
+
+    #[test]
+    fn test_encode_and_decode_with_wat() {
+        let wasm = WasmModule {
+            wat: Some("(module)".to_string()),
+            ..module()
+        };
+
+        assert_eq!(decode(KEY, &encode(KEY, &wasm)).unwrap(), wasm);
+    }
+
+    #[test]
+    fn test_decode_legacy_json() {
+        let wasm = module();
+        let record = serde_json::to_vec(&wasm).unwrap();
+
+        assert!(is_legacy(&record));
+        assert_eq!(decode(KEY, &record).unwrap(), wasm);
+    }
+
+    #[test]
+    fn test_decode_invalid_records() {
+        let mut record = encode(KEY, &module());
+        record.truncate(PREFIX_LEN + 2);
+
+        assert!(decode(KEY, &record).is_err());
+        assert!(decode(KEY, &[42]).is_err());
+        assert!(decode(KEY, &[]).is_err());
+    }
+
+    #[test]
+    fn test_encrypted_records() {
+        let wasm = module();
+        let key = |id: &str, byte| (id.to_string(), vec![byte; 32]);
+        let old = Cipher::new("old", vec![key("old", 1)]).unwrap();
+        let new = Cipher::new("new", vec![key("old", 1), key("new", 2)]).unwrap();
+
+        let record = encode_with(KEY, &wasm, Some(&old));
+        assert_eq!(record[0], ENCRYPTED_VERSION);
+        assert_eq!(key_id(&record), Some("old"));
+        assert!(!record.windows(3).any(|w| w == b"sum"));
+        assert_eq!(decode_with(KEY, &record, Some(&new)).unwrap(), wasm);
+        assert!(decode_with(KEY, &record, None).is_err());
+
+        assert!(is_current(&record, Some(&old)));
+        assert!(!is_current(&record, Some(&new)));
+        assert!(!is_current(&encode_with(KEY, &wasm, None), Some(&new)));
+        assert!(is_current(&encode_with(KEY, &wasm, None), None));
+
+        let mut tampered = record.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decode_with(KEY, &tampered, Some(&new)).is_err());
+        assert!(decode_with("default/other", &record, Some(&new)).is_err());
+    }
+}
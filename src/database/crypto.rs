@@ -0,0 +1,228 @@
+//! # The `crypto` module encrypts module records at rest
+//!
+//! This module contains the following main components:
+//!
+//! - [`Cipher`]: The AES-256-GCM keys of `[database.encryption]`, by key ID.
+//! - [`CIPHER`]: The [`Cipher`] used by [`codec`], `None` when encryption is disabled.
+//! - [`cipher`]: Gets the [`CIPHER`] of the re-encrypt job.
+//!
+//! Each key is 32 bytes, base64 encoded, read from a file or an environment variable.
+//! New records are encrypted with the key of `database.encryption.key_id`, and every
+//! record keeps the ID of its key, so the other keys still decrypt the records written
+//! before a rotation, until the re-encrypt job rewrites them.
+//!
+//! Records are bound to the key they are stored under: [`codec`] authenticates it
+//! along with the record, so swapping the records of two modules fails to decrypt.
+//!
+//! [`codec`]: super::codec
+
+use crate::{
+    config::{EncryptionConfig, KeySource, CONFIG},
+    errors::WessError,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use lazy_static::lazy_static;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{collections::HashMap, env, fmt, fs};
+
+lazy_static! {
+    pub static ref CIPHER: Option<Cipher> = CONFIG.database.encryption.as_ref().map(|config| {
+        Cipher::load(config).unwrap_or_else(|e| panic!("invalid 'database.encryption': {}", e.msg))
+    });
+}
+
+/// The size in bytes of an AES-256 key.
+const KEY_LEN: usize = 32;
+
+/// # The keys records are encrypted with.
+pub struct Cipher {
+    key_id: String,
+    keys: HashMap<String, LessSafeKey>,
+    rng: SystemRandom,
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ids: Vec<&String> = self.keys.keys().collect();
+        ids.sort();
+        f.debug_struct("Cipher")
+            .field("key_id", &self.key_id)
+            .field("keys", &ids)
+            .finish()
+    }
+}
+
+impl Cipher {
+    /// # Reads every key of an [`EncryptionConfig`].
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the [`Cipher`], or a `WessError` object with status 500
+    ///   if a key can't be read or isn't 32 bytes, base64 encoded.
+    pub fn load(config: &EncryptionConfig) -> Result<Self, WessError> {
+        let keys = config
+            .keys
+            .iter()
+            .map(|(id, source)| {
+                let encoded = match source {
+                    KeySource::Path(path) => fs::read_to_string(path)
+                        .map_err(|e| invalid(format!("{}: {}: {}", id, path, e)))?,
+                    KeySource::Env(var) => {
+                        env::var(var).map_err(|e| invalid(format!("{}: {}: {}", id, var, e)))?
+                    }
+                };
+                let key = STANDARD
+                    .decode(encoded.trim())
+                    .map_err(|e| invalid(format!("{}: {}", id, e)))?;
+                Ok((id.clone(), key))
+            })
+            .collect::<Result<Vec<_>, WessError>>()?;
+
+        Self::new(&config.key_id, keys)
+    }
+
+    /// # Creates a new instance of [`Cipher`] from raw keys.
+    ///
+    /// ## Arguments
+    ///
+    /// * `key_id` - The ID of the key new records are encrypted with.
+    /// * `keys` - Every key, by ID.
+    pub fn new(key_id: &str, keys: Vec<(String, Vec<u8>)>) -> Result<Self, WessError> {
+        let keys = keys
+            .into_iter()
+            .map(|(id, key)| {
+                UnboundKey::new(&AES_256_GCM, &key)
+                    .map(|key| (id.clone(), LessSafeKey::new(key)))
+                    .map_err(|_| invalid(format!("{}: keys are {} bytes", id, KEY_LEN)))
+            })
+            .collect::<Result<HashMap<_, _>, WessError>>()?;
+
+        if !keys.contains_key(key_id) {
+            return Err(invalid(format!("unknown key_id {}", key_id)));
+        }
+
+        Ok(Self {
+            key_id: key_id.to_string(),
+            keys,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// # The ID of the key new records are encrypted with.
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// # Encrypts a record with the current key.
+    ///
+    /// ## Arguments
+    ///
+    /// * `aad` - Bytes authenticated with the record but not encrypted.
+    /// * `plaintext` - The record to be encrypted.
+    ///
+    /// ## Returns
+    ///
+    /// * The nonce followed by the ciphertext and its tag.
+    pub fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = [0; NONCE_LEN];
+        self.rng.fill(&mut nonce).expect("no random nonce");
+
+        let mut body = plaintext.to_vec();
+        self.keys[&self.key_id]
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad),
+                &mut body,
+            )
+            .expect("record too large to encrypt");
+
+        let mut sealed = nonce.to_vec();
+        sealed.append(&mut body);
+        sealed
+    }
+
+    /// # Decrypts a record sealed by [`Cipher::seal`].
+    ///
+    /// ## Arguments
+    ///
+    /// * `key_id` - The ID of the key the record was encrypted with.
+    /// * `aad` - The bytes authenticated with the record.
+    /// * `sealed` - The nonce followed by the ciphertext and its tag.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the record, or a `WessError` object with status 500
+    ///   if the key is unknown or the record was tampered with.
+    pub fn open(&self, key_id: &str, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, WessError> {
+        let key = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| log_error!(format!("Unknown Encryption Key: {}", key_id), 500))?;
+        if sealed.len() < NONCE_LEN {
+            return Err(log_error!("Truncated Record".to_string(), 500));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).unwrap();
+        let mut body = ciphertext.to_vec();
+        let len = key
+            .open_in_place(nonce, Aad::from(aad), &mut body)
+            .map_err(|_| log_error!(format!("Undecryptable Record: key {}", key_id), 500))?
+            .len();
+        body.truncate(len);
+        Ok(body)
+    }
+}
+
+/// # Gets the [`CIPHER`] records are re-encrypted with.
+///
+/// ## Returns
+///
+/// * A `Result` object with the [`Cipher`], or a `WessError` object with status 409
+///   if `[database.encryption]` isn't set.
+pub fn cipher() -> Result<&'static Cipher, WessError> {
+    CIPHER.as_ref().ok_or_else(|| {
+        log_error!(
+            "Encryption Disabled: '[database.encryption]' isn't set".to_string(),
+            409
+        )
+    })
+}
+
+fn invalid(msg: String) -> WessError {
+    log_error!(format!("Invalid Encryption Key: {}", msg), 500)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let cipher = Cipher::new(
+            "new",
+            vec![
+                ("old".into(), vec![1; KEY_LEN]),
+                ("new".into(), vec![2; KEY_LEN]),
+            ],
+        )
+        .unwrap();
+        let sealed = cipher.seal(b"header", b"record");
+
+        assert_ne!(&sealed[NONCE_LEN..NONCE_LEN + 6], b"record");
+        assert_ne!(cipher.seal(b"header", b"record"), sealed);
+        assert_eq!(cipher.open("new", b"header", &sealed).unwrap(), b"record");
+        assert!(cipher.open("old", b"header", &sealed).is_err());
+        assert!(cipher.open("new", b"other", &sealed).is_err());
+        assert!(cipher.open("gone", b"header", &sealed).is_err());
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        assert!(Cipher::new("a", vec![("a".into(), vec![1; 16])]).is_err());
+        assert!(Cipher::new("b", vec![("a".into(), vec![1; KEY_LEN])]).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+//! # The `memory` module provides an in-memory implementation of [`ModuleStore`].
+//!
+//! This module contains the following main components:
+//!
+//! - [`MemoryDB`]: A struct that keeps modules in a sorted map shared between its clones.
+//!
+//! Nothing is persisted, which makes [`MemoryDB`] suitable for tests and for
+//! running Wess where RocksDB isn't available.
+
+use super::{
+    crypto::Cipher,
+    models::WasmModule,
+    schema::{split_key, Family},
+    ModuleStore, RecordStore,
+};
+use crate::errors::{ErrorCode, WessError};
+use log::info;
+use std::{
+    collections::BTreeMap,
+    ops::Bound,
+    sync::{Arc, RwLock},
+};
+
+/// The records of every [`Family`], keyed by family and key.
+type Records = BTreeMap<(Family, String), Vec<u8>>;
+
+/// The `MemoryDB` store keeps modules in memory, sorted by key.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryDB {
+    modules: Arc<RwLock<BTreeMap<String, WasmModule>>>,
+    records: Arc<RwLock<Records>>,
+}
+
+impl MemoryDB {
+    /// # Creates a new, empty instance of the `MemoryDB` structure.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ModuleStore for MemoryDB {
+    fn add(&self, key: &str, wasm: WasmModule) -> Result<String, WessError> {
+        let (tenant, id) = split_key(key);
+        info!(target: "wess::tx", "CREATE {id} tenant={tenant}");
+        self.modules.write().unwrap().insert(key.to_owned(), wasm);
+        Ok(key.to_owned())
+    }
+
+    fn get(&self, key: &str) -> Option<WasmModule> {
+        self.modules.read().unwrap().get(key).cloned()
+    }
+
+    fn all(&self) -> Vec<Option<WasmModule>> {
+        self.modules
+            .read()
+            .unwrap()
+            .values()
+            .map(|wasm| Some(wasm.clone()))
+            .collect()
+    }
+
+    fn upd(&self, key: &str, wasm: WasmModule) -> Result<String, WessError> {
+        match self.modules.write().unwrap().get_mut(key) {
+            Some(current) => {
+                let (tenant, id) = split_key(key);
+                info!(target: "wess::tx", "UPDATE {id} tenant={tenant}");
+                *current = wasm;
+                Ok(key.to_owned())
+            }
+            None => Err(log_error!("Not found".to_string(), 404).code(ErrorCode::ModuleNotFound)),
+        }
+    }
+
+    fn del(&self, key: &str) -> Result<String, WessError> {
+        let (tenant, id) = split_key(key);
+        info!(target: "wess::tx", "DELETE {id} tenant={tenant}");
+        self.modules.write().unwrap().remove(key);
+        Ok(key.to_owned())
+    }
+
+    fn scan(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+        filter: &dyn Fn(&WasmModule) -> bool,
+    ) -> Vec<(String, WasmModule)> {
+        let start = match after {
+            Some(key) if key >= prefix => Bound::Excluded(key.to_owned()),
+            _ => Bound::Included(prefix.to_owned()),
+        };
+
+        self.modules
+            .read()
+            .unwrap()
+            .range((start, Bound::Unbounded))
+            .take_while(|(k, _)| k.starts_with(prefix))
+            .filter(|(_, v)| filter(v))
+            .take(limit)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    fn count(&self) -> usize {
+        self.modules.read().unwrap().len()
+    }
+
+    fn size(&self) -> i64 {
+        self.modules
+            .read()
+            .unwrap()
+            .values()
+            .map(|wasm| wasm.wasm.len() as i64)
+            .sum()
+    }
+
+    /// # Modules are kept decoded, never encrypted, so there is nothing to rewrite.
+    fn reencrypt(&self, _cipher: &Cipher) -> Result<usize, WessError> {
+        Ok(0)
+    }
+}
+
+impl RecordStore for MemoryDB {
+    fn put_record(&self, family: Family, key: &str, value: &[u8]) -> Result<(), WessError> {
+        self.records
+            .write()
+            .unwrap()
+            .insert((family, key.to_owned()), value.to_vec());
+        Ok(())
+    }
+
+    fn get_record(&self, family: Family, key: &str) -> Result<Option<Vec<u8>>, WessError> {
+        Ok(self
+            .records
+            .read()
+            .unwrap()
+            .get(&(family, key.to_owned()))
+            .cloned())
+    }
+
+    fn del_record(&self, family: Family, key: &str) -> Result<(), WessError> {
+        self.records
+            .write()
+            .unwrap()
+            .remove(&(family, key.to_owned()));
+        Ok(())
+    }
+
+    fn records(&self, family: Family) -> Result<Vec<(String, Vec<u8>)>, WessError> {
+        Ok(self
+            .records
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|((f, _), _)| *f == family)
+            .map(|((_, k), v)| (k.clone(), v.clone()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::suite;
+
+    #[test]
+    fn test_add_and_get() {
+        suite::test_add_and_get(MemoryDB::new());
+    }
+
+    #[test]
+    fn test_upd_and_del() {
+        suite::test_upd_and_del(MemoryDB::new());
+    }
+
+    #[test]
+    fn test_scan() {
+        suite::test_scan(MemoryDB::new(), "memory_scan");
+    }
+
+    #[test]
+    fn test_count() {
+        suite::test_count(MemoryDB::new());
+    }
+
+    #[test]
+    fn test_records() {
+        suite::test_records(MemoryDB::new());
+    }
+
+    #[test]
+    fn test_range_records() {
+        suite::test_range_records(MemoryDB::new());
+    }
+}
@@ -1,17 +1,27 @@
-//! # The `database` module provides a simple API for interacting with a RocksDB database.
+//! # The `database` module provides the storage layer for WebAssembly modules.
 //!
 //! This module contains the following main components:
 //!
-//! - [`RocksDB`]: A struct that provides a simple API for interacting with a RocksDB database.
+//! - [`ModuleStore`]: A trait describing the operations every storage backend provides.
+//! - [`RecordStore`]: A trait for the raw records of every other [`schema::Family`].
+//! - [`RocksDB`]: A [`ModuleStore`] backed by a RocksDB database.
+//! - [`SqliteDB`]: A [`ModuleStore`] backed by a single SQLite file.
+//! - [`MemoryDB`]: A [`ModuleStore`] kept in memory, lost when the process exits.
 //! - [`WasmModule`]: A struct representing a WebAssembly function.
+//! - [`codec`]: The binary record format used to store a [`WasmModule`].
+//! - [`crypto`]: The keys records are encrypted with when `[database.encryption]` is set.
+//! - [`schema`]: The column families and the migrations between schema versions.
+//!
+//! The backend is selected by `database.db` in `wess.toml` (`rocksdb`, `sqlite` or `memory`).
+//! [`RocksDB`] and [`SqliteDB`] are only built with the `rocksdb` and `sqlite` features.
 //!
 //! # Examples
 //!
 //! ```no_run
-//! use database::RocksDB;
+//! use database::{ModuleStore, RocksDB};
 //! use database::models::WasmModule;
 //!
-//! let mut db = RocksDB::new();
+//! let db = RocksDB::new();
 //! let wasm = WasmModule::new("example_fn", "example.wasm");
 //!
 //! let key = "example_key";
@@ -23,272 +33,178 @@
 
 #![allow(dead_code)]
 
+pub mod codec;
+pub mod crypto;
+pub mod memory;
 pub mod models;
-
-use self::models::WasmModule;
+#[cfg(feature = "rocksdb")]
+pub mod rocks;
+pub mod schema;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+pub use self::memory::MemoryDB;
+#[cfg(feature = "rocksdb")]
+pub use self::rocks::RocksDB;
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteDB;
+
+use self::{crypto::Cipher, models::WasmModule, schema::Family};
 use crate::errors::WessError;
-use crate::metrics::constants::DATABASE_OPERATIONS_TOTAL;
-use crate::metrics::constants::DATABASE_OPERATION_DURATION;
-use lazy_static::lazy_static;
-use log::{error, info};
-use rocksdb::{DBWithThreadMode, IteratorMode, MultiThreaded, Options, DB as DataBase};
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
-
-// Creating the single instance of RocksDB with inter-thread security.
-lazy_static! {
-    pub static ref DB: Arc<Mutex<DBWithThreadMode<MultiThreaded>>> = {
-        let mut options = Options::default();
-        options.create_if_missing(true);
-
-        match DataBase::open_default("./rocksdb/prod") {
-            Ok(db) => Arc::new(Mutex::new(db)),
-            Err(err) => {
-                error!(target: "wess::err","DB dont open: {err}");
-                panic!("DB dont open: {}", err);
-            }
-        }
-    };
-    static ref DEV_DB: Arc<Mutex<DBWithThreadMode<MultiThreaded>>> = {
-        let mut options = Options::default();
-        options.create_if_missing(true);
-
-        match DataBase::open_default("./rocksdb/dev") {
-            Ok(db) => Arc::new(Mutex::new(db)),
-            Err(err) => {
-                error!(target: "wess::err","DEV DB dont open: {err}");
-                panic!("DB dont open: {}", err);
-            }
-        }
-    };
-}
-
-/// The `RocksDB` framework provides a simple API for interacting with the RocksDB database.
-#[derive(Clone, Debug)]
-pub struct RocksDB {
-    db: Arc<Mutex<DBWithThreadMode<MultiThreaded>>>,
-}
 
-impl RocksDB {
-    /// # Creates a new instance of the `RocksDB` structure.
+/// # The operations a storage backend provides to the workers.
+///
+/// Implementations are cheap to clone handles over shared state, so every
+/// worker can hold its own copy.
+pub trait ModuleStore: Send + Sync {
+    /// # Adds a new module under the given key.
+    ///
+    /// ## Arguments
+    ///
+    /// * `key` - A string slice that represents the key to be added.
+    /// * `wasm` - A `WasmModule` object that represents the value to be added.
     ///
     /// ## Returns
     ///
-    /// * An instance of `RocksDB`.
-    pub fn new() -> Self {
-        RocksDB {
-            db: Arc::clone(&DB),
-        }
-    }
+    /// * A `Result` object that returns the key if the operation was successful,
+    ///   or a `WessError` object if the operation failed.
+    fn add(&self, key: &str, wasm: WasmModule) -> Result<String, WessError>;
 
-    /// # [`TEST ONLY`]
+    /// # Gets the module stored under a key.
     ///
-    /// # Creates a new instance of the `RocksDB` structure
-    /// which is used to interact with a temporary RocksDB database,
-    /// used only for testing purposes.
-    ///
-    /// ## Arguments
+    /// ## Returns
     ///
-    /// This function does not take any arguments.
+    /// * An `Option` that returns the module if the key exists, or `None` if it doesn't.
+    fn get(&self, key: &str) -> Option<WasmModule>;
+
+    /// # Gets every stored module.
     ///
     /// ## Returns
     ///
-    /// * An instance of `RocksDB` - a structure that provides a simple API
-    /// for interacting with a temporary RocksDB database used for testing purposes.
-    pub fn dev() -> Self {
-        RocksDB {
-            db: Arc::clone(&DEV_DB),
-        }
-    }
+    /// * A `Vec` with one element per stored key, `None` for records that can't be read.
+    fn all(&self) -> Vec<Option<WasmModule>>;
 
-    /// # Adds a new key-value pair to the RocksDB database.
+    /// # Replaces the module stored under an existing key.
     ///
-    /// ## Arguments
+    /// ## Returns
     ///
-    /// * `key` - A string slice that represents the key to be added.
-    /// * `wasm` - A `WasmModule` object that represents the value to be added.
+    /// * A `Result` object that returns the key if the operation was successful,
+    ///   or a `WessError` object with status 404 if the key doesn't exist.
+    fn upd(&self, key: &str, wasm: WasmModule) -> Result<String, WessError>;
+
+    /// # Deletes the module stored under a key.
     ///
     /// ## Returns
     ///
     /// * A `Result` object that returns the key if the operation was successful,
-    /// or a `WessError` object if the operation failed.
-    pub fn add(&mut self, key: &str, wasm: WasmModule) -> Result<String, WessError> {
-        info!(target: "wess::tx", "CREATE {key}");
-        DATABASE_OPERATIONS_TOTAL
-            .with_label_values(&["write"])
-            .inc();
-        let start = Instant::now();
-
-        let r = self
-            .db
-            .lock()
-            .unwrap()
-            .put(key, serde_json::to_vec(&wasm).unwrap())
-            .map_err(|e| log_error!(e.to_string(), 500))
-            .and_then(|_| Ok(key.to_string()));
-
-        let duration = start.elapsed();
-        DATABASE_OPERATION_DURATION
-            .with_label_values(&["write"])
-            .observe(duration.as_secs_f64());
-
-        r
-    }
+    ///   or a `WessError` object if the operation failed.
+    fn del(&self, key: &str) -> Result<String, WessError>;
 
-    /// # Gets the value of a key in the RocksDB database.
+    /// # Gets up to `limit` modules in key order, starting right after `after`.
     ///
     /// ## Arguments
     ///
-    /// * `key` - A string slice that represents the key to be retrieved.
+    /// * `prefix` - The prefix of every returned key, such as a [`schema::tenant_prefix`].
+    /// * `after` - The last key of the previous page, or `None` to start from the first key.
+    /// * `limit` - The maximum number of modules to return.
+    /// * `filter` - A predicate the returned modules must satisfy.
     ///
     /// ## Returns
     ///
-    /// * An `Option` that returns the value of the key if it exists in the database,
-    /// or `None` if it doesn't.
-    pub fn get(&self, key: &str) -> Option<WasmModule> {
-        DATABASE_OPERATIONS_TOTAL.with_label_values(&["read"]).inc();
-        let start = Instant::now();
-
-        let r = self
-            .db
-            .lock()
-            .unwrap()
-            .get(key)
-            .map_err(|e| log_error!(e.to_string(), 500))
-            .unwrap_or_default();
-
-        let duration = start.elapsed();
-        DATABASE_OPERATION_DURATION
-            .with_label_values(&["read"])
-            .observe(duration.as_secs_f64());
-
-        if let Some(v) = r {
-            Some(serde_json::from_slice::<WasmModule>(&v).unwrap())
-        } else {
-            None
-        }
-    }
-
-    /// # Gets all key-value pairs from the RocksDB database.
+    /// * A `Vec` of key-module pairs sorted by key.
+    fn scan(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+        filter: &dyn Fn(&WasmModule) -> bool,
+    ) -> Vec<(String, WasmModule)>;
+
+    /// # Gets the number of stored modules.
     ///
-    /// ## Returns
-    ///
-    /// * A `Vec` that contains all the key-value pairs in the database.
-    /// Each element of the vector is an `Option` that returns the value if the key exists,
-    /// or `None` if the key doesn't exist.
-    pub fn all(&self) -> Vec<Option<WasmModule>> {
-        DATABASE_OPERATIONS_TOTAL.with_label_values(&["read"]).inc();
-        let start = Instant::now();
-
-        let r = self
-            .db
-            .lock()
-            .unwrap()
-            .iterator(IteratorMode::Start)
-            .map(|item| match item {
-                Ok((_, v)) => Some(serde_json::from_slice(&v).unwrap()),
-                Err(e) => {
-                    log_error!(e.to_string(), 500);
-                    None
-                }
-            })
-            .collect();
+    /// Backends maintain this number on every write, so it doesn't require a scan.
+    fn count(&self) -> usize;
 
-        let duration = start.elapsed();
-        DATABASE_OPERATION_DURATION
-            .with_label_values(&["read"])
-            .observe(duration.as_secs_f64());
+    /// # Gets the estimated size of the stored data in bytes.
+    fn size(&self) -> i64;
 
-        r
-    }
-
-    /// # Updates the value of an existing key in the RocksDB database.
+    /// # Rewrites every stored record not encrypted with the current key.
+    ///
+    /// Records keep their revision and timestamps, only their encryption changes.
+    /// Backends that don't encode their records have nothing to rewrite.
     ///
     /// ## Arguments
     ///
-    /// * `key` - A string slice that represents the key to be updated.
-    /// * `wasm` - A `WasmModule` object that represents the new value to be set.
+    /// * `cipher` - The [`crypto::Cipher`] of `[database.encryption]`.
     ///
     /// ## Returns
     ///
-    /// * A `Result` object that returns the key if the operation was successful,
-    /// or a `WessError` object if the operation failed.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `WessError::NotFound` error if the key doesn't exist in the database.
-    pub fn upd(&mut self, key: &str, wasm: WasmModule) -> Result<String, WessError> {
-        if self.get(&key).is_none() {
-            return Err(log_error!("Not found".to_string(), 404));
-        };
+    /// * A `Result` object with the number of rewritten records, or a `WessError` object
+    ///   if a record can't be decrypted, in which case nothing is rewritten.
+    fn reencrypt(&self, cipher: &Cipher) -> Result<usize, WessError>;
+}
 
-        info!(target: "wess::tx", "UPDATE {key}");
-        DATABASE_OPERATIONS_TOTAL
-            .with_label_values(&["write"])
-            .inc();
-        let new_value = serde_json::to_vec(&wasm).unwrap();
-        let start = Instant::now();
+/// # Raw records kept next to the modules, such as API keys.
+///
+/// Records are grouped by [`Family`] and stored as opaque bytes, the callers own
+/// their encoding. [`Family::Modules`] is reserved to [`ModuleStore`].
+pub trait RecordStore: Send + Sync {
+    /// # Stores a record, replacing any record under the same key.
+    fn put_record(&self, family: Family, key: &str, value: &[u8]) -> Result<(), WessError>;
 
-        self.db
-            .lock()
-            .unwrap()
-            .put(key.as_bytes(), new_value)
-            .map_err(|e| log_error!(e.to_string(), 500))
-            .unwrap();
+    /// # Gets the record stored under a key.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the record, `None` if the key doesn't exist,
+    ///   or a `WessError` object if the operation failed.
+    fn get_record(&self, family: Family, key: &str) -> Result<Option<Vec<u8>>, WessError>;
 
-        let duration = start.elapsed();
-        DATABASE_OPERATION_DURATION
-            .with_label_values(&["write"])
-            .observe(duration.as_secs_f64());
+    /// # Deletes the record stored under a key, if any.
+    fn del_record(&self, family: Family, key: &str) -> Result<(), WessError>;
 
-        Ok(key.to_owned())
-    }
+    /// # Gets every record of a family, in key order.
+    fn records(&self, family: Family) -> Result<Vec<(String, Vec<u8>)>, WessError>;
 
-    /// # Deletes a key from the RocksDB database.
+    /// # Gets the records of a family in a range of keys, in key order.
     ///
     /// ## Arguments
     ///
-    /// * `key` - A string slice that represents the key to be deleted.
+    /// * `from` - The first key of the range, included.
+    /// * `to` - The last key of the range, excluded.
+    /// * `limit` - The maximum number of records returned.
+    fn range_records(
+        &self,
+        family: Family,
+        from: &str,
+        to: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u8>)>, WessError> {
+        Ok(self
+            .records(family)?
+            .into_iter()
+            .filter(|(k, _)| k.as_str() >= from && k.as_str() < to)
+            .take(limit)
+            .collect())
+    }
+
+    /// # Gets the record of a family with the greatest key.
     ///
     /// ## Returns
     ///
-    /// * A `Result` object that returns the key if the operation was successful,
-    /// or a `WessError` object if the operation failed.
-    ///
-    /// # Errors
-    ///
-    /// Returns a `WessError::NotFound` error if the key doesn't exist in the database.
-    pub fn del(&mut self, key: &str) -> Result<String, WessError> {
-        info!(target: "wess::tx", "DELETE {key}");
-        DATABASE_OPERATIONS_TOTAL
-            .with_label_values(&["write"])
-            .inc();
-        let start = Instant::now();
-
-        self.db
-            .lock()
-            .unwrap()
-            .delete(key)
-            .map_err(|e| log_error!(e.to_string(), 500))
-            .unwrap();
-
-        let duration = start.elapsed();
-        DATABASE_OPERATION_DURATION
-            .with_label_values(&["write"])
-            .observe(duration.as_secs_f64());
-
-        Ok(key.to_owned())
+    /// * A `Result` object with the key and the record, `None` if the family is empty,
+    ///   or a `WessError` object if the operation failed.
+    fn last_record(&self, family: Family) -> Result<Option<(String, Vec<u8>)>, WessError> {
+        Ok(self.records(family)?.pop())
     }
 }
 
+/// # The test suite every [`ModuleStore`] implementation must pass.
 #[cfg(test)]
-mod tests {
-    use super::*;
-    use models::WasmModule;
+pub mod suite {
+    use super::{models::WasmModule, schema::Family, ModuleStore, RecordStore};
 
-    #[test]
-    fn test_add_and_get() {
-        let mut db = RocksDB::dev();
+    pub fn test_add_and_get<S: ModuleStore>(db: S) {
         let wasm = WasmModule::default();
         let key = "example_key";
 
@@ -298,12 +214,15 @@ mod tests {
         assert_eq!(wasm_module, wasm);
     }
 
-    #[test]
-    fn test_upd_and_del() {
-        let mut db = RocksDB::dev();
+    pub fn test_upd_and_del<S: ModuleStore>(db: S) {
         let wasm = WasmModule::default();
-        let wasm_updated = WasmModule::default();
-        let key = "example_key";
+        let wasm_updated = WasmModule {
+            wasm: vec![0, 97, 115, 109],
+            ..Default::default()
+        };
+        let key = "example_upd_key";
+
+        assert_eq!(db.upd(key, wasm.clone()).unwrap_err().status, 404);
 
         let _ = db.add(key, wasm).unwrap();
         let _ = db.upd(key, wasm_updated.clone()).unwrap();
@@ -316,4 +235,98 @@ mod tests {
 
         assert_eq!(wasm_module, None);
     }
+
+    pub fn test_scan<S: ModuleStore>(db: S, prefix: &str) {
+        let keys: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .map(|k| format!("{}/{}", prefix, k))
+            .collect();
+        let outside = format!("{}0", prefix);
+        for key in keys.iter().chain([&outside]) {
+            let _ = db.add(key, WasmModule::default()).unwrap();
+        }
+        let prefix = format!("{}/", prefix);
+
+        let page = db.scan(&prefix, None, 10, &|_| true);
+        assert_eq!(page.len(), keys.len());
+        assert_eq!(page[0].0, keys[0]);
+
+        let page = db.scan(&prefix, Some(&keys[0]), 1, &|_| true);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].0, keys[1]);
+
+        let page = db.scan(&prefix, Some(&keys[1]), 10, &|_| true);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].0, keys[2]);
+
+        let page = db.scan(&prefix, Some(&keys[0]), 10, &|wm| {
+            wm.metadata.function_name == "none"
+        });
+        assert!(page.is_empty());
+
+        assert!(db.all().len() > keys.len());
+
+        for key in keys.iter().chain([&outside]) {
+            let _ = db.del(key).unwrap();
+        }
+    }
+
+    pub fn test_count<S: ModuleStore>(db: S) {
+        let key = "example_count_key";
+        let before = db.count();
+
+        let _ = db.add(key, WasmModule::default()).unwrap();
+        let _ = db.add(key, WasmModule::default()).unwrap();
+        assert_eq!(db.count(), before + 1);
+
+        let _ = db.del(key).unwrap();
+        let _ = db.del(key).unwrap();
+        assert_eq!(db.count(), before);
+    }
+
+    pub fn test_records<S: RecordStore>(db: S) {
+        let key = "example_record";
+
+        db.put_record(Family::Jobs, key, b"pending").unwrap();
+        db.put_record(Family::Jobs, key, b"done").unwrap();
+
+        assert_eq!(
+            db.get_record(Family::Jobs, key).unwrap(),
+            Some(b"done".to_vec())
+        );
+        assert_eq!(db.get_record(Family::Keys, key).unwrap(), None);
+        assert!(db
+            .records(Family::Jobs)
+            .unwrap()
+            .contains(&(key.to_string(), b"done".to_vec())));
+
+        db.del_record(Family::Jobs, key).unwrap();
+
+        assert_eq!(db.get_record(Family::Jobs, key).unwrap(), None);
+    }
+
+    pub fn test_range_records<S: RecordStore>(db: S) {
+        for key in ["range_1", "range_2", "range_3", "range_4"].iter() {
+            db.put_record(Family::Audit, key, key.as_bytes()).unwrap();
+        }
+
+        let keys = |from, to, limit| {
+            db.range_records(Family::Audit, from, to, limit)
+                .unwrap()
+                .into_iter()
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(keys("range_2", "range_4", 10), vec!["range_2", "range_3"]);
+        assert_eq!(
+            keys("range_", "range_9", 3),
+            vec!["range_1", "range_2", "range_3"]
+        );
+        assert!(keys("range_5", "range_9", 10).is_empty());
+        assert_eq!(
+            db.last_record(Family::Audit).unwrap().map(|(k, _)| k),
+            Some("range_4".to_string())
+        );
+    }
 }
@@ -6,6 +6,9 @@
 //! - [`wasmer::Type`]: A struct representing an argument for a WebAssembly function, containing a name and a type.
 //! - [`WasmMetadata`]: A struct representing metadata associated with a WebAssembly function, containing its name, return type and a vector of function argument types.
 //! - [`WasmModule`]: A struct representing a WebAssembly function, containing its bytecode and metadata.
+//! - [`Acl`]: A struct listing who besides the owner may run or manage a module.
+//! - [`MetadataPatch`]: A struct representing a partial update of the descriptive fields of a [`WasmMetadata`].
+//! - [`ModuleSummary`]: A struct describing a stored module without its bytecode, used to list modules.
 //!
 //! All types are serializable and deserializable through serde.
 
@@ -17,8 +20,6 @@ pub type Wasm = Vec<u8>;
 /// # Represents metadata associated with a WebAssembly function.
 #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct WasmMetadata {
-    // pub owner: Vec<u8>,
-    // pub signature: Vec<u8>,
     /// The name of the function.
     #[serde(rename = "functionName")]
     pub function_name: String,
@@ -27,14 +28,68 @@ pub struct WasmMetadata {
     pub return_type: Vec<Option<wasmer::Type>>,
     /// A vector of function argument types.
     pub args: Vec<Option<wasmer::Type>>,
+    /// A human readable name of the module.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// A description of what the module does.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Free-form labels used to group and filter modules.
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// The name of the caller who uploaded the current bytecode, or the `X-Wess-Uploader`
+    /// header of the upload when authentication is disabled.
+    #[serde(default)]
+    pub uploader: Option<String>,
+    /// The ID of the caller that created the module, kept across updates. Unset for
+    /// modules created while authentication is disabled.
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Who besides the owner may run or manage the module.
+    #[serde(default)]
+    pub acl: Acl,
+    /// The Ed25519 signature of the bytecode, empty if the module isn't signed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signature: Vec<u8>,
+    /// The ID of the trusted key the signature was verified with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+    /// The number of writes of the module, starting at 1 when it is created.
+    #[serde(default)]
+    pub revision: u64,
+    /// When the module was created, in seconds since the Unix epoch.
+    #[serde(default, rename = "createdAt")]
+    pub created_at: u64,
+    /// When the module was last updated, in seconds since the Unix epoch.
+    #[serde(default, rename = "updatedAt")]
+    pub updated_at: u64,
 }
 
+/// # Lists who besides the owner of a module may use it.
+///
+/// Entries are the IDs of API keys, or `*` for every caller.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Acl {
+    /// Who may read and run the module.
+    #[serde(default)]
+    pub run: Vec<String>,
+    /// Who may also update, patch and delete the module.
+    #[serde(default)]
+    pub manage: Vec<String>,
+}
+
+/// # Represents a WebAssembly function, its bytecode and metadata.
 #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct WasmModule {
     /// The WebAssembly bytecode.
+    #[serde(default)]
     pub wasm: Wasm,
     /// The metadata associated with the function.
     pub metadata: WasmMetadata,
+    /// The WebAssembly text the bytecode was compiled from, if it was uploaded as text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wat: Option<String>,
 }
 
 impl WasmMetadata {
@@ -58,6 +113,29 @@ impl WasmMetadata {
             function_name,
             return_type,
             args,
+            ..Default::default()
+        }
+    }
+}
+
+impl WasmMetadata {
+    /// # Applies a [`MetadataPatch`], replacing only the fields it sets.
+    ///
+    /// ## Arguments
+    ///
+    /// * `patch` - The [`MetadataPatch`] to be applied.
+    pub fn apply(&mut self, patch: MetadataPatch) {
+        if let Some(name) = patch.name {
+            self.name = Some(name);
+        }
+        if let Some(description) = patch.description {
+            self.description = Some(description);
+        }
+        if let Some(labels) = patch.labels {
+            self.labels = labels;
+        }
+        if let Some(acl) = patch.acl {
+            self.acl = acl;
         }
     }
 }
@@ -74,7 +152,11 @@ impl WasmModule {
     ///
     /// * An instance of [`WasmModule`].
     pub fn new(wasm: Wasm, metadata: WasmMetadata) -> Self {
-        Self { wasm, metadata }
+        Self {
+            wasm,
+            metadata,
+            wat: None,
+        }
     }
     /// # Convert the [`Wasm`] bytecode of a [`WasmModule`] instance to a byte slice.
     ///
@@ -86,4 +168,137 @@ impl WasmModule {
     pub fn to_binary(&self) -> &[u8] {
         self.wasm.as_slice()
     }
+
+    /// # Returns the ETag of the module, its quoted revision.
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.metadata.revision)
+    }
+}
+
+/// # Represents a partial update of the descriptive fields of a [`WasmMetadata`].
+///
+/// Fields left out of the request are kept as they are. The function name and
+/// types describe the bytecode, so they only change by uploading a new one.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MetadataPatch {
+    /// The new human readable name of the module.
+    pub name: Option<String>,
+    /// The new description of the module.
+    pub description: Option<String>,
+    /// The new labels of the module, replacing the current ones.
+    pub labels: Option<Vec<String>>,
+    /// The new [`Acl`] of the module, replacing the current one.
+    pub acl: Option<Acl>,
+}
+
+/// # Represents the signature of a WebAssembly function.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Signature {
+    /// A vector of function argument types.
+    pub args: Vec<Option<wasmer::Type>>,
+    /// The return type of the function.
+    #[serde(rename = "returnType")]
+    pub return_type: Vec<Option<wasmer::Type>>,
+}
+
+/// # Describes a stored module without its bytecode.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ModuleSummary {
+    /// The ID of the module.
+    pub id: String,
+    /// The name of the function.
+    #[serde(rename = "functionName")]
+    pub function_name: String,
+    /// A human readable name of the module.
+    pub name: Option<String>,
+    /// A description of what the module does.
+    pub description: Option<String>,
+    /// The signature of the function.
+    pub signature: Signature,
+    /// The size of the WebAssembly bytecode in bytes.
+    pub size: usize,
+    /// The SHA-256 of the WebAssembly bytecode, hex encoded.
+    pub hash: String,
+    /// Free-form labels used to group and filter modules.
+    pub labels: Vec<String>,
+    /// The identity of who uploaded the current bytecode.
+    pub uploader: Option<String>,
+    /// The ID of the caller that created the module.
+    pub owner: Option<String>,
+    /// When the module was created, in seconds since the Unix epoch.
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    /// When the module was last updated, in seconds since the Unix epoch.
+    #[serde(rename = "updatedAt")]
+    pub updated_at: u64,
+}
+
+impl ModuleSummary {
+    /// # Creates a new instance of the [`ModuleSummary`] structure.
+    ///
+    /// ## Arguments
+    ///
+    /// * `id` - The ID of the module.
+    /// * `wasm_module` - The [`WasmModule`] to be described.
+    ///
+    /// ## Returns
+    ///
+    /// * An instance of [`ModuleSummary`].
+    pub fn new(id: String, wasm_module: &WasmModule) -> Self {
+        let metadata = &wasm_module.metadata;
+        Self {
+            id,
+            function_name: metadata.function_name.clone(),
+            name: metadata.name.clone(),
+            description: metadata.description.clone(),
+            signature: Signature {
+                args: metadata.args.clone(),
+                return_type: metadata.return_type.clone(),
+            },
+            size: wasm_module.wasm.len(),
+            hash: sha256::digest(wasm_module.to_binary()),
+            labels: metadata.labels.clone(),
+            uploader: metadata.uploader.clone(),
+            owner: metadata.owner.clone(),
+            created_at: metadata.created_at,
+            updated_at: metadata.updated_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_metadata_patch() {
+        let mut metadata = WasmMetadata {
+            name: Some("Sum".to_string()),
+            description: Some("Adds two numbers".to_string()),
+            labels: vec!["math".to_string()],
+            ..Default::default()
+        };
+
+        metadata.apply(MetadataPatch {
+            description: Some("Adds two integers".to_string()),
+            labels: Some(vec![]),
+            ..Default::default()
+        });
+
+        assert_eq!(metadata.name, Some("Sum".to_string()));
+        assert_eq!(metadata.description, Some("Adds two integers".to_string()));
+        assert!(metadata.labels.is_empty());
+    }
+
+    #[test]
+    fn test_deserialize_metadata_without_optional_fields() {
+        let metadata: WasmMetadata =
+            serde_json::from_str(r#"{"functionName":"sum","returnType":["I32"],"args":[]}"#)
+                .unwrap();
+
+        assert_eq!(metadata.function_name, "sum");
+        assert_eq!(metadata.name, None);
+        assert!(metadata.labels.is_empty());
+    }
 }
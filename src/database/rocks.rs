@@ -0,0 +1,657 @@
+//! # The `rocks` module provides the RocksDB implementation of [`ModuleStore`].
+//!
+//! This module contains the following main components:
+//!
+//! - [`RocksDB`]: A struct that stores modules in a RocksDB database.
+//! - [`DB`]: The single RocksDB instance shared by every [`RocksDB`].
+//!
+//! The database is opened at `{path}/{stage}`, both read from the `[database]`
+//! section of `wess.toml`, with one column family per [`Family`]. Without `path`, it is
+//! opened at [`LEGACY_PATH`], so upgraded deployments keep their data.
+
+use super::{
+    codec,
+    crypto::Cipher,
+    models::WasmModule,
+    schema::{self, Family, MIGRATIONS, MODULE_COUNT_KEY, SCHEMA_VERSION},
+    ModuleStore, RecordStore,
+};
+use crate::config::{DatabaseConfig, CONFIG};
+use crate::errors::{ErrorCode, WessError};
+use crate::metrics::constants::DATABASE_OPERATIONS_TOTAL;
+use crate::metrics::constants::DATABASE_OPERATION_DURATION;
+use lazy_static::lazy_static;
+use log::{error, info};
+use rocksdb::{
+    BlockBasedOptions, Cache, ColumnFamilyDescriptor, DBCompressionType, DBWithThreadMode,
+    Direction, IteratorMode, MultiThreaded, Options, WriteBatch, DB as DataBase,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Where the database was opened before `database.path` was read, whatever the stage.
+pub const LEGACY_PATH: &str = "./rocksdb/prod";
+
+// Creating the single instance of RocksDB with inter-thread security.
+lazy_static! {
+    pub static ref DB: Arc<Mutex<DBWithThreadMode<MultiThreaded>>> = {
+        let config = &CONFIG.database;
+        let path = match &config.path {
+            Some(path) => format!("{}/{}", path, config.stage),
+            None => LEGACY_PATH.to_string(),
+        };
+        open(&path, options(config))
+    };
+    static ref DEV_DB: Arc<Mutex<DBWithThreadMode<MultiThreaded>>> = {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        open("./rocksdb/test", options)
+    };
+}
+
+/// # Builds the RocksDB [`Options`] described by a [`DatabaseConfig`].
+///
+/// ## Arguments
+///
+/// * `config` - The `[database]` section of `wess.toml`.
+///
+/// ## Returns
+///
+/// * The [`Options`] used to open the database and each of its column families.
+fn options(config: &DatabaseConfig) -> Options {
+    let mut options = Options::default();
+    options.create_if_missing(config.create_if_missing);
+    options.set_compression_type(compression(&config.compression));
+    options.set_max_open_files(config.max_open_files);
+
+    if let Some(size) = config.write_buffer_size {
+        options.set_write_buffer_size(size);
+    }
+    if let Some(size) = config.block_cache_size {
+        let mut table = BlockBasedOptions::default();
+        table.set_block_cache(&Cache::new_lru_cache(size));
+        options.set_block_based_table_factory(&table);
+    }
+
+    options
+}
+
+fn compression(name: &str) -> DBCompressionType {
+    match name {
+        "none" => DBCompressionType::None,
+        "snappy" => DBCompressionType::Snappy,
+        "zlib" => DBCompressionType::Zlib,
+        "bz2" => DBCompressionType::Bz2,
+        "lz4" => DBCompressionType::Lz4,
+        "lz4hc" => DBCompressionType::Lz4hc,
+        "zstd" => DBCompressionType::Zstd,
+        _ => panic!("invalid 'database.compression': {}", name),
+    }
+}
+
+/// # Opens a RocksDB database with every column family in [`Family::ALL`].
+///
+/// Missing column families are created, so a database written before they
+/// existed opens with all of them empty until [`RocksDB::migrate`] runs.
+fn open(path: &str, mut options: Options) -> Arc<Mutex<DBWithThreadMode<MultiThreaded>>> {
+    options.create_missing_column_families(true);
+
+    let families = Family::ALL
+        .iter()
+        .map(|family| ColumnFamilyDescriptor::new(family.name(), options.clone()));
+
+    match DataBase::open_cf_descriptors(&options, path, families) {
+        Ok(db) => Arc::new(Mutex::new(db)),
+        Err(err) => {
+            error!(target: "wess::err","DB dont open {path}: {err}");
+            panic!("DB dont open: {}", err);
+        }
+    }
+}
+
+/// The `RocksDB` store keeps modules in the [`Family::Modules`] column family of a RocksDB database.
+#[derive(Clone, Debug)]
+pub struct RocksDB {
+    db: Arc<Mutex<DBWithThreadMode<MultiThreaded>>>,
+}
+
+impl RocksDB {
+    /// # Creates a new instance of the `RocksDB` structure.
+    ///
+    /// ## Returns
+    ///
+    /// * An instance of `RocksDB`.
+    pub fn new() -> Self {
+        RocksDB {
+            db: Arc::clone(&DB),
+        }
+    }
+
+    /// # [`TEST ONLY`]
+    ///
+    /// # Creates a new instance of the `RocksDB` structure
+    /// which is used to interact with a temporary RocksDB database,
+    /// used only for testing purposes.
+    ///
+    /// ## Arguments
+    ///
+    /// This function does not take any arguments.
+    ///
+    /// ## Returns
+    ///
+    /// * An instance of `RocksDB` - a structure that provides a simple API
+    /// for interacting with a temporary RocksDB database used for testing purposes.
+    pub fn dev() -> Self {
+        RocksDB {
+            db: Arc::clone(&DEV_DB),
+        }
+    }
+
+    /// # Stores a raw value under a key of the given column family.
+    ///
+    /// ## Arguments
+    ///
+    /// * `family` - The [`Family`] the key belongs to.
+    /// * `key` - A string slice that represents the key to be written.
+    /// * `value` - The raw bytes to be stored.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object that is empty if the operation was successful,
+    ///   or a `WessError` object if the operation failed.
+    pub fn put_in(&self, family: Family, key: &str, value: &[u8]) -> Result<(), WessError> {
+        let db = self.db.lock().unwrap();
+        let cf = db.cf_handle(family.name()).unwrap();
+        db.put_cf(&cf, key, value)
+            .map_err(|e| log_error!(e.to_string(), 500))
+    }
+
+    /// # Gets the raw value of a key in the given column family.
+    ///
+    /// ## Arguments
+    ///
+    /// * `family` - The [`Family`] the key belongs to.
+    /// * `key` - A string slice that represents the key to be retrieved.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the value if the key exists, `None` if it doesn't,
+    ///   or a `WessError` object if the operation failed.
+    pub fn get_in(&self, family: Family, key: &str) -> Result<Option<Vec<u8>>, WessError> {
+        let db = self.db.lock().unwrap();
+        let cf = db.cf_handle(family.name()).unwrap();
+        db.get_cf(&cf, key)
+            .map_err(|e| log_error!(e.to_string(), 500))
+    }
+
+    /// # Deletes a key from the given column family.
+    ///
+    /// ## Arguments
+    ///
+    /// * `family` - The [`Family`] the key belongs to.
+    /// * `key` - A string slice that represents the key to be deleted.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object that is empty if the operation was successful,
+    ///   or a `WessError` object if the operation failed.
+    pub fn del_in(&self, family: Family, key: &str) -> Result<(), WessError> {
+        let db = self.db.lock().unwrap();
+        let cf = db.cf_handle(family.name()).unwrap();
+        db.delete_cf(&cf, key)
+            .map_err(|e| log_error!(e.to_string(), 500))
+    }
+
+    /// # Gets every key-value pair of the given column family, in key order.
+    ///
+    /// ## Arguments
+    ///
+    /// * `family` - The [`Family`] to be read.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the key-value pairs,
+    ///   or a `WessError` object if the operation failed.
+    pub fn iter_in(&self, family: Family) -> Result<Vec<(String, Vec<u8>)>, WessError> {
+        let db = self.db.lock().unwrap();
+        let cf = db.cf_handle(family.name()).unwrap();
+        db.iterator_cf(&cf, IteratorMode::Start)
+            .map(|item| {
+                item.map(|(k, v)| (String::from_utf8_lossy(&k).into_owned(), v.into_vec()))
+                    .map_err(|e| log_error!(e.to_string(), 500))
+            })
+            .collect()
+    }
+
+    /// # Writes or deletes a module, keeping the module counter in sync.
+    ///
+    /// The existence check and both writes happen under the database lock, and the
+    /// writes are applied as one [`WriteBatch`], so the counter never drifts.
+    ///
+    /// ## Arguments
+    ///
+    /// * `key` - A string slice that represents the key of the module.
+    /// * `wasm` - The module to be stored, or `None` to delete it.
+    fn write_module(&self, key: &str, wasm: Option<&WasmModule>) -> Result<(), WessError> {
+        let db = self.db.lock().unwrap();
+        let modules = db.cf_handle(Family::Modules.name()).unwrap();
+        let metadata = db.cf_handle(Family::Metadata.name()).unwrap();
+
+        let exists = db
+            .get_cf(&modules, key)
+            .map_err(|e| log_error!(e.to_string(), 500))?
+            .is_some();
+        let count = schema::read_count(&db)?;
+
+        let mut batch = WriteBatch::default();
+        match wasm {
+            Some(wasm) => {
+                batch.put_cf(&modules, key, codec::encode(key, wasm));
+                if !exists {
+                    batch.put_cf(&metadata, MODULE_COUNT_KEY, (count + 1).to_le_bytes());
+                }
+            }
+            None if exists => {
+                batch.delete_cf(&modules, key);
+                batch.put_cf(
+                    &metadata,
+                    MODULE_COUNT_KEY,
+                    count.saturating_sub(1).to_le_bytes(),
+                );
+            }
+            None => {}
+        }
+
+        db.write(batch).map_err(|e| log_error!(e.to_string(), 500))
+    }
+
+    /// # Brings the database up to [`SCHEMA_VERSION`].
+    ///
+    /// Runs, in order, every step of [`MIGRATIONS`] newer than the stored schema
+    /// version, recording the version after each step. A database already at
+    /// [`SCHEMA_VERSION`] is left untouched, so it is safe to run this on every startup.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object that returns the schema version of the database if the operation
+    ///   was successful, or a `WessError` object if the operation failed.
+    pub fn migrate(&self) -> Result<u32, WessError> {
+        let db = self.db.lock().unwrap();
+        let current = schema::read_version(&db)?;
+
+        for (version, migration) in MIGRATIONS.iter().filter(|(v, _)| *v > current) {
+            info!(target: "wess::tx", "MIGRATE schema to version {version}");
+            migration(&db)?;
+            schema::write_version(&db, *version)?;
+        }
+
+        Ok(current.max(SCHEMA_VERSION))
+    }
+}
+
+impl ModuleStore for RocksDB {
+    /// # Adds a new key-value pair to the RocksDB database.
+    ///
+    /// ## Arguments
+    ///
+    /// * `key` - A string slice that represents the key to be added.
+    /// * `wasm` - A `WasmModule` object that represents the value to be added.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object that returns the key if the operation was successful,
+    /// or a `WessError` object if the operation failed.
+    fn add(&self, key: &str, wasm: WasmModule) -> Result<String, WessError> {
+        let (tenant, id) = schema::split_key(key);
+        info!(target: "wess::tx", "CREATE {id} tenant={tenant}");
+        DATABASE_OPERATIONS_TOTAL
+            .with_label_values(&["write"])
+            .inc();
+        let start = Instant::now();
+
+        let r = self.write_module(key, Some(&wasm)).map(|_| key.to_string());
+
+        let duration = start.elapsed();
+        DATABASE_OPERATION_DURATION
+            .with_label_values(&["write"])
+            .observe(duration.as_secs_f64());
+
+        r
+    }
+
+    /// # Gets the value of a key in the RocksDB database.
+    ///
+    /// ## Arguments
+    ///
+    /// * `key` - A string slice that represents the key to be retrieved.
+    ///
+    /// ## Returns
+    ///
+    /// * An `Option` that returns the value of the key if it exists in the database,
+    /// or `None` if it doesn't.
+    fn get(&self, key: &str) -> Option<WasmModule> {
+        DATABASE_OPERATIONS_TOTAL.with_label_values(&["read"]).inc();
+        let start = Instant::now();
+
+        let r = self.get_in(Family::Modules, key).unwrap_or_default();
+
+        let duration = start.elapsed();
+        DATABASE_OPERATION_DURATION
+            .with_label_values(&["read"])
+            .observe(duration.as_secs_f64());
+
+        r.and_then(|v| codec::decode(key, &v).ok())
+    }
+
+    /// # Gets all key-value pairs from the RocksDB database.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Vec` that contains all the key-value pairs in the database.
+    /// Each element of the vector is an `Option` that returns the value if the key exists,
+    /// or `None` if the key doesn't exist.
+    fn all(&self) -> Vec<Option<WasmModule>> {
+        DATABASE_OPERATIONS_TOTAL.with_label_values(&["read"]).inc();
+        let start = Instant::now();
+
+        let r = self
+            .iter_in(Family::Modules)
+            .unwrap_or_default()
+            .iter()
+            .map(|(k, v)| codec::decode(k, v).ok())
+            .collect();
+
+        let duration = start.elapsed();
+        DATABASE_OPERATION_DURATION
+            .with_label_values(&["read"])
+            .observe(duration.as_secs_f64());
+
+        r
+    }
+
+    /// # Updates the value of an existing key in the RocksDB database.
+    ///
+    /// ## Arguments
+    ///
+    /// * `key` - A string slice that represents the key to be updated.
+    /// * `wasm` - A `WasmModule` object that represents the new value to be set.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object that returns the key if the operation was successful,
+    /// or a `WessError` object if the operation failed.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `WessError::NotFound` error if the key doesn't exist in the database.
+    fn upd(&self, key: &str, wasm: WasmModule) -> Result<String, WessError> {
+        if self.get(key).is_none() {
+            return Err(log_error!("Not found".to_string(), 404).code(ErrorCode::ModuleNotFound));
+        };
+
+        let (tenant, id) = schema::split_key(key);
+        info!(target: "wess::tx", "UPDATE {id} tenant={tenant}");
+        DATABASE_OPERATIONS_TOTAL
+            .with_label_values(&["write"])
+            .inc();
+        let new_value = codec::encode(key, &wasm);
+        let start = Instant::now();
+
+        self.put_in(Family::Modules, key, &new_value)?;
+
+        let duration = start.elapsed();
+        DATABASE_OPERATION_DURATION
+            .with_label_values(&["write"])
+            .observe(duration.as_secs_f64());
+
+        Ok(key.to_owned())
+    }
+
+    /// # Deletes a key from the RocksDB database.
+    ///
+    /// ## Arguments
+    ///
+    /// * `key` - A string slice that represents the key to be deleted.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object that returns the key if the operation was successful,
+    /// or a `WessError` object if the operation failed.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `WessError::NotFound` error if the key doesn't exist in the database.
+    fn del(&self, key: &str) -> Result<String, WessError> {
+        let (tenant, id) = schema::split_key(key);
+        info!(target: "wess::tx", "DELETE {id} tenant={tenant}");
+        DATABASE_OPERATIONS_TOTAL
+            .with_label_values(&["write"])
+            .inc();
+        let start = Instant::now();
+
+        self.write_module(key, None)?;
+
+        let duration = start.elapsed();
+        DATABASE_OPERATION_DURATION
+            .with_label_values(&["write"])
+            .observe(duration.as_secs_f64());
+
+        Ok(key.to_owned())
+    }
+
+    fn scan(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+        filter: &dyn Fn(&WasmModule) -> bool,
+    ) -> Vec<(String, WasmModule)> {
+        DATABASE_OPERATIONS_TOTAL.with_label_values(&["read"]).inc();
+        let start = Instant::now();
+
+        let db = self.db.lock().unwrap();
+        let cf = db.cf_handle(Family::Modules.name()).unwrap();
+        let from = after.filter(|key| *key >= prefix).unwrap_or(prefix);
+        let mode = IteratorMode::From(from.as_bytes(), Direction::Forward);
+
+        let r = db
+            .iterator_cf(&cf, mode)
+            .filter_map(|item| match item {
+                Ok((k, v)) => Some((String::from_utf8_lossy(&k).into_owned(), v)),
+                Err(e) => {
+                    log_error!(e.to_string(), 500);
+                    None
+                }
+            })
+            .take_while(|(k, _)| k.starts_with(prefix))
+            .filter(|(k, _)| Some(k.as_str()) != after)
+            .filter_map(|(k, v)| codec::decode(&k, &v).ok().map(|wasm| (k, wasm)))
+            .filter(|(_, wasm)| filter(wasm))
+            .take(limit)
+            .collect();
+
+        let duration = start.elapsed();
+        DATABASE_OPERATION_DURATION
+            .with_label_values(&["read"])
+            .observe(duration.as_secs_f64());
+
+        r
+    }
+
+    fn count(&self) -> usize {
+        let db = self.db.lock().unwrap();
+        schema::read_count(&db).unwrap_or_default() as usize
+    }
+
+    fn size(&self) -> i64 {
+        self.db
+            .lock()
+            .unwrap()
+            .property_int_value("rocksdb.estimate-live-data-size")
+            .ok()
+            .flatten()
+            .unwrap_or_default() as i64
+    }
+
+    /// # Rewrites the module records not encrypted with the current key of `cipher`.
+    ///
+    /// The records are read and rewritten under the database lock, as one [`WriteBatch`].
+    fn reencrypt(&self, cipher: &Cipher) -> Result<usize, WessError> {
+        let db = self.db.lock().unwrap();
+        let cf = db.cf_handle(Family::Modules.name()).unwrap();
+        let mut batch = WriteBatch::default();
+
+        for item in db.iterator_cf(&cf, IteratorMode::Start) {
+            let (k, v) = item.map_err(|e| log_error!(e.to_string(), 500))?;
+            if !codec::is_current(&v, Some(cipher)) {
+                let key = String::from_utf8_lossy(&k);
+                let wasm = codec::decode_with(&key, &v, Some(cipher))?;
+                batch.put_cf(&cf, &k, codec::encode_with(&key, &wasm, Some(cipher)));
+            }
+        }
+
+        let count = batch.len();
+        db.write(batch)
+            .map(|_| count)
+            .map_err(|e| log_error!(e.to_string(), 500))
+    }
+}
+
+impl RecordStore for RocksDB {
+    fn put_record(&self, family: Family, key: &str, value: &[u8]) -> Result<(), WessError> {
+        self.put_in(family, key, value)
+    }
+
+    fn get_record(&self, family: Family, key: &str) -> Result<Option<Vec<u8>>, WessError> {
+        self.get_in(family, key)
+    }
+
+    fn del_record(&self, family: Family, key: &str) -> Result<(), WessError> {
+        self.del_in(family, key)
+    }
+
+    fn records(&self, family: Family) -> Result<Vec<(String, Vec<u8>)>, WessError> {
+        self.iter_in(family)
+    }
+
+    fn range_records(
+        &self,
+        family: Family,
+        from: &str,
+        to: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u8>)>, WessError> {
+        let db = self.db.lock().unwrap();
+        let cf = db.cf_handle(family.name()).unwrap();
+        let mode = IteratorMode::From(from.as_bytes(), Direction::Forward);
+
+        db.iterator_cf(&cf, mode)
+            .map(|item| {
+                item.map(|(k, v)| (String::from_utf8_lossy(&k).into_owned(), v.into_vec()))
+                    .map_err(|e| log_error!(e.to_string(), 500))
+            })
+            .take_while(|item| item.as_ref().map_or(true, |(k, _)| k.as_str() < to))
+            .take(limit)
+            .collect()
+    }
+
+    fn last_record(&self, family: Family) -> Result<Option<(String, Vec<u8>)>, WessError> {
+        let db = self.db.lock().unwrap();
+        let cf = db.cf_handle(family.name()).unwrap();
+
+        db.iterator_cf(&cf, IteratorMode::End)
+            .next()
+            .transpose()
+            .map(|item| item.map(|(k, v)| (String::from_utf8_lossy(&k).into_owned(), v.into_vec())))
+            .map_err(|e| log_error!(e.to_string(), 500))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::suite;
+    use std::{env, fs};
+
+    fn temp(name: &str) -> RocksDB {
+        let path = env::temp_dir().join(format!("wess-{}-{}.rocksdb", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let mut options = Options::default();
+        options.create_if_missing(true);
+
+        RocksDB {
+            db: open(&path.to_string_lossy(), options),
+        }
+    }
+
+    #[test]
+    fn test_add_and_get() {
+        suite::test_add_and_get(RocksDB::dev());
+    }
+
+    #[test]
+    fn test_upd_and_del() {
+        suite::test_upd_and_del(RocksDB::dev());
+    }
+
+    #[test]
+    fn test_scan() {
+        suite::test_scan(RocksDB::dev(), "rocks_scan");
+    }
+
+    #[test]
+    fn test_count() {
+        suite::test_count(temp("count"));
+    }
+
+    #[test]
+    fn test_records() {
+        suite::test_records(temp("records"));
+    }
+
+    #[test]
+    fn test_range_records() {
+        suite::test_range_records(temp("range_records"));
+    }
+
+    #[test]
+    fn test_put_in_and_get_in() {
+        let db = RocksDB::dev();
+        let key = "example_job";
+
+        db.put_in(Family::Jobs, key, b"pending").unwrap();
+
+        assert_eq!(
+            db.get_in(Family::Jobs, key).unwrap(),
+            Some(b"pending".to_vec())
+        );
+        assert_eq!(db.get_in(Family::Audit, key).unwrap(), None);
+
+        db.del_in(Family::Jobs, key).unwrap();
+
+        assert_eq!(db.get_in(Family::Jobs, key).unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrate_legacy_records() {
+        let db = RocksDB::dev();
+        let wasm = WasmModule::default();
+        let key = "legacy_key";
+
+        {
+            let raw = db.db.lock().unwrap();
+            raw.put(key, serde_json::to_vec(&wasm).unwrap()).unwrap();
+            schema::write_version(&raw, 0).unwrap();
+        }
+
+        assert_eq!(db.migrate().unwrap(), SCHEMA_VERSION);
+
+        let key = schema::module_key(schema::DEFAULT_TENANT, key);
+        assert_eq!(db.db.lock().unwrap().get(&key).unwrap(), None);
+        let record = db.get_in(Family::Modules, &key).unwrap().unwrap();
+        assert!(!codec::is_legacy(&record));
+        assert_eq!(db.get(&key).unwrap(), wasm);
+
+        let _ = db.del(&key).unwrap();
+    }
+}
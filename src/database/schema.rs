@@ -0,0 +1,257 @@
+//! # The `schema` module describes how data is laid out in RocksDB
+//!
+//! This module contains the following main components:
+//!
+//! - [`Family`]: The column families opened by [`super::RocksDB`], one per kind of data.
+//! - [`SCHEMA_VERSION`]: The version of the layout expected by this build.
+//! - [`MIGRATIONS`]: The steps that bring an older database up to [`SCHEMA_VERSION`].
+//! - [`MODULE_COUNT_KEY`]: The key of the counter of stored modules.
+//! - [`module_key`]: The key of a module, prefixed by its tenant.
+//!
+//! The column families are only backed by RocksDB when the `rocksdb` feature is
+//! enabled, other backends map each [`Family`] to their own storage.
+//!
+//! The schema version is stored as a `u32` (little endian) under [`SCHEMA_VERSION_KEY`]
+//! in the [`Family::Metadata`] column family. A database without that record is at
+//! version `0`, the layout used before column families existed, where every module
+//! lived in the default column family under its bare id.
+//!
+//! Modules are stored under `{tenant}/{id}`, so the modules of a tenant are
+//! contiguous and can be scanned on their own. Modules stored before tenants
+//! existed belong to the [`DEFAULT_TENANT`].
+
+#[cfg(feature = "rocksdb")]
+use super::codec;
+#[cfg(feature = "rocksdb")]
+use crate::errors::WessError;
+#[cfg(feature = "rocksdb")]
+use rocksdb::{IteratorMode, WriteBatch, DB as DataBase};
+#[cfg(feature = "rocksdb")]
+use std::convert::TryInto;
+
+/// The version of the database layout expected by this build.
+pub const SCHEMA_VERSION: u32 = 4;
+
+/// The key of the schema version record in [`Family::Metadata`].
+pub const SCHEMA_VERSION_KEY: &str = "schema:version";
+
+/// The key of the number of stored modules in [`Family::Metadata`], kept up to date on
+/// every write so counting modules doesn't require a scan.
+pub const MODULE_COUNT_KEY: &str = "modules:count";
+
+/// The tenant of callers that don't belong to one, and of modules stored before tenants.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// The separator between the tenant and the ID of a module in its key.
+pub const TENANT_SEPARATOR: char = '/';
+
+/// A migration step, run against a database at the previous version.
+#[cfg(feature = "rocksdb")]
+pub type Migration = fn(&DataBase) -> Result<(), WessError>;
+
+/// The migrations, indexed by the version they upgrade to.
+#[cfg(feature = "rocksdb")]
+pub const MIGRATIONS: [(u32, Migration); 4] = [
+    (1, encode_legacy_records),
+    (2, move_to_modules),
+    (3, count_modules),
+    (4, prefix_tenants),
+];
+
+/// # The column families used by Wess.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Family {
+    /// Module records encoded with [`codec`].
+    Modules,
+    /// Large binary payloads referenced by other records.
+    Blobs,
+    /// Bookkeeping records, such as the schema version.
+    Metadata,
+    /// Derived artifacts, such as compiled modules.
+    Artifacts,
+    /// Background jobs and their status.
+    Jobs,
+    /// Audit trail entries.
+    Audit,
+    /// API keys, stored hashed.
+    Keys,
+}
+
+impl Family {
+    /// Every column family, in the order they are opened.
+    pub const ALL: [Family; 7] = [
+        Family::Modules,
+        Family::Blobs,
+        Family::Metadata,
+        Family::Artifacts,
+        Family::Jobs,
+        Family::Audit,
+        Family::Keys,
+    ];
+
+    /// # Returns the name of the column family in RocksDB.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Family::Modules => "modules",
+            Family::Blobs => "blobs",
+            Family::Metadata => "metadata",
+            Family::Artifacts => "artifacts",
+            Family::Jobs => "jobs",
+            Family::Audit => "audit",
+            Family::Keys => "keys",
+        }
+    }
+}
+
+/// # Returns the key a module of a tenant is stored under.
+pub fn module_key(tenant: &str, id: &str) -> String {
+    format!("{}{}{}", tenant, TENANT_SEPARATOR, id)
+}
+
+/// # Returns the prefix shared by the keys of every module of a tenant.
+pub fn tenant_prefix(tenant: &str) -> String {
+    module_key(tenant, "")
+}
+
+/// # Splits the key of a module into its tenant and its ID.
+///
+/// Keys without a tenant, written before tenants existed, belong to the [`DEFAULT_TENANT`].
+pub fn split_key(key: &str) -> (&str, &str) {
+    key.split_once(TENANT_SEPARATOR)
+        .unwrap_or((DEFAULT_TENANT, key))
+}
+
+/// # Reads the schema version of a database.
+///
+/// ## Returns
+///
+/// * The stored version, or `0` if the database has no version record.
+#[cfg(feature = "rocksdb")]
+pub fn read_version(db: &DataBase) -> Result<u32, WessError> {
+    let cf = db.cf_handle(Family::Metadata.name()).unwrap();
+    let value = db
+        .get_cf(&cf, SCHEMA_VERSION_KEY)
+        .map_err(|e| log_error!(e.to_string(), 500))?;
+
+    match value {
+        Some(v) => v
+            .as_slice()
+            .try_into()
+            .map(u32::from_le_bytes)
+            .map_err(|_| log_error!("Invalid Schema Version".to_string(), 500)),
+        None => Ok(0),
+    }
+}
+
+/// # Reads the number of stored modules.
+///
+/// ## Returns
+///
+/// * The stored counter, or `0` if the database has no counter record.
+#[cfg(feature = "rocksdb")]
+pub fn read_count(db: &DataBase) -> Result<u64, WessError> {
+    let cf = db.cf_handle(Family::Metadata.name()).unwrap();
+    let value = db
+        .get_cf(&cf, MODULE_COUNT_KEY)
+        .map_err(|e| log_error!(e.to_string(), 500))?;
+
+    match value {
+        Some(v) => v
+            .as_slice()
+            .try_into()
+            .map(u64::from_le_bytes)
+            .map_err(|_| log_error!("Invalid Module Count".to_string(), 500)),
+        None => Ok(0),
+    }
+}
+
+/// # Writes the schema version of a database.
+#[cfg(feature = "rocksdb")]
+pub fn write_version(db: &DataBase, version: u32) -> Result<(), WessError> {
+    let cf = db.cf_handle(Family::Metadata.name()).unwrap();
+    db.put_cf(&cf, SCHEMA_VERSION_KEY, version.to_le_bytes())
+        .map_err(|e| log_error!(e.to_string(), 500))
+}
+
+/// # Version 1: rewrites legacy JSON records using the binary [`codec`] format.
+#[cfg(feature = "rocksdb")]
+fn encode_legacy_records(db: &DataBase) -> Result<(), WessError> {
+    let mut batch = WriteBatch::default();
+
+    for item in db.iterator(IteratorMode::Start) {
+        let (k, v) = item.map_err(|e| log_error!(e.to_string(), 500))?;
+        if codec::is_legacy(&v) {
+            let key = String::from_utf8_lossy(&k);
+            let wasm = codec::decode(&key, &v)?;
+            batch.put(&k, codec::encode(&key, &wasm));
+        }
+    }
+
+    db.write(batch).map_err(|e| log_error!(e.to_string(), 500))
+}
+
+/// # Version 2: moves module records from the default column family to [`Family::Modules`].
+#[cfg(feature = "rocksdb")]
+fn move_to_modules(db: &DataBase) -> Result<(), WessError> {
+    let cf = db.cf_handle(Family::Modules.name()).unwrap();
+    let mut batch = WriteBatch::default();
+
+    for item in db.iterator(IteratorMode::Start) {
+        let (k, v) = item.map_err(|e| log_error!(e.to_string(), 500))?;
+        batch.put_cf(&cf, &k, v);
+        batch.delete(k);
+    }
+
+    db.write(batch).map_err(|e| log_error!(e.to_string(), 500))
+}
+
+/// # Version 3: initializes the counter of stored modules.
+#[cfg(feature = "rocksdb")]
+fn count_modules(db: &DataBase) -> Result<(), WessError> {
+    let modules = db.cf_handle(Family::Modules.name()).unwrap();
+    let metadata = db.cf_handle(Family::Metadata.name()).unwrap();
+    let count = db.iterator_cf(&modules, IteratorMode::Start).count() as u64;
+
+    db.put_cf(&metadata, MODULE_COUNT_KEY, count.to_le_bytes())
+        .map_err(|e| log_error!(e.to_string(), 500))
+}
+
+/// # Version 4: moves modules stored under their bare id to the [`DEFAULT_TENANT`].
+///
+/// Encrypted records are encrypted again, their key is authenticated with them.
+#[cfg(feature = "rocksdb")]
+fn prefix_tenants(db: &DataBase) -> Result<(), WessError> {
+    let cf = db.cf_handle(Family::Modules.name()).unwrap();
+    let mut batch = WriteBatch::default();
+
+    for item in db.iterator_cf(&cf, IteratorMode::Start) {
+        let (k, v) = item.map_err(|e| log_error!(e.to_string(), 500))?;
+        if !k.contains(&(TENANT_SEPARATOR as u8)) {
+            let id = String::from_utf8_lossy(&k);
+            let key = module_key(DEFAULT_TENANT, &id);
+            let record = match codec::key_id(&v) {
+                Some(_) => codec::encode(&key, &codec::decode(&id, &v)?),
+                None => v.into_vec(),
+            };
+            batch.put_cf(&cf, key, record);
+            batch.delete_cf(&cf, k);
+        }
+    }
+
+    db.write(batch).map_err(|e| log_error!(e.to_string(), 500))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_keys() {
+        let key = module_key("search", "sum");
+
+        assert_eq!(key, "search/sum");
+        assert!(key.starts_with(&tenant_prefix("search")));
+        assert_eq!(split_key(&key), ("search", "sum"));
+        assert_eq!(split_key("sum"), (DEFAULT_TENANT, "sum"));
+    }
+}
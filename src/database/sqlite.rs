@@ -0,0 +1,680 @@
+//! # The `sqlite` module provides the SQLite implementation of [`ModuleStore`].
+//!
+//! This module contains the following main components:
+//!
+//! - [`SqliteDB`]: A struct that stores modules in a single SQLite file.
+//!
+//! The database is opened at `{path}/{stage}.sqlite3`, both read from the `[database]`
+//! section of `wess.toml` (`path` defaults to `./rocksdb`), in WAL mode so readers don't block the writer. It holds
+//! the following tables:
+//!
+//! - `modules`: The current record of each module, encoded with [`codec`].
+//! - `revisions`: Every record ever written for a module, one row per revision.
+//! - `metadata`: Bookkeeping key-value pairs, such as the schema version and the
+//!   number of stored modules.
+//! - `records`: The raw records of every other [`Family`], such as API keys.
+//!
+//! Databases written before tenants existed are migrated on open, their modules
+//! move to the [`DEFAULT_TENANT`].
+
+use super::{
+    codec,
+    crypto::Cipher,
+    models::WasmModule,
+    schema::{self, Family, DEFAULT_TENANT, MODULE_COUNT_KEY, SCHEMA_VERSION_KEY},
+    ModuleStore, RecordStore,
+};
+use crate::{
+    config::CONFIG,
+    errors::{ErrorCode, WessError},
+    metrics::constants::{DATABASE_OPERATIONS_TOTAL, DATABASE_OPERATION_DURATION},
+};
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    convert::TryInto,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The version of the SQLite tables expected by this build.
+const SQLITE_SCHEMA_VERSION: u32 = 3;
+
+/// The directory of the database when `database.path` isn't set.
+const DEFAULT_DIR: &str = "./rocksdb";
+
+const CREATE_TABLES: &str = "
+    CREATE TABLE IF NOT EXISTS modules (
+        id TEXT PRIMARY KEY,
+        revision INTEGER NOT NULL,
+        record BLOB NOT NULL,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS revisions (
+        id TEXT NOT NULL,
+        revision INTEGER NOT NULL,
+        record BLOB NOT NULL,
+        created_at INTEGER NOT NULL,
+        PRIMARY KEY (id, revision)
+    );
+    CREATE TABLE IF NOT EXISTS metadata (
+        key TEXT PRIMARY KEY,
+        value BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS records (
+        family TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value BLOB NOT NULL,
+        PRIMARY KEY (family, key)
+    );
+";
+
+/// The `SqliteDB` store keeps modules in a SQLite database.
+#[derive(Clone, Debug)]
+pub struct SqliteDB {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteDB {
+    /// # Opens the SQLite database described by the `[database]` section of `wess.toml`.
+    ///
+    /// ## Returns
+    ///
+    /// * An instance of `SqliteDB`.
+    pub fn new() -> Self {
+        let config = &CONFIG.database;
+        let dir = config.path.as_deref().unwrap_or(DEFAULT_DIR);
+        if config.create_if_missing {
+            fs::create_dir_all(dir).expect("Cant not create 'database.path'");
+        }
+        let path = format!("{}/{}.sqlite3", dir, config.stage);
+
+        match Self::open(&path) {
+            Ok(db) => db,
+            Err(err) => panic!("DB dont open: {}", err),
+        }
+    }
+
+    /// # Opens a SQLite database at the given path, creating its tables if needed.
+    ///
+    /// ## Arguments
+    ///
+    /// * `path` - The path of the database file.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with an instance of `SqliteDB`,
+    ///   or a `WessError` object if the database can't be opened.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, WessError> {
+        let conn = Connection::open(path).map_err(|e| log_error!(e.to_string(), 500))?;
+
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .and_then(|_| conn.execute_batch(CREATE_TABLES))
+            .and_then(|_| {
+                conn.execute(
+                    "INSERT OR IGNORE INTO metadata (key, value) VALUES (?1, ?2)",
+                    params![SCHEMA_VERSION_KEY, SQLITE_SCHEMA_VERSION.to_le_bytes()],
+                )
+            })
+            .and_then(|_| {
+                conn.execute(
+                    "INSERT OR IGNORE INTO metadata (key, value)
+                     SELECT ?1, COUNT(*) FROM modules",
+                    params![MODULE_COUNT_KEY],
+                )
+            })
+            .map_err(|e| log_error!(e.to_string(), 500))?;
+        migrate(&conn).map_err(|e| log_error!(e.to_string(), 500))?;
+
+        Ok(SqliteDB {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// # Gets every stored revision of a module, oldest first.
+    ///
+    /// ## Arguments
+    ///
+    /// * `key` - A string slice that represents the key of the module.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Vec` of revision numbers and the module as it was at that revision.
+    pub fn revisions(&self, key: &str) -> Vec<(u64, WasmModule)> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT revision, record FROM revisions WHERE id = ?1 ORDER BY revision")
+            .unwrap();
+
+        stmt.query_map(params![key], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })
+        .map(|rows| {
+            rows.filter_map(|row| row.ok())
+                .filter_map(|(rev, record)| {
+                    codec::decode(key, &record).ok().map(|w| (rev as u64, w))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    /// # Writes a new revision of a module.
+    ///
+    /// Inserts or replaces the module, records the revision in the `revisions`
+    /// table and bumps the module counter for new modules, all within the same transaction.
+    fn write(&self, key: &str, wasm: &WasmModule, revision: i64) -> Result<(), WessError> {
+        DATABASE_OPERATIONS_TOTAL
+            .with_label_values(&["write"])
+            .inc();
+        let start = Instant::now();
+        let record = codec::encode(key, wasm);
+        let now = now();
+
+        let mut conn = self.conn.lock().unwrap();
+        let r = conn
+            .transaction()
+            .and_then(|tx| {
+                if !exists(&tx, key)? {
+                    tx.execute(
+                        "UPDATE metadata SET value = value + 1 WHERE key = ?1",
+                        params![MODULE_COUNT_KEY],
+                    )?;
+                }
+                tx.execute(
+                    "INSERT INTO modules (id, revision, record, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?4)
+                     ON CONFLICT (id) DO UPDATE SET
+                        revision = excluded.revision,
+                        record = excluded.record,
+                        updated_at = excluded.updated_at",
+                    params![key, revision, record, now],
+                )?;
+                tx.execute(
+                    "INSERT INTO revisions (id, revision, record, created_at)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![key, revision, record, now],
+                )?;
+                tx.commit()
+            })
+            .map_err(|e| log_error!(e.to_string(), 500));
+
+        let duration = start.elapsed();
+        DATABASE_OPERATION_DURATION
+            .with_label_values(&["write"])
+            .observe(duration.as_secs_f64());
+
+        r
+    }
+
+    /// # Gets the revision number the next write of a module will get.
+    ///
+    /// Revisions keep counting after a module is deleted and created again,
+    /// so rows in the `revisions` table are never overwritten.
+    fn next_revision(&self, key: &str) -> i64 {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT COALESCE(MAX(revision), 0) + 1 FROM revisions WHERE id = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .map_err(|e| log_error!(e.to_string(), 500))
+            .unwrap_or(1)
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        exists(&self.conn.lock().unwrap(), key)
+            .map_err(|e| log_error!(e.to_string(), 500))
+            .unwrap_or_default()
+    }
+}
+
+impl ModuleStore for SqliteDB {
+    fn add(&self, key: &str, wasm: WasmModule) -> Result<String, WessError> {
+        let (tenant, id) = schema::split_key(key);
+        info!(target: "wess::tx", "CREATE {id} tenant={tenant}");
+        self.write(key, &wasm, self.next_revision(key))
+            .map(|_| key.to_owned())
+    }
+
+    fn get(&self, key: &str) -> Option<WasmModule> {
+        DATABASE_OPERATIONS_TOTAL.with_label_values(&["read"]).inc();
+        let start = Instant::now();
+
+        let r = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT record FROM modules WHERE id = ?1",
+                params![key],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(|e| log_error!(e.to_string(), 500))
+            .unwrap_or_default();
+
+        let duration = start.elapsed();
+        DATABASE_OPERATION_DURATION
+            .with_label_values(&["read"])
+            .observe(duration.as_secs_f64());
+
+        r.and_then(|v| codec::decode(key, &v).ok())
+    }
+
+    fn all(&self) -> Vec<Option<WasmModule>> {
+        DATABASE_OPERATIONS_TOTAL.with_label_values(&["read"]).inc();
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, record FROM modules").unwrap();
+
+        stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })
+        .map(|rows| {
+            rows.map(|row| row.ok().and_then(|(k, v)| codec::decode(&k, &v).ok()))
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    fn upd(&self, key: &str, wasm: WasmModule) -> Result<String, WessError> {
+        if !self.exists(key) {
+            return Err(log_error!("Not found".to_string(), 404).code(ErrorCode::ModuleNotFound));
+        }
+
+        let (tenant, id) = schema::split_key(key);
+        info!(target: "wess::tx", "UPDATE {id} tenant={tenant}");
+        self.write(key, &wasm, self.next_revision(key))
+            .map(|_| key.to_owned())
+    }
+
+    fn del(&self, key: &str) -> Result<String, WessError> {
+        let (tenant, id) = schema::split_key(key);
+        info!(target: "wess::tx", "DELETE {id} tenant={tenant}");
+        DATABASE_OPERATIONS_TOTAL
+            .with_label_values(&["write"])
+            .inc();
+
+        let mut conn = self.conn.lock().unwrap();
+        conn.transaction()
+            .and_then(|tx| {
+                if tx.execute("DELETE FROM modules WHERE id = ?1", params![key])? > 0 {
+                    tx.execute(
+                        "UPDATE metadata SET value = value - 1 WHERE key = ?1",
+                        params![MODULE_COUNT_KEY],
+                    )?;
+                }
+                tx.commit()
+            })
+            .map(|_| key.to_owned())
+            .map_err(|e| log_error!(e.to_string(), 500))
+    }
+
+    fn scan(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+        filter: &dyn Fn(&WasmModule) -> bool,
+    ) -> Vec<(String, WasmModule)> {
+        DATABASE_OPERATIONS_TOTAL.with_label_values(&["read"]).inc();
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT id, record FROM modules WHERE id > ?1 AND id >= ?2 ORDER BY id")
+            .unwrap();
+
+        stmt.query_map(params![after.unwrap_or_default(), prefix], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })
+        .map(|rows| {
+            rows.filter_map(|row| row.ok())
+                .take_while(|(k, _)| k.starts_with(prefix))
+                .filter_map(|(k, v)| codec::decode(&k, &v).ok().map(|wasm| (k, wasm)))
+                .filter(|(_, wasm)| filter(wasm))
+                .take(limit)
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    fn count(&self) -> usize {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT value FROM metadata WHERE key = ?1",
+                params![MODULE_COUNT_KEY],
+                |row| row.get::<_, i64>(0),
+            )
+            .map_err(|e| log_error!(e.to_string(), 500))
+            .unwrap_or_default() as usize
+    }
+
+    fn size(&self) -> i64 {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or_default()
+    }
+
+    /// # Rewrites the records of the `modules` and `revisions` tables not encrypted
+    /// with the current key of `cipher`, within one transaction.
+    fn reencrypt(&self, cipher: &Cipher) -> Result<usize, WessError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn
+            .transaction()
+            .map_err(|e| log_error!(e.to_string(), 500))?;
+
+        let mut count = 0;
+        for table in ["modules", "revisions"] {
+            count += reencrypt_table(&tx, table, cipher)?;
+        }
+
+        tx.commit()
+            .map(|_| count)
+            .map_err(|e| log_error!(e.to_string(), 500))
+    }
+}
+
+/// # Rewrites the records of a table not encrypted with the current key of `cipher`.
+fn reencrypt_table(conn: &Connection, table: &str, cipher: &Cipher) -> Result<usize, WessError> {
+    let rows = conn
+        .prepare(&format!("SELECT id, revision, record FROM {}", table))
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Vec<u8>>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .map_err(|e| log_error!(e.to_string(), 500))?;
+
+    let mut count = 0;
+    for (id, revision, record) in rows {
+        if codec::is_current(&record, Some(cipher)) {
+            continue;
+        }
+        let wasm = codec::decode_with(&id, &record, Some(cipher))?;
+        conn.execute(
+            &format!(
+                "UPDATE {} SET record = ?3 WHERE id = ?1 AND revision = ?2",
+                table
+            ),
+            params![id, revision, codec::encode_with(&id, &wasm, Some(cipher))],
+        )
+        .map_err(|e| log_error!(e.to_string(), 500))?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// # Brings the tables of a database written by an older build up to [`SQLITE_SCHEMA_VERSION`].
+///
+/// Version 3 moves the modules stored under their bare id to the [`DEFAULT_TENANT`].
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    let version = conn.query_row(
+        "SELECT value FROM metadata WHERE key = ?1",
+        params![SCHEMA_VERSION_KEY],
+        |row| row.get::<_, Vec<u8>>(0),
+    )?;
+    let version = version
+        .as_slice()
+        .try_into()
+        .map(u32::from_le_bytes)
+        .unwrap_or_default();
+    if version >= SQLITE_SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    info!(target: "wess::tx", "MIGRATE schema to version {SQLITE_SCHEMA_VERSION}");
+    let prefix = schema::tenant_prefix(DEFAULT_TENANT);
+    conn.execute(
+        "UPDATE modules SET id = ?1 || id WHERE instr(id, '/') = 0",
+        params![prefix],
+    )?;
+    conn.execute(
+        "UPDATE revisions SET id = ?1 || id WHERE instr(id, '/') = 0",
+        params![prefix],
+    )?;
+    conn.execute(
+        "UPDATE metadata SET value = ?2 WHERE key = ?1",
+        params![SCHEMA_VERSION_KEY, SQLITE_SCHEMA_VERSION.to_le_bytes()],
+    )
+    .map(|_| ())
+}
+
+fn exists(conn: &Connection, key: &str) -> rusqlite::Result<bool> {
+    conn.query_row("SELECT 1 FROM modules WHERE id = ?1", params![key], |_| {
+        Ok(())
+    })
+    .optional()
+    .map(|r| r.is_some())
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+impl RecordStore for SqliteDB {
+    fn put_record(&self, family: Family, key: &str, value: &[u8]) -> Result<(), WessError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO records (family, key, value) VALUES (?1, ?2, ?3)",
+                params![family.name(), key, value],
+            )
+            .map(|_| ())
+            .map_err(|e| log_error!(e.to_string(), 500))
+    }
+
+    fn get_record(&self, family: Family, key: &str) -> Result<Option<Vec<u8>>, WessError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT value FROM records WHERE family = ?1 AND key = ?2",
+                params![family.name(), key],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(|e| log_error!(e.to_string(), 500))
+    }
+
+    fn del_record(&self, family: Family, key: &str) -> Result<(), WessError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "DELETE FROM records WHERE family = ?1 AND key = ?2",
+                params![family.name(), key],
+            )
+            .map(|_| ())
+            .map_err(|e| log_error!(e.to_string(), 500))
+    }
+
+    fn records(&self, family: Family) -> Result<Vec<(String, Vec<u8>)>, WessError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT key, value FROM records WHERE family = ?1 ORDER BY key")
+            .map_err(|e| log_error!(e.to_string(), 500))?;
+
+        stmt.query_map(params![family.name()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })
+        .and_then(|rows| rows.collect())
+        .map_err(|e| log_error!(e.to_string(), 500))
+    }
+
+    fn range_records(
+        &self,
+        family: Family,
+        from: &str,
+        to: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u8>)>, WessError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT key, value FROM records WHERE family = ?1 AND key >= ?2 AND key < ?3 \
+                 ORDER BY key LIMIT ?4",
+            )
+            .map_err(|e| log_error!(e.to_string(), 500))?;
+
+        stmt.query_map(params![family.name(), from, to, limit as i64], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })
+        .and_then(|rows| rows.collect())
+        .map_err(|e| log_error!(e.to_string(), 500))
+    }
+
+    fn last_record(&self, family: Family) -> Result<Option<(String, Vec<u8>)>, WessError> {
+        self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT key, value FROM records WHERE family = ?1 ORDER BY key DESC LIMIT 1",
+                params![family.name()],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)),
+            )
+            .optional()
+            .map_err(|e| log_error!(e.to_string(), 500))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::suite;
+    use std::env;
+
+    fn dev(name: &str) -> SqliteDB {
+        let path = env::temp_dir().join(format!("wess-{}-{}.sqlite3", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        SqliteDB::open(path).unwrap()
+    }
+
+    #[test]
+    fn test_add_and_get() {
+        suite::test_add_and_get(dev("add_and_get"));
+    }
+
+    #[test]
+    fn test_upd_and_del() {
+        suite::test_upd_and_del(dev("upd_and_del"));
+    }
+
+    #[test]
+    fn test_scan() {
+        suite::test_scan(dev("scan"), "sqlite_scan");
+    }
+
+    #[test]
+    fn test_count() {
+        suite::test_count(dev("count"));
+    }
+
+    #[test]
+    fn test_records() {
+        suite::test_records(dev("records"));
+    }
+
+    #[test]
+    fn test_range_records() {
+        suite::test_range_records(dev("range_records"));
+    }
+
+    #[test]
+    fn test_migrate_to_tenants() {
+        let path = env::temp_dir().join(format!("wess-migrate-{}.sqlite3", std::process::id()));
+        let _ = fs::remove_file(&path);
+        {
+            let db = SqliteDB::open(&path).unwrap();
+            let _ = db.add("legacy_key", WasmModule::default()).unwrap();
+            db.conn
+                .lock()
+                .unwrap()
+                .execute(
+                    "UPDATE metadata SET value = ?2 WHERE key = ?1",
+                    params![SCHEMA_VERSION_KEY, 2u32.to_le_bytes()],
+                )
+                .unwrap();
+        }
+
+        let db = SqliteDB::open(&path).unwrap();
+        let key = schema::module_key(DEFAULT_TENANT, "legacy_key");
+
+        assert_eq!(db.get("legacy_key"), None);
+        assert_eq!(db.get(&key), Some(WasmModule::default()));
+        assert_eq!(db.revisions(&key).len(), 1);
+        assert_eq!(db.count(), 1);
+    }
+
+    #[test]
+    fn test_revisions() {
+        let db = dev("revisions");
+        let key = "example_key";
+        let updated = WasmModule {
+            wasm: vec![0, 97, 115, 109],
+            ..Default::default()
+        };
+
+        let _ = db.add(key, WasmModule::default()).unwrap();
+        let _ = db.upd(key, updated.clone()).unwrap();
+
+        let revisions = db.revisions(key);
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0], (1, WasmModule::default()));
+        assert_eq!(revisions[1], (2, updated));
+    }
+
+    #[test]
+    fn test_reencrypt() {
+        let db = dev("reencrypt");
+        let key = |id: &str| (id.to_string(), vec![id.len() as u8; 32]);
+        let old = Cipher::new("old", vec![key("old")]).unwrap();
+        let new = Cipher::new("newer", vec![key("old"), key("newer")]).unwrap();
+        let record = |table: &str| {
+            db.conn
+                .lock()
+                .unwrap()
+                .query_row(
+                    &format!("SELECT record FROM {} WHERE revision = 2", table),
+                    [],
+                    |row| row.get::<_, Vec<u8>>(0),
+                )
+                .unwrap()
+        };
+
+        let _ = db.add("example_key", WasmModule::default()).unwrap();
+        let _ = db.upd("example_key", WasmModule::default()).unwrap();
+
+        assert_eq!(db.reencrypt(&old).unwrap(), 3);
+        assert_eq!(codec::key_id(&record("modules")), Some("old"));
+        assert_eq!(db.reencrypt(&old).unwrap(), 0);
+
+        assert_eq!(db.reencrypt(&new).unwrap(), 3);
+        assert_eq!(codec::key_id(&record("revisions")), Some("newer"));
+        assert_eq!(
+            codec::decode_with("example_key", &record("modules"), Some(&new)).unwrap(),
+            WasmModule::default()
+        );
+    }
+}
@@ -1,20 +1,229 @@
-use serde::Serialize;
+//! # The `errors` module provides the error type shared by every part of Wess.
+//!
+//! - [`WessError`]: An error with a message, an HTTP status and an [`ErrorCode`].
+//! - [`ErrorCode`]: A stable, machine-readable name for a kind of error.
+//! - [`ErrorDetails`]: Optional details about an error, such as the offending argument.
+//! - [`WasmFrame`]: A frame of the WebAssembly backtrace of a guest trap.
+//!
+//! Errors reach clients as RFC 7807 `application/problem+json` bodies, see
+//! [`crate::server::response::respond_with_error`].
+
+use serde::{Deserialize, Serialize};
 use std::{convert::TryFrom, fmt::Display};
 use tide::StatusCode;
 
+/// # The kinds of errors, serialized as `SCREAMING_SNAKE_CASE` codes.
+///
+/// The codes are part of the API: clients match on them, so they are never renamed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// The request is malformed.
+    BadRequest,
+    /// The body of the request can't be read.
+    InvalidBody,
+    /// A module ID is malformed or reserved.
+    InvalidId,
+    /// The metadata of an upload is missing or malformed.
+    InvalidMetadata,
+    /// The bytecode of an upload isn't valid WebAssembly.
+    InvalidWasm,
+    /// The WebAssembly text or the bytecode of a module doesn't compile.
+    CompileFailed,
+    /// The signature of an upload is malformed, untrusted or doesn't match the bytecode.
+    InvalidSignature,
+    /// The module isn't signed by a trusted key and `signing.require_signed` is set.
+    UnsignedModule,
+    /// The write would take a tenant over its quota of modules or bytes, or the tenant
+    /// spent its CPU time of the day.
+    QuotaExceeded,
+    /// The caller or the module ran out of its rate limit.
+    RateLimited,
+    /// The number of arguments doesn't match the signature of the function.
+    ArgCountMismatch,
+    /// An argument doesn't match the type in the signature of the function.
+    ArgTypeMismatch,
+    /// The request doesn't carry a valid API key.
+    Unauthorized,
+    /// The caller isn't allowed to make the request.
+    Forbidden,
+    /// No route or resource matches the request.
+    NotFound,
+    /// No module is stored under the requested ID.
+    ModuleNotFound,
+    /// No write job is tracked under the requested ID.
+    JobNotFound,
+    /// No API key is stored under the requested ID.
+    KeyNotFound,
+    /// The module doesn't export the function named in its metadata.
+    FunctionNotFound,
+    /// The `If-Match` header doesn't match the current revision of the module.
+    PreconditionFailed,
+    /// The `Idempotency-Key` was already used for a different module.
+    IdempotencyConflict,
+    /// The request is well-formed but can't be processed.
+    Unprocessable,
+    /// The module can't be instantiated.
+    InstantiationFailed,
+    /// The function trapped while running.
+    Trap,
+    /// A worker is too busy to take the job before its deadline.
+    Overloaded,
+    /// Something went wrong inside Wess.
+    Internal,
+}
+
+impl ErrorCode {
+    /// # Gets the default code of an HTTP status.
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::Unauthorized => ErrorCode::Unauthorized,
+            StatusCode::Forbidden => ErrorCode::Forbidden,
+            StatusCode::NotFound => ErrorCode::NotFound,
+            StatusCode::PreconditionFailed => ErrorCode::PreconditionFailed,
+            StatusCode::TooManyRequests => ErrorCode::RateLimited,
+            StatusCode::UnprocessableEntity => ErrorCode::Unprocessable,
+            StatusCode::ServiceUnavailable => ErrorCode::Overloaded,
+            s if s.is_client_error() => ErrorCode::BadRequest,
+            _ => ErrorCode::Internal,
+        }
+    }
+
+    /// # Gets the code as it is sent to clients, such as `MODULE_NOT_FOUND`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "BAD_REQUEST",
+            ErrorCode::InvalidBody => "INVALID_BODY",
+            ErrorCode::InvalidId => "INVALID_ID",
+            ErrorCode::InvalidMetadata => "INVALID_METADATA",
+            ErrorCode::InvalidWasm => "INVALID_WASM",
+            ErrorCode::CompileFailed => "COMPILE_FAILED",
+            ErrorCode::InvalidSignature => "INVALID_SIGNATURE",
+            ErrorCode::UnsignedModule => "UNSIGNED_MODULE",
+            ErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::ArgCountMismatch => "ARG_COUNT_MISMATCH",
+            ErrorCode::ArgTypeMismatch => "ARG_TYPE_MISMATCH",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::ModuleNotFound => "MODULE_NOT_FOUND",
+            ErrorCode::JobNotFound => "JOB_NOT_FOUND",
+            ErrorCode::KeyNotFound => "KEY_NOT_FOUND",
+            ErrorCode::FunctionNotFound => "FUNCTION_NOT_FOUND",
+            ErrorCode::PreconditionFailed => "PRECONDITION_FAILED",
+            ErrorCode::IdempotencyConflict => "IDEMPOTENCY_CONFLICT",
+            ErrorCode::Unprocessable => "UNPROCESSABLE",
+            ErrorCode::InstantiationFailed => "INSTANTIATION_FAILED",
+            ErrorCode::Trap => "TRAP",
+            ErrorCode::Overloaded => "OVERLOADED",
+            ErrorCode::Internal => "INTERNAL",
+        }
+    }
+
+    /// # Gets a short, human-readable summary of the kind of error.
+    pub fn title(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "Bad request",
+            ErrorCode::InvalidBody => "Invalid body",
+            ErrorCode::InvalidId => "Invalid module ID",
+            ErrorCode::InvalidMetadata => "Invalid metadata",
+            ErrorCode::InvalidWasm => "Invalid WebAssembly",
+            ErrorCode::CompileFailed => "Compilation failed",
+            ErrorCode::InvalidSignature => "Invalid signature",
+            ErrorCode::UnsignedModule => "Unsigned module",
+            ErrorCode::QuotaExceeded => "Quota exceeded",
+            ErrorCode::RateLimited => "Too many requests",
+            ErrorCode::ArgCountMismatch => "Wrong number of arguments",
+            ErrorCode::ArgTypeMismatch => "Wrong argument type",
+            ErrorCode::Unauthorized => "Unauthorized",
+            ErrorCode::Forbidden => "Forbidden",
+            ErrorCode::NotFound => "Not found",
+            ErrorCode::ModuleNotFound => "Module not found",
+            ErrorCode::JobNotFound => "Job not found",
+            ErrorCode::KeyNotFound => "API key not found",
+            ErrorCode::FunctionNotFound => "Function not found",
+            ErrorCode::PreconditionFailed => "Precondition failed",
+            ErrorCode::IdempotencyConflict => "Idempotency key conflict",
+            ErrorCode::Unprocessable => "Unprocessable request",
+            ErrorCode::InstantiationFailed => "Instantiation failed",
+            ErrorCode::Trap => "Function trapped",
+            ErrorCode::Overloaded => "Service overloaded",
+            ErrorCode::Internal => "Internal error",
+        }
+    }
+}
+
+/// # Optional details about an error, sent as extra members of the problem body.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorDetails {
+    /// The position of the offending argument.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arg_index: Option<usize>,
+    /// What was expected, such as a type or a number of arguments.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    /// What was received instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub got: Option<String>,
+    /// The trap code of a guest trap, such as `unreachable` or `division_by_zero`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trap: Option<String>,
+    /// The WebAssembly frames of a guest trap, innermost first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backtrace: Option<Vec<WasmFrame>>,
+    /// Seconds to wait before retrying, also sent as the `Retry-After` header.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+/// # A WebAssembly frame of a trap backtrace.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WasmFrame {
+    /// The name of the function, from the name section of the module when it has one.
+    pub function: Option<String>,
+    /// The index of the function in the module.
+    pub func_index: u32,
+    /// The offset of the instruction from the start of the module.
+    pub offset: usize,
+}
+
 #[derive(Serialize, Debug)]
 pub struct WessError {
     pub msg: String,
     pub status: StatusCode,
+    pub code: ErrorCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Box<ErrorDetails>>,
 }
 
 impl WessError {
+    /// # Creates an error with the default [`ErrorCode`] of its status.
     pub fn new(msg: String, status: u16) -> WessError {
         match StatusCode::try_from(status) {
-            Ok(status) => WessError { msg, status },
+            Ok(status) => WessError {
+                msg,
+                status,
+                code: ErrorCode::from_status(status),
+                details: None,
+            },
             Err(_) => WessError::new(msg, 500),
         }
     }
+
+    /// # Sets the [`ErrorCode`] of the error.
+    pub fn code(mut self, code: ErrorCode) -> Self {
+        self.code = code;
+        self
+    }
+
+    /// # Sets the [`ErrorDetails`] of the error.
+    pub fn details(mut self, details: ErrorDetails) -> Self {
+        self.details = Some(Box::new(details));
+        self
+    }
 }
 
 impl Display for WessError {
@@ -22,3 +231,35 @@ impl Display for WessError {
         write!(f, "Error: {}", self.msg)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_codes() {
+        assert_eq!(
+            WessError::new("".to_string(), 404).code,
+            ErrorCode::NotFound
+        );
+        assert_eq!(
+            WessError::new("".to_string(), 400).code,
+            ErrorCode::BadRequest
+        );
+        assert_eq!(
+            WessError::new("".to_string(), 999).code,
+            ErrorCode::Internal
+        );
+    }
+
+    #[test]
+    fn test_codes_serialize_as_str() {
+        for code in [
+            ErrorCode::ModuleNotFound,
+            ErrorCode::ArgTypeMismatch,
+            ErrorCode::Trap,
+        ] {
+            assert_eq!(serde_json::to_value(code).unwrap(), code.as_str());
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! # The `limits` module keeps callers from flooding the Runner
+//!
+//! This module contains the following main components:
+//!
+//! - [`RateLimiter`]: Token buckets keyed by API key or by module, from `[limits]` in `wess.toml`.
+//! - [`CpuTime`]: The time each tenant spent running functions today, measured by the
+//!   Runner and checked against the `cpu_seconds_per_day` of its [`Quota`].
+//! - [`Limits`]: Checks every limit before a run is sent to the Runner.
+//!
+//! A run over a limit answers 429 with a `Retry-After` header, before it takes a place
+//! in the channel of the Runner.
+
+use crate::{
+    auth::Identity,
+    config::{LimitsConfig, Quota, RateConfig, CONFIG},
+    database::schema::split_key,
+    errors::{ErrorCode, ErrorDetails, WessError},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The number of buckets a [`RateLimiter`] keeps before dropping the full ones.
+const MAX_BUCKETS: usize = 10_000;
+
+/// The number of seconds in a day.
+const DAY: u64 = 86_400;
+
+/// # A bucket of tokens, refilled at a constant rate.
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// # Token buckets, one per key.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// # Creates a new instance of [`RateLimiter`] whose buckets start full.
+    pub fn new(config: &RateConfig) -> Self {
+        Self {
+            rate: config.rate,
+            burst: config.burst as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// # Takes a token from the bucket of a key.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object, or the time until the bucket holds a token again if it is empty.
+    pub fn acquire(&self, key: &str) -> Result<(), Duration> {
+        self.acquire_at(key, Instant::now())
+    }
+
+    fn acquire_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(TokenBucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let tokens = self.refill(bucket, now);
+        bucket.tokens = tokens;
+        bucket.updated = now;
+
+        if tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - tokens) / self.rate))
+        }
+    }
+
+    /// # Gets the tokens a bucket holds at `now`.
+    fn refill(&self, bucket: &TokenBucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+/// # The time each tenant spent running functions today, shared with the Runner.
+#[derive(Clone, Debug, Default)]
+pub struct CpuTime {
+    used: Arc<Mutex<HashMap<String, (u64, Duration)>>>,
+}
+
+impl CpuTime {
+    /// # Adds the time a function of a tenant ran for.
+    pub fn record(&self, tenant: &str, elapsed: Duration) {
+        self.record_on(today(), tenant, elapsed)
+    }
+
+    /// # Gets the time a tenant spent running functions today.
+    pub fn used(&self, tenant: &str) -> Duration {
+        self.used_on(today(), tenant)
+    }
+
+    /// # Checks that a tenant has CPU time left today.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object, or a `WessError` object with status 429 and the seconds
+    ///   until midnight UTC, when the time of the tenant is reset.
+    pub fn check(&self, tenant: &str, quota: &Quota) -> Result<(), WessError> {
+        match quota.cpu_seconds_per_day {
+            Some(max) if self.used(tenant) >= Duration::from_secs(max) => {
+                let now = now();
+                Err(too_many(
+                    format!("Quota Exceeded: {} ran for {}s today", tenant, max),
+                    DAY - now % DAY,
+                )
+                .code(ErrorCode::QuotaExceeded))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn record_on(&self, day: u64, tenant: &str, elapsed: Duration) {
+        let mut used = self.used.lock().unwrap();
+        let entry = used.entry(tenant.to_string()).or_default();
+        if entry.0 != day {
+            *entry = (day, Duration::ZERO);
+        }
+        entry.1 += elapsed;
+    }
+
+    fn used_on(&self, day: u64, tenant: &str) -> Duration {
+        match self.used.lock().unwrap().get(tenant) {
+            Some((used_on, used)) if *used_on == day => *used,
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// # Every limit on runs.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    per_key: Option<Arc<RateLimiter>>,
+    per_module: Option<Arc<RateLimiter>>,
+    /// The CPU time of every tenant, recorded by the Runner.
+    pub cpu: CpuTime,
+}
+
+impl Limits {
+    /// # Creates a new instance of [`Limits`].
+    ///
+    /// ## Arguments
+    ///
+    /// * `config` - The `[limits]` section of `wess.toml`.
+    /// * `cpu` - The CPU time of every tenant, also given to the Runner.
+    pub fn new(config: &LimitsConfig, cpu: CpuTime) -> Self {
+        Self {
+            per_key: config
+                .per_key
+                .as_ref()
+                .map(|c| Arc::new(RateLimiter::new(c))),
+            per_module: config
+                .per_module
+                .as_ref()
+                .map(|c| Arc::new(RateLimiter::new(c))),
+            cpu,
+        }
+    }
+
+    /// # Checks that a caller may run a module now.
+    ///
+    /// ## Arguments
+    ///
+    /// * `identity` - The caller, limited by the ID of its API key or token.
+    /// * `key` - The key of the module, prefixed by its tenant.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object, or a `WessError` object with status 429 and the seconds
+    ///   to wait if the caller, the module or its tenant is over a limit.
+    pub fn check_run(&self, identity: &Identity, key: &str) -> Result<(), WessError> {
+        let (tenant, id) = split_key(key);
+        self.cpu.check(tenant, CONFIG.tenants.quota(tenant))?;
+
+        if let Some(Err(wait)) = self
+            .per_key
+            .as_ref()
+            .map(|limiter| limiter.acquire(&identity.id))
+        {
+            return Err(rate_limited(
+                format!("{} runs too often", identity.id),
+                wait,
+            ));
+        }
+        if let Some(Err(wait)) = self.per_module.as_ref().map(|limiter| limiter.acquire(key)) {
+            return Err(rate_limited(format!("{} runs too often", id), wait));
+        }
+
+        Ok(())
+    }
+}
+
+fn rate_limited(msg: String, wait: Duration) -> WessError {
+    too_many(
+        format!("Rate Limited: {}", msg),
+        wait.as_secs_f64().ceil().max(1.0) as u64,
+    )
+    .code(ErrorCode::RateLimited)
+}
+
+fn too_many(msg: String, retry_after: u64) -> WessError {
+    log_error!(msg, 429).details(ErrorDetails {
+        retry_after: Some(retry_after),
+        ..Default::default()
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// # Returns the number of days since the Unix epoch, in UTC.
+fn today() -> u64 {
+    now() / DAY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(&RateConfig {
+            rate: 2.0,
+            burst: 2,
+        });
+        let start = Instant::now();
+
+        assert!(limiter.acquire_at("ci", start).is_ok());
+        assert!(limiter.acquire_at("ci", start).is_ok());
+        let wait = limiter.acquire_at("ci", start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        assert!(limiter.acquire_at("other", start).is_ok());
+
+        assert!(limiter
+            .acquire_at("ci", start + Duration::from_millis(500))
+            .is_ok());
+        assert!(limiter
+            .acquire_at("ci", start + Duration::from_millis(600))
+            .is_err());
+    }
+
+    #[test]
+    fn test_cpu_time() {
+        let cpu = CpuTime::default();
+        let quota = Quota {
+            cpu_seconds_per_day: Some(1),
+            ..Default::default()
+        };
+
+        cpu.record("search", Duration::from_millis(600));
+        assert!(cpu.check("search", &quota).is_ok());
+
+        cpu.record("search", Duration::from_millis(400));
+        let err = cpu.check("search", &quota).unwrap_err();
+        assert_eq!(err.status, 429);
+        assert_eq!(err.code, ErrorCode::QuotaExceeded);
+        assert!(err.details.unwrap().retry_after.unwrap() <= DAY);
+        assert!(cpu.check("billing", &quota).is_ok());
+
+        cpu.record_on(today() + 1, "search", Duration::from_millis(10));
+        assert_eq!(
+            cpu.used_on(today() + 1, "search"),
+            Duration::from_millis(10)
+        );
+    }
+}
@@ -18,9 +18,11 @@
 //!
 //! The main components of Wess include:
 //!
+//! - `audit`: The module that keeps the trail of writes and API key changes.
 //! - `database`: The module responsible for managing data storage using RocksDB.
 //! - `logger`: A utility module for handling logs and terminal output.
 //! - `server`: The module that implements the Wess server and API endpoints.
+//! - `tenants`: The module that keeps track of what each tenant stores.
 //! - `workers`: A set of modules that manage the execution of WebAssembly functions,
 //!   including reader, writer, and runner.
 //!
@@ -29,14 +31,26 @@
 #[macro_use]
 mod logger;
 
+mod audit;
+mod auth;
 mod config;
 mod database;
 mod errors;
+mod limits;
 mod metrics;
 mod server;
+mod tenants;
 mod workers;
 
-use crate::{config::CONFIG, database::RocksDB, metrics::collect_usage_metrics};
+use crate::{
+    audit::AuditLog,
+    auth::{jwt::JwtVerifier, keys::KeyRing, Authenticator},
+    config::CONFIG,
+    database::{crypto::CIPHER, MemoryDB, ModuleStore, RecordStore},
+    limits::{CpuTime, Limits},
+    metrics::collect_usage_metrics,
+    tenants::Tenants,
+};
 use log::info;
 use logger::init_logger;
 use server::WessServer;
@@ -47,23 +61,60 @@ use tokio::{
 };
 use workers::{reader::Reader, runner::Runner, writer::Writer};
 
+#[cfg(feature = "rocksdb")]
+use crate::database::RocksDB;
+#[cfg(feature = "sqlite")]
+use crate::database::SqliteDB;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
-    tokio::spawn(collect_usage_metrics());
-
-    let config = Arc::clone(&CONFIG);
     init_logger();
 
     info!("------------------------------------------------");
     info!("Starting Wess");
     info!("------------------------------------------------");
 
-    info!("Start RocksDB data base");
-    let db = RocksDB::new();
+    // Reads the keys before anything is encoded, an invalid key stops Wess here
+    if let Some(cipher) = CIPHER.as_ref() {
+        info!("Encrypt modules at rest with key {}", cipher.key_id());
+    }
+
+    match CONFIG.database.db.as_str() {
+        #[cfg(feature = "rocksdb")]
+        "rocksdb" => {
+            info!("Start RocksDB data base");
+            let db = RocksDB::new();
+            db.migrate().expect("RocksDB migration failed");
+            start(db).await
+        }
+        #[cfg(feature = "sqlite")]
+        "sqlite" => {
+            info!("Start SQLite data base");
+            start(SqliteDB::new()).await
+        }
+        "memory" => {
+            info!("Start in-memory data base");
+            start(MemoryDB::new()).await
+        }
+        other => panic!("invalid 'database.db': {}", other),
+    }
+}
+
+/// # Starts the workers and the server on top of the given [`ModuleStore`].
+async fn start<S>(db: S) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    S: ModuleStore + RecordStore + Clone + 'static,
+{
+    tokio::spawn(collect_usage_metrics(db.clone()));
+
+    let config = Arc::clone(&CONFIG);
+    let tenants = Tenants::load(&db);
+    let audit = AuditLog::new(Arc::new(db.clone()), config.audit.path.as_deref())
+        .expect("invalid 'audit.path'");
 
     let (tx_writer, rx_writer) = mpsc::channel::<String>(1);
     info!("Start Writer executor");
-    let (writer_tx, writer) = Writer::new(db.clone(), tx_writer);
+    let (writer_tx, writer) = Writer::new(db.clone(), tx_writer, tenants.clone(), audit.clone());
     let writer_task = {
         let writer = Arc::clone(&writer);
         tokio::spawn(async move {
@@ -72,7 +123,7 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     };
 
     info!("Start Reader executor");
-    let (reader_tx, reader) = Reader::new(db.clone(), rx_writer);
+    let (reader_tx, reader) = Reader::new(db.clone(), rx_writer, tenants);
     let reader_task = {
         let reader = Arc::clone(&reader);
         tokio::spawn(async move {
@@ -80,7 +131,15 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         })
     };
     info!("Start Runner executor");
-    let (runner_tx, runner) = Runner::new(db);
+    let keys = KeyRing::new(Arc::new(db.clone()), config.auth.admin_key.clone());
+    let jwt = config.auth.jwt.clone().map(|jwt| {
+        info!("Accept JWTs signed by {}", jwt.jwks_path);
+        JwtVerifier::new(jwt).expect("invalid 'auth.jwt.jwks_path'")
+    });
+    let auth = Authenticator::new(keys, jwt);
+    let cpu = CpuTime::default();
+    let limits = Limits::new(&config.limits, cpu.clone());
+    let (runner_tx, runner) = Runner::new(db, cpu);
     let runner_task = {
         let runner = Arc::clone(&runner);
         tokio::spawn(async move {
@@ -90,7 +149,9 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
     let addr = format!("{}:{}", config.server.address, config.server.port);
     info!("Start server on {}", &addr);
-    let wess = Arc::new(Mutex::new(WessServer::new(writer_tx, reader_tx, runner_tx)));
+    let wess = Arc::new(Mutex::new(WessServer::new(
+        writer_tx, reader_tx, runner_tx, auth, limits, audit,
+    )));
 
     let server_task = {
         let wess = Arc::clone(&wess);
@@ -1,8 +1,8 @@
 use lazy_static::lazy_static;
 use prometheus::{
     exponential_buckets, register_histogram, register_histogram_vec, register_int_counter,
-    register_int_counter_vec, register_int_gauge, Histogram, HistogramVec, IntCounter,
-    IntCounterVec, IntGauge,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, Histogram, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
 };
 
 #[rustfmt::skip]
@@ -33,6 +33,18 @@ lazy_static! {
         "All requests in the Runner channel queue"
     ).unwrap_or_else(|e| panic!("Metric create `RUNNER_CHANNEL_QUEUE` failed,  {}", e));
 
+    pub static ref HTTP_RESPONSES_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "wess_http_responses_total",
+        "Total number of HTTP responses per status",
+        &["status"]
+    ).unwrap_or_else(|e| panic!("Metric create `HTTP_RESPONSES_TOTAL` failed,  {}", e));
+
+    pub static ref SHED_JOBS_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "wess_shed_jobs_total",
+        "Total number of jobs shed because a worker was too busy",
+        &["worker", "reason"]
+    ).unwrap_or_else(|e| panic!("Metric create `SHED_JOBS_TOTAL` failed,  {}", e));
+
     pub static ref HTTP_REQUEST_LATENCY: Histogram = register_histogram!(
         "wess_http_request_latency_seconds",
         "HTTP request latency in seconds",
@@ -75,14 +87,32 @@ lazy_static! {
     pub static ref WASM_COMPILER_TIME: HistogramVec = register_histogram_vec!(
         "wess_wasm_compile_time_seconds",
         "Compile Wasm time in seconds",
-        &["wasm_module"],
+        &["tenant", "wasm_module"],
         exponential_buckets(0.0005, 2.0, 12).unwrap_or_else(|e| panic!("Metric create `WASM_COMPILER_TIME` failed, {}", e))
     ).unwrap_or_else(|e| panic!("Metric create `WASM_COMPILER_TIME` failed,  {}", e));
     
     pub static ref WASM_EXECUTION_TIME: HistogramVec = register_histogram_vec!(
         "wess_wasm_execution_time_seconds",
         "Execution Wasm function time in seconds",
-        &["function_name"],
+        &["tenant", "function_name"],
         exponential_buckets(0.000005, 2.0, 12).unwrap_or_else(|e| panic!("Metric create `WASM_EXECUTION_TIME` failed, {}", e))
     ).unwrap_or_else(|e| panic!("Metric create `WASM_EXECUTION_TIME` failed, {}", e));
+
+    pub static ref TENANT_REQUESTS_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "wess_tenant_requests_total",
+        "Total number of authenticated HTTP requests per tenant",
+        &["tenant"]
+    ).unwrap_or_else(|e| panic!("Metric create `TENANT_REQUESTS_TOTAL` failed,  {}", e));
+
+    pub static ref TENANT_MODULES: IntGaugeVec = register_int_gauge_vec!(
+        "wess_tenant_modules",
+        "Number of modules stored per tenant",
+        &["tenant"]
+    ).unwrap_or_else(|e| panic!("Metric create `TENANT_MODULES` failed,  {}", e));
+
+    pub static ref TENANT_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "wess_tenant_bytes",
+        "Size of the bytecode stored per tenant in bytes",
+        &["tenant"]
+    ).unwrap_or_else(|e| panic!("Metric create `TENANT_BYTES` failed,  {}", e));
 }
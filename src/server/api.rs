@@ -0,0 +1,266 @@
+//! # The `api` module defines the routes of the Wess HTTP API
+//!
+//! This module contains the following main components:
+//!
+//! - [`Endpoint`]: An enum naming every operation of the API.
+//! - [`Route`]: A struct binding a method and a path to an [`Endpoint`].
+//! - [`ROUTES`]: Every route of the API, used both to register the routes in the
+//!   server and to generate the OpenAPI document, see [`super::openapi`].
+//!
+//! The API lives under `/v1`. The routes served at the root before the API was
+//! versioned are kept as deprecated aliases, answering with a `Deprecation` header.
+
+use super::{
+    openapi::get_openapi,
+    routes::{
+        audit_ops::get_audit,
+        health::health,
+        key_ops::{create_key, list_keys, revoke_key},
+        read_ops::{get_wasm, list_modules, make_read_op},
+        run_ops::make_run_op,
+        write_ops::{get_write_status, make_write_op},
+    },
+    AppState,
+};
+use crate::workers::writer::models::WriteOps;
+use tide::{http::Method, Request, Response, Server};
+
+/// # The operations of the API
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Count,
+    List,
+    Create,
+    Get,
+    Put,
+    Update,
+    Patch,
+    Delete,
+    Wasm,
+    Run,
+    Job,
+    OpenApi,
+    Health,
+    CreateKey,
+    ListKeys,
+    RevokeKey,
+    Reencrypt,
+    Audit,
+}
+
+/// # A route of the API
+pub struct Route {
+    pub method: Method,
+    /// The path of the route, with tide's `:param` placeholders.
+    pub path: &'static str,
+    pub endpoint: Endpoint,
+    /// A one line description of the route.
+    pub summary: &'static str,
+    /// Whether the route is an alias kept for clients of the unversioned API.
+    pub deprecated: bool,
+}
+
+const fn route(
+    method: Method,
+    path: &'static str,
+    endpoint: Endpoint,
+    summary: &'static str,
+) -> Route {
+    Route {
+        method,
+        path,
+        endpoint,
+        summary,
+        deprecated: false,
+    }
+}
+
+const fn alias(
+    method: Method,
+    path: &'static str,
+    endpoint: Endpoint,
+    summary: &'static str,
+) -> Route {
+    Route {
+        method,
+        path,
+        endpoint,
+        summary,
+        deprecated: true,
+    }
+}
+
+/// Every route of the API, the `/v1` routes first and then their deprecated aliases.
+pub const ROUTES: [Route; 28] = [
+    route(Method::Get, "/v1/modules", Endpoint::List, "List modules"),
+    route(
+        Method::Post,
+        "/v1/modules",
+        Endpoint::Create,
+        "Upload a module",
+    ),
+    route(
+        Method::Get,
+        "/v1/modules/count",
+        Endpoint::Count,
+        "Count modules",
+    ),
+    route(
+        Method::Get,
+        "/v1/modules/:id",
+        Endpoint::Get,
+        "Get a module",
+    ),
+    route(
+        Method::Put,
+        "/v1/modules/:id",
+        Endpoint::Put,
+        "Create or replace a module",
+    ),
+    route(
+        Method::Delete,
+        "/v1/modules/:id",
+        Endpoint::Delete,
+        "Delete a module",
+    ),
+    route(
+        Method::Patch,
+        "/v1/modules/:id/metadata",
+        Endpoint::Patch,
+        "Update the metadata of a module",
+    ),
+    route(
+        Method::Get,
+        "/v1/modules/:id/wasm",
+        Endpoint::Wasm,
+        "Download the bytecode of a module",
+    ),
+    route(
+        Method::Post,
+        "/v1/modules/:id/run",
+        Endpoint::Run,
+        "Run a module",
+    ),
+    route(
+        Method::Get,
+        "/v1/jobs/:job",
+        Endpoint::Job,
+        "Get the status of a write",
+    ),
+    route(
+        Method::Get,
+        "/v1/openapi.json",
+        Endpoint::OpenApi,
+        "Get this document",
+    ),
+    route(
+        Method::Get,
+        "/v1/health",
+        Endpoint::Health,
+        "Check that the server is serving",
+    ),
+    route(
+        Method::Get,
+        "/v1/admin/keys",
+        Endpoint::ListKeys,
+        "List API keys",
+    ),
+    route(
+        Method::Post,
+        "/v1/admin/keys",
+        Endpoint::CreateKey,
+        "Create an API key",
+    ),
+    route(
+        Method::Delete,
+        "/v1/admin/keys/:key",
+        Endpoint::RevokeKey,
+        "Revoke an API key",
+    ),
+    route(
+        Method::Post,
+        "/v1/admin/reencrypt",
+        Endpoint::Reencrypt,
+        "Re-encrypt stored modules with the current key",
+    ),
+    route(
+        Method::Get,
+        "/v1/admin/audit",
+        Endpoint::Audit,
+        "Read the audit trail",
+    ),
+    alias(Method::Get, "/", Endpoint::Count, "Count modules"),
+    alias(Method::Post, "/", Endpoint::Create, "Upload a module"),
+    alias(Method::Get, "/modules", Endpoint::List, "List modules"),
+    alias(
+        Method::Put,
+        "/modules/:id",
+        Endpoint::Put,
+        "Create or replace a module",
+    ),
+    alias(
+        Method::Get,
+        "/jobs/:job",
+        Endpoint::Job,
+        "Get the status of a write",
+    ),
+    alias(Method::Get, "/:id", Endpoint::Get, "Get a module"),
+    alias(Method::Put, "/:id", Endpoint::Update, "Replace a module"),
+    alias(Method::Delete, "/:id", Endpoint::Delete, "Delete a module"),
+    alias(Method::Post, "/:id", Endpoint::Run, "Run a module"),
+    alias(
+        Method::Patch,
+        "/:id/metadata",
+        Endpoint::Patch,
+        "Update the metadata of a module",
+    ),
+    alias(
+        Method::Get,
+        "/:id/wasm",
+        Endpoint::Wasm,
+        "Download the bytecode of a module",
+    ),
+];
+
+/// # Registers every route of [`ROUTES`] in the server.
+///
+/// ## Arguments
+///
+/// * `app` - The tide [`Server`] the routes are added to.
+pub fn register(app: &mut Server<AppState>) {
+    for route in ROUTES.iter() {
+        let endpoint = route.endpoint;
+        let deprecated = route.deprecated;
+
+        app.at(route.path)
+            .method(route.method, move |req| async move {
+                let mut response = dispatch(endpoint, req).await?;
+                if deprecated {
+                    response.insert_header("Deprecation", "true");
+                }
+                Ok(response)
+            });
+    }
+}
+
+async fn dispatch(endpoint: Endpoint, req: Request<AppState>) -> tide::Result<Response> {
+    match endpoint {
+        Endpoint::Count | Endpoint::Get => make_read_op(req).await,
+        Endpoint::List => list_modules(req).await,
+        Endpoint::Create => make_write_op(req, WriteOps::Create).await,
+        Endpoint::Put => make_write_op(req, WriteOps::Put).await,
+        Endpoint::Update => make_write_op(req, WriteOps::Update).await,
+        Endpoint::Patch => make_write_op(req, WriteOps::Patch).await,
+        Endpoint::Delete => make_write_op(req, WriteOps::Delete).await,
+        Endpoint::Wasm => get_wasm(req).await,
+        Endpoint::Run => make_run_op(req).await,
+        Endpoint::Job => get_write_status(req).await,
+        Endpoint::OpenApi => get_openapi(req).await,
+        Endpoint::Health => health(req).await,
+        Endpoint::CreateKey => create_key(req).await,
+        Endpoint::ListKeys => list_keys(req).await,
+        Endpoint::RevokeKey => revoke_key(req).await,
+        Endpoint::Reencrypt => make_write_op(req, WriteOps::Reencrypt).await,
+        Endpoint::Audit => get_audit(req).await,
+    }
+}
@@ -7,20 +7,35 @@
 //!
 //! The `server` module depends on the following modules:
 //!
+//! - [`api`]: A module that contains the table of HTTP routes.
+//! - [`openapi`]: A module that generates the OpenAPI document of the routes.
 //! - [`routes`]: A module that contains the logic for handling HTTP routes.
+//! - [`tls`]: A module that serves the routes over HTTPS when `[server.tls]` is set.
 //! - [`models`]: A module that contains the models for wrap json requests.
 
-
+pub mod api;
+pub mod openapi;
 pub mod response;
 mod routes;
+pub mod tls;
 
-use self::routes::{metrics::prometheus_metrics, middleware::RequestMetricsMiddleware};
+use self::{
+    routes::{
+        metrics::prometheus_metrics,
+        middleware::{auth::AuthMiddleware, RequestMetricsMiddleware},
+    },
+    tls::{TlsAcceptor, TlsListener},
+};
+use crate::audit::AuditLog;
+use crate::auth::Authenticator;
+use crate::config::CONFIG;
+use crate::limits::Limits;
 use crate::workers::{
+    queue::Queue,
     reader::models::ReadJob,
     runner::models::RunJob,
-    writer::models::{WriteJob, WriteOps},
+    writer::{models::WriteJob, status::WriteStatuses},
 };
-use routes::{read_ops::make_read_op, run_ops::make_run_op, write_ops::make_write_op};
 use tide::Server;
 use tokio::sync::mpsc::Sender;
 
@@ -30,8 +45,16 @@ use tokio::sync::mpsc::Sender;
 #[derive(Clone)]
 pub struct AppState {
     pub writer_tx: Sender<WriteJob>,
-    pub reader_tx: Sender<ReadJob>,
+    pub reader_tx: Queue<ReadJob>,
     pub runner_tx: Sender<RunJob>,
+    /// The status of writes sent without waiting for their outcome.
+    pub write_statuses: WriteStatuses,
+    /// Finds the caller of each request.
+    pub auth: Authenticator,
+    /// The rate limits and CPU time quotas checked before each run.
+    pub limits: Limits,
+    /// The audit trail, the Writer records the writes it runs.
+    pub audit: AuditLog,
 }
 
 /// The main server struct for the Wess application.
@@ -46,40 +69,40 @@ impl WessServer {
     /// ## Arguments
     ///
     /// * `writer_tx` - A `Sender<WriteJob>` for sending jobs to the writer worker.
-    /// * `reader_tx` - A `Sender<ReadJob>` for sending jobs to the reader worker.
+    /// * `reader_tx` - A `Queue<ReadJob>` for sending jobs to the reader worker.
     /// * `runner_tx` - A `Sender<RunJob>` for sending jobs to the runner worker.
+    /// * `auth` - The [`Authenticator`] checking the bearer tokens of requests.
+    /// * `limits` - The [`Limits`] checked before each run.
+    /// * `audit` - The [`AuditLog`] recording writes and key changes.
     ///
     /// ## Returns
     ///
     /// * An instance of `WessServer`.
     pub fn new(
         writer_tx: Sender<WriteJob>,
-        reader_tx: Sender<ReadJob>,
+        reader_tx: Queue<ReadJob>,
         runner_tx: Sender<RunJob>,
+        auth: Authenticator,
+        limits: Limits,
+        audit: AuditLog,
     ) -> Self {
         let mut app = tide::with_state(AppState {
             writer_tx,
             reader_tx,
             runner_tx,
+            write_statuses: WriteStatuses::new(),
+            auth,
+            limits,
+            audit,
         });
 
         // Metrics middleware
         app.with(RequestMetricsMiddleware);
+        // Auth middleware
+        app.with(AuthMiddleware);
 
-        // Write ops
-        app.at("/")
-            // Read ops
-            .get(|req| async { make_read_op(req).await })
-            .post(|req| async { make_write_op(req, WriteOps::Create).await });
-        app.at("/:id")
-            .put(|req| async { make_write_op(req, WriteOps::Update).await })
-            .delete(|req| async { make_write_op(req, WriteOps::Delete).await });
-
-        // Read ops
-        app.at("/:id").get(|req| async { make_read_op(req).await });
-
-        // Run Ops
-        app.at("/:id").post(|req| async { make_run_op(req).await });
+        // Module, job and OpenAPI routes, see `api::ROUTES`
+        api::register(&mut app);
 
         // Metrics routes
         app.at("/metrics")
@@ -90,6 +113,8 @@ impl WessServer {
 
     /// # Starts the server on the specified address.
     ///
+    /// Serves HTTPS when `[server.tls]` is set, and plain HTTP otherwise.
+    ///
     /// ## Arguments
     ///
     /// * `addr` - A `&str` representing the address to listen to.
@@ -98,6 +123,13 @@ impl WessServer {
     ///
     /// * A `std::io::Result` indicating if the server started successfully.
     pub async fn run(self, addr: &str) -> std::io::Result<()> {
-        self.app.listen(addr).await
+        match &CONFIG.server.tls {
+            Some(tls) => {
+                let acceptor = TlsAcceptor::new(tls.clone())
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.msg))?;
+                self.app.listen(TlsListener::new(addr, acceptor)).await
+            }
+            None => self.app.listen(addr).await,
+        }
     }
 }
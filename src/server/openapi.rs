@@ -0,0 +1,684 @@
+//! # The `openapi` module generates the OpenAPI document of the Wess HTTP API
+//!
+//! The document is built from [`ROUTES`], so it can't drift from the routes the server
+//! actually registers. Besides the generic routes, it holds one `run` path per module
+//! the caller may run, whose request and response schemas are derived from the
+//! [`WasmMetadata`] of the module.
+//!
+//! [`WasmMetadata`]: crate::database::models::WasmMetadata
+
+use super::{
+    api::{Endpoint, Route, ROUTES},
+    response::respond_with_error,
+    routes::middleware::auth::{caller, PUBLIC_PATHS},
+    AppState,
+};
+use crate::{
+    auth::Identity,
+    database::models::ModuleSummary,
+    errors::WessError,
+    workers::{
+        queue::{Priority, Queue},
+        reader::models::{ListQuery, ReadJob, ReadOps, ReadResponse, MAX_PAGE_SIZE},
+    },
+};
+use serde_json::{json, Map, Value};
+use tide::{Error, Request, Response, StatusCode};
+use tokio::sync::oneshot;
+
+/// The version of the OpenAPI specification the document follows.
+pub const OPENAPI_VERSION: &str = "3.1.0";
+
+/// # Serves the OpenAPI document.
+///
+/// The document needs authentication like the routes it describes, and only holds
+/// the `run` paths of the modules of the tenant of the caller that the caller may run.
+///
+/// ## Returns
+///
+/// * A `Result` object with the document, or an error response if the modules can't be listed.
+pub async fn get_openapi(req: Request<AppState>) -> Result<Response, Error> {
+    let identity = match caller(&req) {
+        Ok(identity) => identity,
+        Err(e) => return respond_with_error(e).await,
+    };
+
+    match list_all(req.state().reader_tx.clone(), identity).await {
+        Ok(modules) => Ok(Response::builder(StatusCode::Ok)
+            .body(document(&modules))
+            .build()),
+        Err(e) => respond_with_error(e).await,
+    }
+}
+
+/// # Builds the OpenAPI document.
+///
+/// ## Arguments
+///
+/// * `modules` - The stored modules, each one gets its own `run` path.
+///
+/// ## Returns
+///
+/// * The document as a JSON `Value`.
+pub fn document(modules: &[ModuleSummary]) -> Value {
+    let mut paths = Map::new();
+
+    for route in ROUTES.iter() {
+        let item = paths
+            .entry(openapi_path(route.path))
+            .or_insert_with(|| json!({}));
+        item[route.method.to_string().to_lowercase()] = operation(route);
+    }
+
+    for module in modules {
+        paths.insert(
+            format!("/v1/modules/{}/run", module.id),
+            json!({ "post": run_operation(module) }),
+        );
+    }
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": "Wess",
+            "description": "WebAssembly Serverless Service",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "security": [{ "apiKey": [] }],
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "apiKey": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "An API key, or a JWT when [auth.jwt] is set",
+                },
+            },
+            "responses": { "Error": {
+                "description": "The request failed",
+                "content": { "application/problem+json": {
+                    "schema": { "$ref": "#/components/schemas/Problem" },
+                } },
+            } },
+        },
+    })
+}
+
+/// # Maps a WebAssembly type to the JSON schema of its values.
+///
+/// ## Arguments
+///
+/// * `t` - The type of an argument or a result, `None` if it isn't known.
+///
+/// ## Returns
+///
+/// * The JSON schema, empty for types that can't be sent as JSON.
+pub fn type_schema(t: &Option<wasmer::Type>) -> Value {
+    match t {
+        Some(wasmer::Type::I32) => json!({ "type": "integer", "format": "int32" }),
+        Some(wasmer::Type::I64) => json!({ "type": "integer", "format": "int64" }),
+        Some(wasmer::Type::F32) => json!({ "type": "number", "format": "float" }),
+        Some(wasmer::Type::F64) => json!({ "type": "number", "format": "double" }),
+        _ => json!({}),
+    }
+}
+
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(param) => format!("{{{}}}", param),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn operation(route: &Route) -> Value {
+    let mut op = json!({
+        "summary": route.summary,
+        "responses": responses(route.endpoint),
+    });
+
+    if route.deprecated {
+        op["deprecated"] = json!(true);
+    } else {
+        op["operationId"] = json!(format!("{:?}", route.endpoint).to_lowercase());
+    }
+
+    let params: Vec<Value> = route
+        .path
+        .split('/')
+        .filter_map(|segment| segment.strip_prefix(':'))
+        .map(|name| json!({ "name": name, "in": "path", "required": true, "schema": { "type": "string" } }))
+        .chain(query_parameters(route.endpoint))
+        .chain(header_parameters(route.endpoint))
+        .collect();
+    if !params.is_empty() {
+        op["parameters"] = json!(params);
+    }
+
+    if let Some(body) = request_body(route.endpoint) {
+        op["requestBody"] = body;
+    }
+
+    if PUBLIC_PATHS.contains(&route.path) {
+        op["security"] = json!([]);
+    } else {
+        op["responses"]["401"] = json!({ "$ref": "#/components/responses/Error" });
+    }
+
+    op
+}
+
+fn query_parameters(endpoint: Endpoint) -> Vec<Value> {
+    let query =
+        |name: &str, schema: Value| json!({ "name": name, "in": "query", "schema": schema });
+
+    match endpoint {
+        Endpoint::List => vec![
+            query("cursor", json!({ "type": "string" })),
+            query(
+                "limit",
+                json!({ "type": "integer", "minimum": 1, "maximum": MAX_PAGE_SIZE }),
+            ),
+            query("functionName", json!({ "type": "string" })),
+            query("label", json!({ "type": "string" })),
+        ],
+        Endpoint::Audit => vec![
+            query(
+                "from",
+                json!({ "type": "integer", "description": "Unix time, included" }),
+            ),
+            query(
+                "to",
+                json!({ "type": "integer", "description": "Unix time, excluded" }),
+            ),
+            query("cursor", json!({ "type": "string" })),
+            query(
+                "limit",
+                json!({ "type": "integer", "minimum": 1, "maximum": MAX_PAGE_SIZE }),
+            ),
+            query(
+                "tenant",
+                json!({ "type": "string", "description": "Set to their own by the admins of a tenant" }),
+            ),
+        ],
+        Endpoint::Create
+        | Endpoint::Put
+        | Endpoint::Update
+        | Endpoint::Patch
+        | Endpoint::Delete => {
+            vec![query("wait", json!({ "type": "boolean", "default": true }))]
+        }
+        _ => vec![],
+    }
+}
+
+fn header_parameters(endpoint: Endpoint) -> Vec<Value> {
+    let header = |name: &str, description: &str| json!({ "name": name, "in": "header", "description": description, "schema": { "type": "string" } });
+
+    match endpoint {
+        Endpoint::Create | Endpoint::Put | Endpoint::Update => vec![
+            header(
+                "X-Wess-Signature",
+                "The Ed25519 signature of the bytecode, base64 encoded",
+            ),
+            header(
+                "X-Wess-Signer",
+                "The ID of the trusted key that signed the bytecode",
+            ),
+        ],
+        _ => vec![],
+    }
+}
+
+fn request_body(endpoint: Endpoint) -> Option<Value> {
+    let module = json!({ "$ref": "#/components/schemas/WasmModule" });
+
+    match endpoint {
+        Endpoint::Create | Endpoint::Put | Endpoint::Update => Some(json!({
+            "required": true,
+            "content": {
+                "application/json": { "schema": module },
+                "application/wasm": { "schema": { "type": "string", "format": "binary" } },
+                "text/wat": { "schema": { "type": "string" } },
+                "multipart/form-data": { "schema": {
+                    "type": "object",
+                    "properties": {
+                        "wasm": { "type": "string", "format": "binary" },
+                        "wat": { "type": "string" },
+                        "metadata": { "$ref": "#/components/schemas/WasmMetadata" },
+                    },
+                    "required": ["metadata"],
+                } },
+            },
+        })),
+        Endpoint::Patch => Some(json!({
+            "required": true,
+            "content": { "application/json": {
+                "schema": { "$ref": "#/components/schemas/MetadataPatch" },
+            } },
+        })),
+        Endpoint::Run => Some(json!({
+            "required": true,
+            "content": { "application/json": { "schema": { "type": "array" } } },
+        })),
+        Endpoint::CreateKey => Some(json!({
+            "required": true,
+            "content": { "application/json": {
+                "schema": { "$ref": "#/components/schemas/NewKey" },
+            } },
+        })),
+        _ => None,
+    }
+}
+
+fn responses(endpoint: Endpoint) -> Value {
+    let ok = |description: &str, schema: Value| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": message(schema) } },
+        })
+    };
+    let error = json!({ "$ref": "#/components/responses/Error" });
+
+    let mut responses = match endpoint {
+        Endpoint::Count => {
+            json!({ "202": ok("The number of modules", json!({ "type": "integer" })) })
+        }
+        Endpoint::List => {
+            json!({ "200": ok("A page of modules", json!({ "$ref": "#/components/schemas/ModulePage" })) })
+        }
+        Endpoint::Get => json!({
+            "200": ok("The module", json!({ "$ref": "#/components/schemas/WasmModule" })),
+            "403": error,
+            "404": error,
+        }),
+        Endpoint::Create | Endpoint::Put => json!({
+            "201": ok("The module was created", json!({ "type": "string" })),
+            "202": ok("The write was queued", json!({ "$ref": "#/components/schemas/WriteJob" })),
+            "403": error,
+        }),
+        Endpoint::Update | Endpoint::Patch => json!({
+            "200": ok("The module was updated", json!({ "type": "string" })),
+            "202": ok("The write was queued", json!({ "$ref": "#/components/schemas/WriteJob" })),
+            "403": error,
+            "404": error,
+            "412": error,
+        }),
+        Endpoint::Delete => json!({
+            "204": { "description": "The module was deleted" },
+            "202": ok("The write was queued", json!({ "$ref": "#/components/schemas/WriteJob" })),
+            "403": error,
+            "404": error,
+            "412": error,
+        }),
+        Endpoint::Wasm => json!({
+            "200": {
+                "description": "The bytecode of the module",
+                "content": { "application/wasm": { "schema": { "type": "string", "format": "binary" } } },
+            },
+            "304": { "description": "The bytecode matches If-None-Match" },
+            "403": error,
+            "404": error,
+        }),
+        Endpoint::Run => json!({
+            "200": ok("The results of the function", json!({ "type": "array" })),
+            "403": error,
+            "404": error,
+            "422": error,
+            "429": error,
+        }),
+        Endpoint::Job => json!({
+            "200": { "description": "The status of the write", "content": { "application/json": {
+                "schema": { "$ref": "#/components/schemas/WriteStatus" },
+            } } },
+            "404": error,
+        }),
+        Endpoint::OpenApi => json!({
+            "200": { "description": "This document", "content": { "application/json": {} } },
+        }),
+        Endpoint::Health => json!({
+            "200": ok("The server is serving", json!({ "type": "object" })),
+            "503": error,
+        }),
+        Endpoint::CreateKey => json!({
+            "201": ok("The key, with its token", json!({ "$ref": "#/components/schemas/ApiKey" })),
+            "403": error,
+        }),
+        Endpoint::ListKeys => json!({
+            "200": ok("The keys", json!({ "type": "array", "items": { "$ref": "#/components/schemas/ApiKey" } })),
+            "403": error,
+        }),
+        Endpoint::RevokeKey => json!({
+            "204": { "description": "The key was revoked" },
+            "403": error,
+            "404": error,
+        }),
+        Endpoint::Reencrypt => json!({
+            "202": ok("The job was queued", json!({ "$ref": "#/components/schemas/WriteJob" })),
+            "403": error,
+            "409": error,
+        }),
+        Endpoint::Audit => json!({
+            "200": ok("A page of the audit trail", json!({ "$ref": "#/components/schemas/AuditPage" })),
+            "403": error,
+        }),
+    };
+    responses["400"] = json!({ "$ref": "#/components/responses/Error" });
+    // Every endpoint waiting for a worker sheds its request when the worker is too busy
+    if !matches!(
+        endpoint,
+        Endpoint::Job
+            | Endpoint::CreateKey
+            | Endpoint::ListKeys
+            | Endpoint::RevokeKey
+            | Endpoint::Audit
+    ) {
+        responses["503"] = json!({ "$ref": "#/components/responses/Error" });
+    }
+
+    responses
+}
+
+fn run_operation(module: &ModuleSummary) -> Value {
+    let signature = &module.signature;
+    let args: Vec<Value> = signature.args.iter().map(type_schema).collect();
+    let results: Vec<Value> = signature.return_type.iter().map(type_schema).collect();
+
+    json!({
+        "summary": format!("Run {}", module.name.as_deref().unwrap_or(&module.function_name)),
+        "description": module.description,
+        "operationId": format!("run-{}", module.id),
+        "requestBody": {
+            "required": true,
+            "content": { "application/json": { "schema": tuple(args) } },
+        },
+        "responses": {
+            "200": {
+                "description": "The results of the function",
+                "content": { "application/json": { "schema": message(tuple(results)) } },
+            },
+            "400": { "$ref": "#/components/responses/Error" },
+            "401": { "$ref": "#/components/responses/Error" },
+            "403": { "$ref": "#/components/responses/Error" },
+            "404": { "$ref": "#/components/responses/Error" },
+            "422": { "$ref": "#/components/responses/Error" },
+            "429": { "$ref": "#/components/responses/Error" },
+            "503": { "$ref": "#/components/responses/Error" },
+        },
+    })
+}
+
+fn tuple(items: Vec<Value>) -> Value {
+    json!({
+        "type": "array",
+        "minItems": items.len(),
+        "maxItems": items.len(),
+        "prefixItems": items,
+    })
+}
+
+fn message(schema: Value) -> Value {
+    json!({
+        "type": "object",
+        "properties": { "message": schema },
+        "required": ["message"],
+    })
+}
+
+fn schemas() -> Value {
+    let types = json!({ "type": "array", "items": {
+        "type": ["string", "null"],
+        "enum": ["I32", "I64", "F32", "F64", "V128", "ExternRef", "FuncRef", null],
+    } });
+    let labels = json!({ "type": "array", "items": { "type": "string" } });
+
+    json!({
+        "WasmMetadata": {
+            "type": "object",
+            "properties": {
+                "functionName": { "type": "string" },
+                "returnType": types,
+                "args": types,
+                "name": { "type": "string" },
+                "description": { "type": "string" },
+                "labels": labels,
+                "uploader": { "type": "string" },
+                "owner": { "type": "string", "readOnly": true },
+                "signer": { "type": "string", "readOnly": true },
+                "signature": { "type": "array", "items": { "type": "integer" }, "readOnly": true },
+                "acl": { "$ref": "#/components/schemas/Acl" },
+            },
+            "required": ["functionName", "returnType", "args"],
+        },
+        "Acl": {
+            "type": "object",
+            "description": "IDs of API keys, or * for everyone",
+            "properties": {
+                "run": { "type": "array", "items": { "type": "string" } },
+                "manage": { "type": "array", "items": { "type": "string" } },
+            },
+            "additionalProperties": false,
+        },
+        "WasmModule": {
+            "type": "object",
+            "properties": {
+                "wasm": { "type": "array", "items": { "type": "integer", "minimum": 0, "maximum": 255 } },
+                "wat": { "type": "string" },
+                "metadata": { "$ref": "#/components/schemas/WasmMetadata" },
+            },
+            "required": ["metadata"],
+        },
+        "MetadataPatch": {
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "description": { "type": "string" },
+                "labels": labels,
+                "acl": { "$ref": "#/components/schemas/Acl" },
+            },
+            "additionalProperties": false,
+        },
+        "ModuleSummary": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "string" },
+                "functionName": { "type": "string" },
+                "name": { "type": "string" },
+                "description": { "type": "string" },
+                "signature": {
+                    "type": "object",
+                    "properties": { "args": types, "returnType": types },
+                },
+                "size": { "type": "integer" },
+                "hash": { "type": "string" },
+                "labels": labels,
+                "uploader": { "type": "string" },
+                "owner": { "type": "string" },
+                "createdAt": { "type": "integer" },
+                "updatedAt": { "type": "integer" },
+            },
+        },
+        "ModulePage": {
+            "type": "object",
+            "properties": {
+                "modules": { "type": "array", "items": { "$ref": "#/components/schemas/ModuleSummary" } },
+                "next": { "type": ["string", "null"] },
+            },
+        },
+        "Problem": {
+            "type": "object",
+            "properties": {
+                "type": { "type": "string" },
+                "title": { "type": "string" },
+                "status": { "type": "integer" },
+                "detail": { "type": "string" },
+                "code": { "type": "string" },
+                "argIndex": { "type": "integer" },
+                "expected": { "type": "string" },
+                "got": { "type": "string" },
+                "trap": { "type": "string" },
+                "backtrace": { "type": "array", "items": {
+                    "type": "object",
+                    "properties": {
+                        "function": { "type": ["string", "null"] },
+                        "funcIndex": { "type": "integer" },
+                        "offset": { "type": "integer" },
+                    },
+                } },
+                "retryAfter": { "type": "integer" },
+            },
+            "required": ["type", "title", "status", "code"],
+        },
+        "ApiKey": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "string" },
+                "name": { "type": "string" },
+                "role": { "$ref": "#/components/schemas/Role" },
+                "tenant": { "type": "string" },
+                "createdAt": { "type": "integer" },
+                "token": { "type": "string", "description": "Only sent when the key is created" },
+            },
+        },
+        "Role": { "type": "string", "enum": ["admin", "publisher", "invoker"], "default": "invoker" },
+        "NewKey": {
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "role": { "$ref": "#/components/schemas/Role" },
+                "tenant": { "type": "string", "pattern": "^[a-z0-9_-]{1,64}$" },
+            },
+            "required": ["name"],
+            "additionalProperties": false,
+        },
+        "AuditEntry": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "string" },
+                "timestamp": { "type": "integer" },
+                "caller": { "type": "string" },
+                "tenant": { "type": "string" },
+                "operation": { "type": "string", "enum": [
+                    "create", "put", "update", "patch", "delete", "reencrypt", "createKey", "revokeKey", "run",
+                ] },
+                "module": { "type": "string" },
+                "key": { "type": "string" },
+                "revision": { "type": "integer" },
+                "hash": { "type": "string" },
+                "outcome": { "type": "string", "enum": ["success", "failure"] },
+                "status": { "type": "integer" },
+                "code": { "type": "string" },
+            },
+        },
+        "AuditPage": {
+            "type": "object",
+            "properties": {
+                "entries": { "type": "array", "items": { "$ref": "#/components/schemas/AuditEntry" } },
+                "next": { "type": ["string", "null"] },
+            },
+        },
+        "WriteJob": {
+            "type": "object",
+            "properties": { "id": { "type": "string" }, "job": { "type": "string" } },
+        },
+        "WriteStatus": {
+            "type": "object",
+            "properties": {
+                "status": { "type": "string", "enum": ["pending", "done", "failed"] },
+                "id": { "type": "string" },
+                "code": { "type": "string" },
+                "message": { "type": "string" },
+            },
+        },
+    })
+}
+
+async fn list_all(
+    reader_tx: Queue<ReadJob>,
+    identity: Identity,
+) -> Result<Vec<ModuleSummary>, WessError> {
+    let mut modules = vec![];
+    let mut cursor = None;
+
+    loop {
+        let (tx, rx) = oneshot::channel::<ReadResponse>();
+        let query = ListQuery {
+            cursor,
+            limit: Some(MAX_PAGE_SIZE),
+            ..Default::default()
+        };
+        reader_tx
+            .send(
+                ReadJob::new(tx, ReadOps::List(identity.clone(), query)),
+                Priority::High,
+            )
+            .await?;
+
+        match rx.await {
+            Ok(ReadResponse::Page(page)) => {
+                modules.extend(page.modules);
+                match page.next {
+                    Some(next) => cursor = Some(next),
+                    None => return Ok(modules),
+                }
+            }
+            Ok(ReadResponse::Fail(e)) => return Err(e),
+            Ok(_) => unreachable!(),
+            Err(e) => return Err(log_error!(format!("Channel Error: {}", e), 500)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::{WasmMetadata, WasmModule};
+
+    #[test]
+    fn test_document_paths() {
+        let doc = document(&[]);
+        let paths = doc["paths"].as_object().unwrap();
+
+        assert_eq!(doc["openapi"], OPENAPI_VERSION);
+        assert!(doc["components"]["responses"]["Error"].is_object());
+        assert!(paths["/v1/modules"]["get"]["operationId"].is_string());
+        assert!(paths["/v1/modules/{id}"]["put"]["requestBody"].is_object());
+        assert_eq!(
+            paths["/v1/modules/{id}"]["get"]["parameters"][0]["name"],
+            "id"
+        );
+        assert_eq!(paths["/{id}"]["post"]["deprecated"], true);
+        assert_eq!(paths["/v1/health"]["get"]["security"], json!([]));
+        assert!(paths["/v1/openapi.json"]["get"].get("security").is_none());
+        assert!(paths["/v1/admin/keys/{key}"]["delete"]["responses"]["401"].is_object());
+        assert!(paths["/v1/modules/{id}/run"]["post"]
+            .get("deprecated")
+            .is_none());
+    }
+
+    #[test]
+    fn test_run_schema_from_metadata() {
+        let metadata = WasmMetadata::new(
+            "sum".to_string(),
+            vec![Some(wasmer::Type::F64)],
+            vec![Some(wasmer::Type::I32), Some(wasmer::Type::I64)],
+        );
+        let module = ModuleSummary::new("sum".to_string(), &WasmModule::new(vec![], metadata));
+
+        let doc = document(&[module]);
+        let run = &doc["paths"]["/v1/modules/sum/run"]["post"];
+        let args = &run["requestBody"]["content"]["application/json"]["schema"];
+        let results = &run["responses"]["200"]["content"]["application/json"]["schema"];
+
+        assert_eq!(args["prefixItems"][0]["format"], "int32");
+        assert_eq!(args["prefixItems"][1]["format"], "int64");
+        assert_eq!(args["maxItems"], 2);
+        assert_eq!(
+            results["properties"]["message"]["prefixItems"][0]["format"],
+            "double"
+        );
+    }
+}
@@ -17,19 +17,54 @@ use tide::{Error, Response, StatusCode};
 /// }
 /// ```
 
-/// # Returns an error response with the provided message.
+/// The media type of error bodies, defined by RFC 7807.
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// # Returns an error response with a RFC 7807 problem body.
+///
+/// The body holds the standard `type`, `title`, `status` and `detail` members, the
+/// stable `code` of the error and, when the error has them, its [`ErrorDetails`].
+/// Errors telling when to retry also set the `Retry-After` header.
 ///
 /// ## Arguments
 ///
-/// * `message` - The error message to include in the response body.
+/// * `error` - The [`WessError`] to describe in the response body.
 ///
 /// ## Returns
 ///
 /// * A [`Result`] containing the error response.
+///
+/// [`ErrorDetails`]: crate::errors::ErrorDetails
 pub async fn respond_with_error(error: WessError) -> Result<Response, Error> {
-    Ok(Response::builder(error.status)
-        .body(json!({ "message": error.msg }))
-        .build())
+    let mut response = Response::builder(error.status)
+        .body(problem(&error))
+        .content_type(PROBLEM_JSON)
+        .build();
+    if let Some(retry_after) = error.details.as_ref().and_then(|d| d.retry_after) {
+        response.insert_header("Retry-After", retry_after.to_string());
+    }
+
+    Ok(response)
+}
+
+/// # Builds the RFC 7807 problem body of an error.
+pub fn problem(error: &WessError) -> serde_json::Value {
+    let mut body = json!({
+        "type": format!("urn:wess:error:{}", error.code.as_str().to_lowercase().replace('_', "-")),
+        "title": error.code.title(),
+        "status": u16::from(error.status),
+        "detail": error.msg,
+        "code": error.code,
+    });
+    if let Some(details) = &error.details {
+        if let (Some(body), Ok(serde_json::Value::Object(details))) =
+            (body.as_object_mut(), serde_json::to_value(details))
+        {
+            body.extend(details);
+        }
+    }
+
+    body
 }
 
 /// # Returns a successful response with the provided message.
@@ -49,3 +84,46 @@ where
         .body(json!({ "message": message }))
         .build())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{ErrorCode, ErrorDetails};
+
+    #[test]
+    fn test_problem() {
+        let error = WessError::new("Invalid Type: I32".to_string(), 400)
+            .code(ErrorCode::ArgTypeMismatch)
+            .details(ErrorDetails {
+                arg_index: Some(1),
+                expected: Some("I32".to_string()),
+                got: Some("string".to_string()),
+                ..Default::default()
+            });
+
+        let body = problem(&error);
+
+        assert_eq!(body["type"], "urn:wess:error:arg-type-mismatch");
+        assert_eq!(body["status"], 400);
+        assert_eq!(body["code"], "ARG_TYPE_MISMATCH");
+        assert_eq!(body["detail"], "Invalid Type: I32");
+        assert_eq!(body["argIndex"], 1);
+        assert_eq!(body["expected"], "I32");
+        assert!(problem(&WessError::new("".to_string(), 404))
+            .get("argIndex")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        let error = WessError::new("Rate Limited".to_string(), 429).details(ErrorDetails {
+            retry_after: Some(3),
+            ..Default::default()
+        });
+
+        let response = respond_with_error(error).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::TooManyRequests);
+        assert_eq!(response["Retry-After"], "3");
+    }
+}
@@ -0,0 +1,40 @@
+use crate::{
+    audit::AuditQuery,
+    errors::ErrorCode,
+    server::{
+        response::{respond, respond_with_error},
+        routes::middleware::auth::require_admin,
+        AppState,
+    },
+};
+use tide::{Error, Request, Response, StatusCode};
+
+/// # Handler function for reading the audit trail.
+///
+/// Admins with a tenant only read the entries of the callers of their tenant.
+///
+/// ## Arguments
+///
+/// * `req` - The [`Request`] object with an [`AuditQuery`] as query string.
+///
+/// ## Returns
+///
+/// A [`Result`] containing the [`Response`] object with a page of entries, oldest first.
+pub async fn get_audit(req: Request<AppState>) -> Result<Response, Error> {
+    let page = require_admin(&req)
+        .and_then(|identity| {
+            let mut query = req.query::<AuditQuery>().map_err(|e| {
+                log_error!(format!("Invalid Query: {}", e), 400).code(ErrorCode::BadRequest)
+            })?;
+            if identity.tenant.is_some() {
+                query.tenant = Some(identity.tenant_name().to_string());
+            }
+            Ok(query)
+        })
+        .and_then(|query| req.state().audit.query(&query));
+
+    match page {
+        Ok(page) => respond(page, StatusCode::Ok).await,
+        Err(e) => respond_with_error(e).await,
+    }
+}
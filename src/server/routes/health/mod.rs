@@ -0,0 +1,46 @@
+use crate::{
+    server::{
+        response::{respond, respond_with_error},
+        AppState,
+    },
+    workers::{
+        queue::Priority,
+        reader::models::{ReadJob, ReadOps, ReadResponse},
+    },
+};
+use tide::{Error, Request, Response, StatusCode};
+use tokio::sync::oneshot;
+
+/// # Handler function for health checks.
+///
+/// Pings the Reader through its high priority queue, so the check keeps answering
+/// while run requests fill the queues, and answers 503 if the Reader doesn't take
+/// the ping in time.
+///
+/// ## Arguments
+///
+/// * `req` - The [`Request`] object of the health check.
+///
+/// ## Returns
+///
+/// A [`Result`] containing the [`Response`] object.
+pub async fn health(req: Request<AppState>) -> Result<Response, Error> {
+    let (tx, rx) = oneshot::channel::<ReadResponse>();
+
+    if let Err(e) = req
+        .state()
+        .reader_tx
+        .send(ReadJob::new(tx, ReadOps::Ping), Priority::High)
+        .await
+    {
+        return respond_with_error(e).await;
+    }
+
+    match rx.await {
+        Ok(_) => respond(serde_json::json!({ "status": "ok" }), StatusCode::Ok).await,
+        Err(e) => {
+            let werr = log_error!(format!("Channel Error: {}", e), 500);
+            respond_with_error(werr).await
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! # Validation of module IDs
+//!
+//! Modules get a random UUID when they are created with `POST /`, or the ID chosen by
+//! the client when they are created with `PUT /modules/{id}`. Either way an ID is a slug:
+//!
+//! - 1 to [`MAX_ID_LEN`] characters long.
+//! - Only lowercase ASCII letters, digits, `-` and `_`.
+//! - Starting with a letter or a digit.
+//! - None of the [`RESERVED_IDS`], which would clash with other routes.
+
+use crate::errors::{ErrorCode, WessError};
+
+/// The maximum length of a module ID.
+pub const MAX_ID_LEN: usize = 64;
+
+/// IDs that are taken by other routes.
+pub const RESERVED_IDS: [&str; 5] = ["count", "jobs", "metrics", "modules", "v1"];
+
+/// # Validates a module ID.
+///
+/// ## Arguments
+///
+/// * `id` - The ID taken from the request path.
+///
+/// ## Returns
+///
+/// * A `Result` object with the ID, or a `WessError` object with status 400 if it isn't valid.
+pub fn validate_id(id: &str) -> Result<String, WessError> {
+    let starts_alphanumeric = id
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+    let valid_chars = id
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+
+    if !starts_alphanumeric || !valid_chars || id.len() > MAX_ID_LEN {
+        return Err(log_error!(
+            format!(
+                "Invalid ID: '{}' must be 1 to {} characters of a-z, 0-9, '-' or '_'",
+                id, MAX_ID_LEN
+            ),
+            400
+        )
+        .code(ErrorCode::InvalidId));
+    }
+    if RESERVED_IDS.contains(&id) {
+        return Err(
+            log_error!(format!("Invalid ID: '{}' is reserved", id), 400).code(ErrorCode::InvalidId)
+        );
+    }
+
+    Ok(id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_ids() {
+        assert!(validate_id("sum").is_ok());
+        assert!(validate_id("sum-i32_v2").is_ok());
+        assert!(validate_id("b38ed019-45be-42c1-9bd8-2b781b91d082").is_ok());
+    }
+
+    #[test]
+    fn test_invalid_ids() {
+        assert!(validate_id("").is_err());
+        assert!(validate_id("-sum").is_err());
+        assert!(validate_id("Sum").is_err());
+        assert!(validate_id("sum/i32").is_err());
+        assert!(validate_id(&"a".repeat(MAX_ID_LEN + 1)).is_err());
+        assert!(validate_id("metrics").is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{
+    audit::{AuditEntry, Operation},
+    auth::keys::{ApiKey, NewKey},
+    errors::{ErrorCode, WessError},
+    server::{
+        response::{respond, respond_with_error},
+        routes::middleware::auth::{caller, require_admin},
+        AppState,
+    },
+};
+use serde_json::json;
+use tide::{Error, Request, Response, StatusCode};
+
+/// # Handler function for creating an API key.
+///
+/// ## Arguments
+///
+/// * `req` - The [`Request`] object with a [`NewKey`] as JSON.
+///
+/// ## Returns
+///
+/// A [`Result`] containing the [`Response`] object with the key and its token,
+/// the only time the token is sent.
+pub async fn create_key(mut req: Request<AppState>) -> Result<Response, Error> {
+    let r = new_key(&mut req).await;
+    let id = r.as_ref().ok().map(|(key, _)| key.id.as_str());
+    audit(&req, Operation::CreateKey, id, &r);
+
+    match r {
+        Ok((key, token)) => {
+            let mut body = json!(key);
+            body["token"] = json!(token);
+            respond(body, StatusCode::Created).await
+        }
+        Err(e) => respond_with_error(e).await,
+    }
+}
+
+/// # Handler function for listing the API keys, without their tokens.
+///
+/// Admins with a tenant only list the keys of their tenant.
+pub async fn list_keys(req: Request<AppState>) -> Result<Response, Error> {
+    match require_admin(&req)
+        .and_then(|identity| req.state().auth.keys.list(identity.tenant.as_deref()))
+    {
+        Ok(keys) => respond(keys, StatusCode::Ok).await,
+        Err(e) => respond_with_error(e).await,
+    }
+}
+
+/// # Handler function for revoking an API key.
+///
+/// Admins with a tenant only revoke the keys of their tenant.
+pub async fn revoke_key(req: Request<AppState>) -> Result<Response, Error> {
+    let id = req.param("key").unwrap_or_default();
+
+    let r = require_admin(&req)
+        .and_then(|identity| req.state().auth.keys.revoke(id, identity.tenant.as_deref()));
+    audit(&req, Operation::RevokeKey, Some(id), &r);
+
+    match r {
+        Ok(_) => Ok(Response::new(StatusCode::NoContent)),
+        Err(e) => respond_with_error(e).await,
+    }
+}
+
+/// # Checks the admin rights of the caller and creates the key of the request.
+///
+/// Admins with a tenant only create keys of their tenant, the keys they create
+/// without one get it.
+async fn new_key(req: &mut Request<AppState>) -> Result<(ApiKey, String), WessError> {
+    let identity = require_admin(req)?;
+
+    let mut new_key = req.body_json::<NewKey>().await.map_err(|e| {
+        log_error!(format!("Invalid Json: {}", e), 400).code(ErrorCode::InvalidBody)
+    })?;
+    if let Some(tenant) = identity.tenant {
+        match &new_key.tenant {
+            Some(other) if *other != tenant => {
+                return Err(log_error!(
+                    format!("Forbidden: {} can't create keys of {}", identity.id, other),
+                    403
+                )
+                .code(ErrorCode::Forbidden))
+            }
+            _ => new_key.tenant = Some(tenant),
+        }
+    }
+    req.state().auth.keys.create(new_key)
+}
+
+/// # Records a key operation in the audit trail, with its outcome.
+fn audit<T>(
+    req: &Request<AppState>,
+    operation: Operation,
+    key: Option<&str>,
+    r: &Result<T, WessError>,
+) {
+    if let Ok(identity) = caller(req) {
+        let entry = AuditEntry::new(&identity, operation, None).key(key);
+        req.state().audit.record(entry.outcome(r));
+    }
+}
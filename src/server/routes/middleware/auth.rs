@@ -0,0 +1,112 @@
+use crate::{
+    auth::Identity,
+    config::CONFIG,
+    errors::{ErrorCode, WessError},
+    metrics::constants::TENANT_REQUESTS_TOTAL,
+    server::{response::respond_with_error, tls::ClientCert, AppState},
+};
+use tide::{Middleware, Next, Request, Result};
+
+/// The paths served without an API key.
+pub const PUBLIC_PATHS: [&str; 2] = ["/metrics", "/v1/health"];
+
+/// # Authenticates requests with the `Authorization: Bearer` API key or JSON Web Token they carry.
+///
+/// The [`Identity`] of the caller is attached to the request, handlers read it
+/// with `req.ext::<Identity>()`. Requests without a bearer token are made by the
+/// client certificate of the connection when it is listed in `[server.tls.clients]`,
+/// the other requests without a valid key are answered with 401.
+/// Authenticated requests are counted per tenant.
+pub struct AuthMiddleware;
+
+#[tide::utils::async_trait]
+impl Middleware<AppState> for AuthMiddleware {
+    async fn handle(&self, req: Request<AppState>, next: Next<'_, AppState>) -> Result {
+        if PUBLIC_PATHS.contains(&req.url().path()) {
+            return Ok(next.run(req).await);
+        }
+        if !CONFIG.auth.enabled {
+            return Ok(next.run(authenticated(req, Identity::anonymous())).await);
+        }
+
+        let identity = match req
+            .header("Authorization")
+            .and_then(|values| values.as_str().strip_prefix("Bearer "))
+        {
+            Some(token) => req.state().auth.verify(token.trim()),
+            None => req
+                .ext::<ClientCert>()
+                .zip(CONFIG.server.tls.as_ref())
+                .and_then(|(cert, tls)| cert.identity(tls))
+                .ok_or_else(|| {
+                    log_error!("Missing API key".to_string(), 401).code(ErrorCode::Unauthorized)
+                }),
+        };
+
+        match identity {
+            Ok(identity) => Ok(next.run(authenticated(req, identity)).await),
+            Err(werr) => {
+                let mut response = respond_with_error(werr).await?;
+                response.insert_header("WWW-Authenticate", "Bearer");
+                Ok(response)
+            }
+        }
+    }
+}
+
+/// # Attaches the caller to a request.
+fn authenticated(mut req: Request<AppState>, identity: Identity) -> Request<AppState> {
+    TENANT_REQUESTS_TOTAL
+        .with_label_values(&[identity.tenant_name()])
+        .inc();
+    req.set_ext(identity);
+    req
+}
+
+/// # Gets the caller the [`AuthMiddleware`] attached to a request.
+///
+/// ## Returns
+///
+/// * A `Result` object with the [`Identity`] of the caller, or a `WessError` object
+///   with status 401 if the request went around the middleware.
+pub fn caller<State>(req: &Request<State>) -> std::result::Result<Identity, WessError> {
+    req.ext::<Identity>()
+        .cloned()
+        .ok_or_else(|| log_error!("Unauthenticated".to_string(), 401).code(ErrorCode::Unauthorized))
+}
+
+/// # Checks that the caller of a request is an admin.
+///
+/// Admins with a tenant only administer the keys and the audit trail of their tenant,
+/// the handlers scope their requests with the returned [`Identity`].
+///
+/// ## Returns
+///
+/// * A `Result` object with the [`Identity`] of the admin, or a `WessError` object with
+///   status 401 if the request went around the middleware, or 403 if the caller isn't
+///   an admin.
+pub fn require_admin<State>(req: &Request<State>) -> std::result::Result<Identity, WessError> {
+    match caller(req)? {
+        identity if identity.is_admin() => Ok(identity),
+        _ => Err(log_error!("Admin rights required".to_string(), 403).code(ErrorCode::Forbidden)),
+    }
+}
+
+/// # Checks that the caller of a request is an admin of every tenant.
+///
+/// These admins are the root key, the anonymous caller when authentication is
+/// disabled, and the admin keys and tokens without a tenant.
+///
+/// ## Returns
+///
+/// * A `Result` object, or a `WessError` object with status 401 if the request went
+///   around the middleware, or 403 if the caller isn't an admin of every tenant.
+pub fn require_global_admin<State>(req: &Request<State>) -> std::result::Result<(), WessError> {
+    match require_admin(req)? {
+        identity if identity.tenant.is_none() => Ok(()),
+        _ => Err(
+            log_error!("Admin rights over every tenant required".to_string(), 403)
+                .code(ErrorCode::Forbidden),
+        ),
+    }
+}
@@ -1,7 +1,9 @@
+pub mod auth;
+
 use std::time::Instant;
 
 use crate::metrics::constants::{
-    CONCURRENT_CONNECTIONS, HTTP_REQUESTS_TOTAL, HTTP_REQUEST_LATENCY,
+    CONCURRENT_CONNECTIONS, HTTP_REQUESTS_TOTAL, HTTP_REQUEST_LATENCY, HTTP_RESPONSES_TOTAL,
 };
 use tide::{Middleware, Next, Request, Result};
 
@@ -21,6 +23,10 @@ impl<State: Clone + Send + Sync + 'static> Middleware<State> for RequestMetricsM
 
         CONCURRENT_CONNECTIONS.dec();
         HTTP_REQUEST_LATENCY.observe(duration.as_secs_f64());
+        // Counts shed (503) and rate limited (429) requests along with the rest
+        HTTP_RESPONSES_TOTAL
+            .with_label_values(&[&(response.status() as u16).to_string()])
+            .inc();
 
         Ok(response)
     }
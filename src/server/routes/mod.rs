@@ -9,6 +9,10 @@
 //! * [`write_op`] - handles write operations (create, update, delete) on a WebAssembly function.
 //! * [`read_op`] - handles read operations (get) on a WebAssembly function.
 //! * [`run_op`] - handles requests to run a WebAssembly function.
+//! * [`ids`] - validates the IDs of WebAssembly functions taken from the request path.
+//! * [`key_ops`] - handles the admin requests creating, listing and revoking API keys.
+//! * [`audit_ops`] - handles the admin requests reading the audit trail.
+//! * [`health`] - answers health checks, ahead of the run requests queued for the workers.
 //!
 //! All routes take a [`Request`] object that provides access to the HTTP request data and a
 //! [`AppState`] object that contains the application state (i.e., the channels used to communicate
@@ -24,6 +28,10 @@
 //! The [`run_op`] function is used to run a WebAssembly function. It extracts the request data and
 //! passes it to the [`send_to_runner`] function to be sent to the runner thread.
 
+pub mod audit_ops;
+pub mod health;
+pub mod ids;
+pub mod key_ops;
 pub mod metrics;
 pub mod middleware;
 pub mod read_ops;
@@ -1,20 +1,34 @@
 use std::convert::TryInto;
 
 use crate::{
-    errors::WessError,
+    auth::{
+        acl::{authorize, Permission},
+        Identity,
+    },
+    database::schema::module_key,
+    errors::{ErrorCode, WessError},
     metrics::constants::READER_CHANNEL_QUEUE,
     server::{
         response::{respond, respond_with_error},
+        routes::{ids, middleware::auth::caller},
         AppState,
     },
-    workers::reader::models::{ReadJob, ReadResponse},
+    workers::{
+        queue::{Priority, Queue},
+        reader::models::{ListQuery, ReadJob, ReadOps, ReadResponse},
+    },
 };
-use tide::{Error, Request, Response};
-use tokio::sync::{mpsc::Sender, oneshot};
-use uuid::Uuid;
+use tide::{
+    http::{mime, Body},
+    Error, Request, Response, StatusCode,
+};
+use tokio::sync::oneshot;
 
 /// # Handler function for read operations.
 ///
+/// Reading a module needs the permission to run it, see [`authorize`]. Only the
+/// modules of the tenant of the caller can be read and counted.
+///
 /// ## Arguments
 ///
 /// * `req` - The [`Request`] object containing the read operation to perform.
@@ -23,12 +37,12 @@ use uuid::Uuid;
 ///
 /// A [`Result`] containing the [`Response`] object.
 pub async fn make_read_op(req: Request<AppState>) -> Result<Response, Error> {
-    match validate_id(&req) {
-        Ok(input) => {
-            let reader_tx: Sender<ReadJob> = req.state().reader_tx.clone();
+    match validate_id(&req).and_then(|input| Ok((input, caller(&req)?))) {
+        Ok((input, identity)) => {
+            let reader_tx: Queue<ReadJob> = req.state().reader_tx.clone();
             match input {
-                Some(id) => send_to_reader(id, reader_tx).await,
-                None => get_all(reader_tx).await,
+                Some(id) => send_to_reader(id, reader_tx, &identity).await,
+                None => get_all(reader_tx, &identity).await,
             }
         }
         Err(e) => respond_with_error(e).await,
@@ -37,36 +51,60 @@ pub async fn make_read_op(req: Request<AppState>) -> Result<Response, Error> {
 
 fn validate_id(req: &Request<AppState>) -> Result<Option<String>, WessError> {
     match req.param("id") {
-        Ok(input) => match Uuid::parse_str(input) {
-            Ok(id) => Ok(Some(id.to_string())),
-            Err(e) => {
-                let werr = log_error!(format!("Invalid ID: {}", e.to_string()), 400);
-                Err(werr)
-            }
-        },
+        Ok(input) => ids::validate_id(input).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+/// # Handler function for listing modules.
+///
+/// Reads the cursor, page size and filters from the query string,
+/// e.g. `GET /modules?limit=20&label=math&cursor=<id>`. Only the modules of the
+/// tenant of the caller that the caller may run are listed.
+///
+/// ## Arguments
+///
+/// * `req` - The [`Request`] object containing the list query.
+///
+/// ## Returns
+///
+/// A [`Result`] containing the [`Response`] object with a page of module summaries.
+pub async fn list_modules(req: Request<AppState>) -> Result<Response, Error> {
+    let identity = match caller(&req) {
+        Ok(identity) => identity,
+        Err(e) => return respond_with_error(e).await,
+    };
+
+    match req.query::<ListQuery>() {
+        Ok(query) => {
+            let reader_tx: Queue<ReadJob> = req.state().reader_tx.clone();
+            let read_op = ReadOps::List(identity, query);
+            request_reader(read_op, reader_tx, None).await
+        }
         Err(e) => {
-            eprint!("hmmm");
-            let werr = log_error!(e.to_string(), 400);
-            Err(werr)
+            let werr = log_error!(format!("Invalid Query: {}", e), 400);
+            respond_with_error(werr).await
         }
     }
 }
 
-async fn get_all(reader_tx: Sender<ReadJob>) -> Result<Response, Error> {
+async fn get_all(reader_tx: Queue<ReadJob>, identity: &Identity) -> Result<Response, Error> {
     let (tx, rx) = oneshot::channel::<ReadResponse>();
-    let read_job = ReadJob { id: None, tx };
+    let read_job = ReadJob::new(tx, ReadOps::Count(identity.tenant_name().to_string()));
 
-    reader_tx.send(read_job).await.unwrap();
+    if let Err(e) = reader_tx.send(read_job, Priority::High).await {
+        return respond_with_error(e).await;
+    }
     READER_CHANNEL_QUEUE.set(reader_tx.capacity().try_into().unwrap());
 
     match rx.await {
         Ok(response) => match response {
-            ReadResponse::Module(_) => todo!(),
             ReadResponse::Size(r) => respond(r, tide::StatusCode::Accepted).await,
             ReadResponse::Fail(e) => {
                 let werr = log_error!(e.to_string(), 500);
                 respond_with_error(werr).await
             }
+            _ => unreachable!(),
         },
         Err(e) => {
             let werr = log_error!(e.to_string(), 500);
@@ -75,32 +113,132 @@ async fn get_all(reader_tx: Sender<ReadJob>) -> Result<Response, Error> {
     }
 }
 
+/// # Handler function for downloading the raw bytecode of a module.
+///
+/// Responds with `application/wasm` and the SHA-256 of the bytecode as `ETag`,
+/// or with `304 Not Modified` when it matches `If-None-Match`.
+///
+/// ## Arguments
+///
+/// * `req` - The [`Request`] object containing the ID of the module.
+///
+/// ## Returns
+///
+/// A [`Result`] containing the [`Response`] object with the bytecode.
+pub async fn get_wasm(req: Request<AppState>) -> Result<Response, Error> {
+    let id = match validate_id(&req) {
+        Ok(Some(id)) => id,
+        Ok(None) => {
+            return respond_with_error(
+                log_error!("Missing ID".to_string(), 400).code(ErrorCode::InvalidId),
+            )
+            .await
+        }
+        Err(e) => return respond_with_error(e).await,
+    };
+    let identity = match caller(&req) {
+        Ok(identity) => identity,
+        Err(e) => return respond_with_error(e).await,
+    };
+    let (tx, rx) = oneshot::channel::<ReadResponse>();
+    let reader_tx = req.state().reader_tx.clone();
+
+    if let Err(e) = reader_tx
+        .send(
+            ReadJob::new(tx, ReadOps::Get(module_key(identity.tenant_name(), &id))),
+            Priority::High,
+        )
+        .await
+    {
+        return respond_with_error(e).await;
+    }
+    READER_CHANNEL_QUEUE.set(reader_tx.capacity() as i64);
+
+    match rx.await {
+        Ok(ReadResponse::Module(wm)) => {
+            if let Err(e) = authorize(&identity, &wm.metadata, Permission::Run) {
+                return respond_with_error(e).await;
+            }
+            let etag = format!("\"{}\"", sha256::digest(wm.to_binary()));
+            let fresh = req.header("If-None-Match").is_some_and(|values| {
+                values
+                    .iter()
+                    .flat_map(|v| v.as_str().split(','))
+                    .any(|tag| tag.trim() == etag || tag.trim() == "*")
+            });
+
+            if fresh {
+                return Ok(Response::builder(StatusCode::NotModified)
+                    .header("ETag", etag)
+                    .build());
+            }
+            Ok(Response::builder(StatusCode::Ok)
+                .content_type(mime::WASM)
+                .header("ETag", etag)
+                .body(Body::from_bytes(wm.wasm))
+                .build())
+        }
+        Ok(ReadResponse::Fail(e)) => respond_with_error(e).await,
+        Ok(_) => unreachable!(),
+        Err(e) => {
+            let werr = log_error!(e.to_string(), 500);
+            respond_with_error(werr).await
+        }
+    }
+}
+
 /// # Sends a message to the reader worker to read a WebAssembly function.
 ///
 /// ## Arguments
 ///
 /// * `id`: A [`String`] containing the ID of the WebAssembly function to read.
-/// * `reader_tx`: A [`Sender`] of [`ReadJob`] messages to send the job to the Reader worker.
+/// * `reader_tx`: A [`Queue`] of [`ReadJob`] messages to send the job to the Reader worker.
+/// * `identity`: The caller of the request, who must be allowed to run the function
+///   and only reads the functions of its tenant.
 ///
 /// ## Returns
 ///
 /// * A [`Result`] containing the `Response` with the result of the read operation
 /// or an [`Error`] if the operation failed.
-pub async fn send_to_reader(id: String, reader_tx: Sender<ReadJob>) -> Result<Response, Error> {
+pub async fn send_to_reader(
+    id: String,
+    reader_tx: Queue<ReadJob>,
+    identity: &Identity,
+) -> Result<Response, Error> {
+    let key = module_key(identity.tenant_name(), &id);
+    request_reader(ReadOps::Get(key), reader_tx, Some(identity)).await
+}
+
+async fn request_reader(
+    read_op: ReadOps,
+    reader_tx: Queue<ReadJob>,
+    identity: Option<&Identity>,
+) -> Result<Response, Error> {
     let (tx, rx) = oneshot::channel::<ReadResponse>();
-    let job = ReadJob { id: Some(id), tx };
+    let job = ReadJob::new(tx, read_op);
 
-    reader_tx.send(job).await.unwrap();
+    if let Err(e) = reader_tx.send(job, Priority::High).await {
+        return respond_with_error(e).await;
+    }
     READER_CHANNEL_QUEUE.set(reader_tx.capacity() as i64);
 
     match rx.await {
         Ok(response) => match response {
-            ReadResponse::Module(wm) => respond(wm, tide::StatusCode::Accepted).await,
-            ReadResponse::Size(r) => respond(r, tide::StatusCode::Accepted).await,
-            ReadResponse::Fail(e) => {
-                let werr = log_error!(e.to_string(), e.status.into());
-                respond_with_error(werr).await
+            ReadResponse::Module(wm) => {
+                if let Some(Err(e)) =
+                    identity.map(|identity| authorize(identity, &wm.metadata, Permission::Run))
+                {
+                    return respond_with_error(e).await;
+                }
+                let etag = wm.etag();
+                let mut response = respond(wm, tide::StatusCode::Accepted).await?;
+                response.insert_header("ETag", etag);
+                Ok(response)
             }
+            ReadResponse::Size(r) => respond(r, tide::StatusCode::Accepted).await,
+            ReadResponse::Page(page) => respond(page, tide::StatusCode::Ok).await,
+            ReadResponse::Pong => Ok(Response::new(StatusCode::NoContent)),
+            ReadResponse::Fail(e) => respond_with_error(e).await,
         },
         Err(e) => {
             let werr = log_error!(e.to_string(), 500);
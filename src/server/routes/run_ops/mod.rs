@@ -2,40 +2,79 @@ use self::utils::{
     deserialize_request, get_id_from_request, retrieve_wasm_module, send_to_runner,
     serialize_wasm_return,
 };
-use crate::server::{
-    response::{respond, respond_with_error},
-    AppState,
+use crate::{
+    audit::{AuditEntry, Operation},
+    auth::{
+        acl::{authorize, Permission},
+        signing::check_signed,
+        Identity,
+    },
+    config::CONFIG,
+    database::schema::module_key,
+    errors::WessError,
+    server::{
+        response::{respond, respond_with_error},
+        routes::middleware::auth::caller,
+        AppState,
+    },
 };
+use serde_json::Value;
 use tide::{Error, Request, Response};
 
 mod utils;
 
+/// # Handler function for running the function of a module.
+///
+/// Every run is recorded in the audit trail with the revision and hash of the module
+/// and its outcome, including the runs refused by the ACL, the rate limits, a busy
+/// Runner or bad arguments.
+///
+/// ## Arguments
+///
+/// * `req` - The [`Request`] object with the arguments of the function.
+///
+/// ## Returns
+///
+/// A [`Result`] containing the [`Response`] object with the results of the function.
 pub async fn make_run_op(mut req: Request<AppState>) -> Result<Response, Error> {
-    let id = match get_id_from_request(&req) {
-        Ok(id) => id,
+    let identity = match caller(&req) {
+        Ok(identity) => identity,
         Err(e) => return respond_with_error(e).await,
     };
 
-    let wasm_module = match retrieve_wasm_module(&id, &req).await {
-        Ok(wm) => wm,
-        Err(e) => return respond_with_error(e).await,
-    };
+    let id = get_id_from_request(&req).ok();
+    let mut entry = AuditEntry::new(&identity, Operation::Run, id.as_deref());
+    let r = run(&mut req, &identity, &mut entry).await;
+    req.state().audit.record(entry.outcome(&r));
 
-    let request_args = match deserialize_request(&wasm_module, &mut req).await {
-        Ok(args) => args,
-        Err(e) => return respond_with_error(e).await,
-    };
+    match r {
+        Ok(response) => respond(response, tide::StatusCode::Ok).await,
+        Err(e) => respond_with_error(e).await,
+    }
+}
 
-    let result = match send_to_runner(id.clone(), request_args, req.state().runner_tx.clone()).await
-    {
-        Ok(r) => r,
-        Err(e) => return respond_with_error(e).await,
-    };
+/// # Runs the function of the module of a request, once the caller may run it.
+///
+/// ## Returns
+///
+/// * A `Result` object with the results of the function, or a `WessError` object if
+///   the run is refused or fails.
+async fn run(
+    req: &mut Request<AppState>,
+    identity: &Identity,
+    entry: &mut AuditEntry,
+) -> Result<Vec<Value>, WessError> {
+    let id = get_id_from_request(req)?;
+    let key = module_key(identity.tenant_name(), &id);
 
-    let response = match serialize_wasm_return(result, &wasm_module.metadata.return_type).await {
-        Ok(r) => r,
-        Err(e) => return respond_with_error(e).await,
-    };
+    let wasm_module = retrieve_wasm_module(&key, req).await?;
+    entry.stored(&wasm_module);
+
+    authorize(identity, &wasm_module.metadata, Permission::Run)
+        .and_then(|_| check_signed(&CONFIG.signing, &wasm_module))?;
+    req.state().limits.check_run(identity, &key)?;
 
-    respond(response, tide::StatusCode::Ok).await
+    let request_args = deserialize_request(&wasm_module, req).await?;
+    let result = send_to_runner(key, request_args, req.state().runner_tx.clone()).await?;
+    serialize_wasm_return(result, &wasm_module.metadata.return_type).await
 }
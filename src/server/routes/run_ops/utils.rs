@@ -1,13 +1,16 @@
 use crate::{
+    config::CONFIG,
     database::models::WasmModule,
-    errors::WessError,
+    errors::{ErrorCode, ErrorDetails, WessError},
     metrics::constants::RUNNER_CHANNEL_QUEUE,
-    server::AppState,
+    server::{routes::ids::validate_id, AppState},
     workers::{
-        reader::models::{ReadJob, ReadResponse},
+        queue::{enqueue, Priority},
+        reader::models::{ReadJob, ReadOps, ReadResponse},
         runner::models::{RunJob, RunResponse},
     },
 };
+use std::time::Instant;
 use tide::Request;
 use tokio::sync::{mpsc::Sender, oneshot};
 
@@ -40,16 +43,20 @@ pub async fn retrieve_wasm_module(
     let reader_tx = req.state().reader_tx.clone();
     let (done_tx, done_rx) = oneshot::channel::<ReadResponse>();
 
+    // Run lookups go last, so a flood of runs never delays the other reads
     reader_tx
-        .send(ReadJob::new(done_tx, Some(id.clone())))
-        .await
-        .unwrap();
+        .send(
+            ReadJob::new(done_tx, ReadOps::Get(id.clone())),
+            Priority::Low,
+        )
+        .await?;
 
     match done_rx.await {
         Ok(response) => match response {
-            ReadResponse::Module(wm) => Ok(wm),
+            ReadResponse::Module(wm) => Ok(*wm),
             ReadResponse::Fail(e) => {
-                let werr = log_error!(format!("Invalid Id: {}", e.to_string()), 404);
+                let werr = log_error!(format!("Invalid Id: {}", e.to_string()), 404)
+                    .code(ErrorCode::ModuleNotFound);
                 Err(werr)
             }
             _ => unreachable!(),
@@ -70,7 +77,17 @@ pub async fn deserialize_request(
     let dynamic_args: Result<Vec<wasmer::Value>, WessError> = arg_types
         .iter()
         .zip(arg_values.iter())
-        .map(|(arg_type, arg_value)| map_json_value_to_wasmer_value(arg_type, arg_value))
+        .enumerate()
+        .map(|(index, (arg_type, arg_value))| {
+            map_json_value_to_wasmer_value(arg_type, arg_value).map_err(|e| {
+                e.details(ErrorDetails {
+                    arg_index: Some(index),
+                    expected: Some(arg_type.to_string()),
+                    got: Some(json_type(arg_value).to_string()),
+                    ..Default::default()
+                })
+            })
+        })
         .collect();
 
     dynamic_args
@@ -82,21 +99,15 @@ pub async fn send_to_runner(
     runner_tx: Sender<RunJob>,
 ) -> Result<Box<[wasmer::Value]>, WessError> {
     let (done_tx, done_rx) = oneshot::channel::<RunResponse>();
-    let run_job = RunJob::new(done_tx, args, id);
+    let deadline = Instant::now() + CONFIG.runner.queue_timeout;
+    let run_job = RunJob::new(done_tx, args, id).deadline(deadline);
 
-    runner_tx
-        .send(run_job)
-        .await
-        .map_err(|e| log_error!(e.to_string(), 500))
-        .unwrap();
+    enqueue(&runner_tx, run_job, deadline, "runner").await?;
     RUNNER_CHANNEL_QUEUE.set(runner_tx.capacity() as i64);
 
     match done_rx.await {
         Ok(RunResponse::Success(r)) => Ok(r),
-        Ok(RunResponse::Fail(f)) => {
-            let werr = log_error!(f.to_string(), 500);
-            Err(werr)
-        }
+        Ok(RunResponse::Fail(f)) => Err(f),
         Err(e) => {
             let werr = log_error!(format!("Channel Error: {}", e.to_string()), 500);
             Err(werr)
@@ -106,18 +117,17 @@ pub async fn send_to_runner(
 
 pub fn get_id_from_request(req: &Request<AppState>) -> Result<String, WessError> {
     req.param("id")
-        .map(|id| id.to_string())
-        .map_err(|e| log_error!(e.to_string(), 400))
+        .map_err(|e| log_error!(e.to_string(), 400).code(ErrorCode::InvalidId))
+        .and_then(validate_id)
 }
 
 async fn parse_request_args(
     wasm_module: &WasmModule,
     req: &mut Request<AppState>,
 ) -> Result<(Vec<wasmer::Type>, Vec<serde_json::Value>), WessError> {
-    let args: Vec<Option<serde_json::Value>> = req
-        .body_json()
-        .await
-        .map_err(|e| log_error!(format!("Invalid Json: {}", e.to_string()), 400))?;
+    let args: Vec<Option<serde_json::Value>> = req.body_json().await.map_err(|e| {
+        log_error!(format!("Invalid Json: {}", e.to_string()), 400).code(ErrorCode::InvalidBody)
+    })?;
 
     let arg_types = wasm_module
         .metadata
@@ -136,7 +146,13 @@ async fn parse_request_args(
                 arg_values.len()
             ),
             400
-        );
+        )
+        .code(ErrorCode::ArgCountMismatch)
+        .details(ErrorDetails {
+            expected: Some(arg_types.len().to_string()),
+            got: Some(arg_values.len().to_string()),
+            ..Default::default()
+        });
         return Err(werr);
     }
 
@@ -169,23 +185,39 @@ fn map_json_value_to_wasmer_value(
         wasmer::Type::I32 => arg_value
             .as_i64()
             .map(|i| wasmer::Value::I32(i as i32))
-            .ok_or_else(|| log_error!(format!("Invalid Type: I32"), 400)),
+            .ok_or_else(|| invalid_type(arg_type)),
 
         wasmer::Type::I64 => arg_value
             .as_i64()
             .map(|i| wasmer::Value::I64(i))
-            .ok_or_else(|| log_error!(format!("Invalid Type: I64"), 400)),
+            .ok_or_else(|| invalid_type(arg_type)),
 
         wasmer::Type::F32 => arg_value
             .as_f64()
             .map(|f| wasmer::Value::F32(f as f32))
-            .ok_or_else(|| log_error!(format!("Invalid Type: F32"), 400)),
+            .ok_or_else(|| invalid_type(arg_type)),
 
         wasmer::Type::F64 => arg_value
             .as_f64()
             .map(|f| wasmer::Value::F64(f))
-            .ok_or_else(|| log_error!(format!("Invalid Type: F64"), 400)),
+            .ok_or_else(|| invalid_type(arg_type)),
+
+        _ => Err(invalid_type(arg_type)),
+    }
+}
+
+fn invalid_type(arg_type: &wasmer::Type) -> WessError {
+    log_error!(format!("Invalid Type: {}", arg_type), 400).code(ErrorCode::ArgTypeMismatch)
+}
 
-        _ => Err(log_error!(format!("Invalid Type: {}", *arg_type), 400)),
+fn json_type(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
     }
 }
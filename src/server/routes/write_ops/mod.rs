@@ -1,26 +1,76 @@
-use std::convert::TryInto;
+use self::utils::{compile, from_multipart, from_wasm, from_wat, METADATA_HEADER};
+use std::{convert::TryInto, time::Instant};
 
 use crate::{
-    database::models::WasmModule,
-    errors::WessError,
+    audit::{AuditEntry, Operation},
+    auth::{acl::authorize_create, signing, Identity},
+    config::CONFIG,
+    database::{
+        crypto::cipher,
+        models::{MetadataPatch, WasmModule},
+    },
+    errors::{ErrorCode, WessError},
     metrics::constants::WRITER_CHANNEL_QUEUE,
     server::{
         response::{respond, respond_with_error},
+        routes::{
+            ids::validate_id,
+            middleware::auth::{caller, require_global_admin},
+        },
         AppState,
     },
     workers::{
-        reader::models::{ReadJob, ReadResponse},
-        writer::models::{WriteJob, WriteOps},
+        queue::enqueue,
+        writer::{
+            models::{WriteJob, WriteOps, WriteResponse},
+            status::WriteStatus,
+        },
     },
 };
 use async_std::task;
+use serde::Deserialize;
 use tide::{Error, Request, Response};
-use tokio::sync::{mpsc::Sender, oneshot};
+use tokio::sync::oneshot;
 use uuid::Uuid;
 use wasmer::{Engine, Module};
 
+mod utils;
+
+/// The ID the re-encrypt job reports in its [`WriteStatus`].
+const REENCRYPT_ID: &str = "reencrypt";
+
+/// The header identifying who uploads a module, stored as [`WasmMetadata::uploader`].
+///
+/// It is only read when `auth.enabled` is off, authenticated uploads are stamped with
+/// the name of their caller instead.
+///
+/// [`WasmMetadata::uploader`]: crate::database::models::WasmMetadata::uploader
+const UPLOADER_HEADER: &str = "X-Wess-Uploader";
+
+/// The header holding the Ed25519 signature of the bytecode, base64 encoded.
+pub const SIGNATURE_HEADER: &str = "X-Wess-Signature";
+
+/// The header holding the ID of the trusted key the bytecode was signed with.
+pub const SIGNER_HEADER: &str = "X-Wess-Signer";
+
+/// The query string of a write request.
+#[derive(Deserialize)]
+struct WriteQuery {
+    /// Whether to wait for the Writer to commit the job, `true` by default.
+    wait: Option<bool>,
+}
+
 /// # Handler function for write operations.
 ///
+/// Creating a module needs the admin or publisher role. The Writer checks that the
+/// caller may manage the module any other write applies to.
+///
+/// Re-encrypting the stored records, those of every tenant, needs an admin without a
+/// tenant and `[database.encryption]`.
+/// It never waits for the Writer, the response holds the job to poll.
+///
+/// Every request is recorded in the audit trail, by the Writer when it runs the job.
+///
 /// ## Arguments
 ///
 /// * `req` - The [`Request`] object containing the write operation to perform.
@@ -33,129 +83,321 @@ pub async fn make_write_op(
     mut req: Request<AppState>,
     write_ops: WriteOps,
 ) -> Result<Response, Error> {
-    let reader_tx = req.state().reader_tx.clone();
-    match write_ops {
-        WriteOps::Create => {
-            let id: Uuid = Uuid::new_v4();
-            match deserialize_request(&mut req).await {
-                Ok(body_request) => {
-                    send_to_writer(
-                        Some(body_request),
-                        id.to_string(),
-                        req.state().writer_tx.clone(),
-                        WriteOps::Create,
-                    )
-                        .await
-                }
-                Err(e) => respond_with_error(e).await,
-            }
+    let identity = match caller(&req) {
+        Ok(identity) => identity,
+        Err(e) => return respond_with_error(e).await,
+    };
+
+    match write_job(&mut req, &write_ops, &identity).await {
+        Ok((write_job, wait)) => {
+            // Without authentication there is no caller, so new modules get no owner
+            let caller = Some(identity).filter(|_| CONFIG.auth.enabled);
+            send_to_writer(write_job.caller(caller), req.state(), wait).await
+        }
+        Err(e) => {
+            // The Writer records the jobs it runs, requests refused before are recorded here
+            let id = get_id(&req).ok();
+            let entry = AuditEntry::new(&identity, Operation::from(&write_ops), id.as_deref());
+            req.state().audit.record(entry.failed(&e));
+            respond_with_error(e).await
         }
-        WriteOps::Update => match verify_id(&req, reader_tx).await {
-            Ok(id) => match deserialize_request(&mut req).await {
-                Ok(write_request) => {
-                    send_to_writer(
-                        Some(write_request),
-                        id,
-                        req.state().writer_tx.clone(),
-                        WriteOps::Update,
-                    )
-                        .await
-                }
-                Err(e) => respond_with_error(e).await,
-            },
-            Err(e) => respond_with_error(e).await,
-        },
-        WriteOps::Delete => match verify_id(&req, reader_tx).await {
-            Ok(id) => {
-                send_to_writer(None, id, req.state().writer_tx.clone(), WriteOps::Delete).await
-            }
-            Err(e) => respond_with_error(e).await,
-        },
     }
 }
 
-async fn send_to_writer(
-    write_request: Option<WasmModule>,
-    id: String,
-    tx: Sender<WriteJob>,
-    write_ops: WriteOps,
-) -> Result<Response, Error> {
-    let write_job = WriteJob::new(write_request, id.clone(), write_ops.clone());
-
-    task::spawn(async move {
-        if let Err(e) = tx.send(write_job).await {
-            log_error!(format!("Channel Error: {}", e.to_string()), 500);
-        }
-        WRITER_CHANNEL_QUEUE.set(tx.capacity().try_into().unwrap());
-    });
-
-    match write_ops {
+/// # Checks a write request and reads the job it sends to the Writer.
+///
+/// ## Returns
+///
+/// * A `Result` object with the job and whether to wait for its outcome, or a
+///   `WessError` object if the request is refused.
+async fn write_job(
+    req: &mut Request<AppState>,
+    write_ops: &WriteOps,
+    identity: &Identity,
+) -> Result<(WriteJob, bool), WessError> {
+    let write_job = match write_ops {
         WriteOps::Create => {
-            respond(
-                serde_json::json!({
-                    "id": id
-                }),
-                tide::StatusCode::Accepted,
-            )
-                .await
+            authorize_create(identity)?;
+            let id: Uuid = Uuid::new_v4();
+            let wasm_module = deserialize_request(req, identity).await?;
+            WriteJob::new(Some(wasm_module), id.to_string(), WriteOps::Create)
+                .idempotency_key(idempotency_key(req))
         }
-        WriteOps::Update => {
-            respond(
-                serde_json::json!({
-                    "id": id
-                }),
-                tide::StatusCode::Accepted,
-            )
-                .await
+        WriteOps::Put | WriteOps::Update => {
+            let id = get_id(req)?;
+            let wasm_module = deserialize_request(req, identity).await?;
+            WriteJob::new(Some(wasm_module), id, write_ops.clone()).if_match(if_match(req))
+        }
+        WriteOps::Patch => {
+            let id = get_id(req)?;
+            WriteJob::patch(id, deserialize_patch(req).await?).if_match(if_match(req))
         }
         WriteOps::Delete => {
-            respond(
-                serde_json::json!({
-                    "id": id
-                }),
-                tide::StatusCode::Accepted,
+            WriteJob::new(None, get_id(req)?, WriteOps::Delete).if_match(if_match(req))
+        }
+        WriteOps::Reencrypt => {
+            require_global_admin(req)?;
+            cipher()?;
+            let write_job = WriteJob::new(None, REENCRYPT_ID.to_string(), WriteOps::Reencrypt);
+            return Ok((write_job, false));
+        }
+    };
+    Ok((write_job, wait(req)))
+}
+
+/// # Handler function for polling the status of a write sent with `?wait=false`.
+///
+/// ## Arguments
+///
+/// * `req` - The [`Request`] object containing the ID of the write job.
+///
+/// ## Returns
+///
+/// A [`Result`] containing the [`Response`] object with the [`WriteStatus`] of the job.
+pub async fn get_write_status(req: Request<AppState>) -> Result<Response, Error> {
+    let job = req.param("job").unwrap_or_default();
+
+    match req.state().write_statuses.get(job) {
+        Some(status) => respond(status, tide::StatusCode::Ok).await,
+        None => {
+            respond_with_error(
+                log_error!(format!("Unknown Job: {}", job), 404).code(ErrorCode::JobNotFound),
             )
-                .await
+            .await
         }
     }
 }
 
-async fn verify_id(
-    req: &Request<AppState>,
-    reader_tx: Sender<ReadJob>,
-) -> Result<String, WessError> {
-    let id = req.param("id").unwrap();
-    let (done_tx, done_rx) = oneshot::channel::<ReadResponse>();
-    let read_job = ReadJob::new(done_tx, Some(id.to_string()));
+/// # Reads the `wait` query parameter, `true` when absent.
+fn wait(req: &Request<AppState>) -> bool {
+    req.query::<WriteQuery>()
+        .ok()
+        .and_then(|query| query.wait)
+        .unwrap_or(true)
+}
+
+/// # Sends a job to the writer worker.
+///
+/// When `wait` is set, responds with the outcome once the Writer commits the job:
+/// `201 Created`, `200 OK` or `204 No Content`. Otherwise responds right away with
+/// `202 Accepted` and the ID of a job whose [`WriteStatus`] can be polled.
+async fn send_to_writer(
+    write_job: WriteJob,
+    state: &AppState,
+    wait: bool,
+) -> Result<Response, Error> {
+    let id = write_job.id.clone();
+    let write_ops = write_job.write_op.clone();
+    let tx = state.writer_tx.clone();
+    let (done_tx, done_rx) = oneshot::channel::<WriteResponse>();
+
+    let deadline = Instant::now() + CONFIG.writer.queue_timeout;
+    if let Err(e) = enqueue(&tx, write_job.with_responder(done_tx), deadline, "writer").await {
+        return respond_with_error(e).await;
+    }
+    WRITER_CHANNEL_QUEUE.set(tx.capacity().try_into().unwrap());
 
-    reader_tx.send(read_job).await.unwrap();
+    if !wait {
+        let job = Uuid::new_v4().to_string();
+        let statuses = state.write_statuses.clone();
+        statuses.set(&job, WriteStatus::Pending { id: id.clone() });
+
+        let tracked = job.clone();
+        let tracked_id = id.clone();
+        task::spawn(async move {
+            let status = match done_rx.await {
+                Ok(WriteResponse::Created(id)) | Ok(WriteResponse::Done(id)) => {
+                    WriteStatus::Done { id }
+                }
+                Ok(WriteResponse::Fail(e)) => WriteStatus::failed(tracked_id, &e),
+                Err(e) => {
+                    let werr = log_error!(format!("Channel Error: {}", e.to_string()), 500);
+                    WriteStatus::failed(tracked_id, &werr)
+                }
+            };
+            statuses.set(&tracked, status);
+        });
+
+        return respond(
+            serde_json::json!({
+                "id": id,
+                "job": job
+            }),
+            tide::StatusCode::Accepted,
+        )
+        .await;
+    }
 
     match done_rx.await {
-        Ok(response) => match response {
-            ReadResponse::Module(_) => Ok(id.to_string()),
-            ReadResponse::Fail(e) => {
-                let werr = log_error!(format!("Invalid Id: {}", e.to_string()), 404);
-                Err(werr)
+        Ok(WriteResponse::Created(id)) => respond_created(id).await,
+        Ok(WriteResponse::Done(id)) => match write_ops {
+            WriteOps::Create | WriteOps::Put | WriteOps::Update | WriteOps::Patch => {
+                respond(
+                    serde_json::json!({
+                        "id": id
+                    }),
+                    tide::StatusCode::Ok,
+                )
+                .await
+            }
+            WriteOps::Delete | WriteOps::Reencrypt => {
+                Ok(Response::new(tide::StatusCode::NoContent))
             }
-            _ => unreachable!(),
         },
+        Ok(WriteResponse::Fail(e)) => respond_with_error(e).await,
         Err(e) => {
             let werr = log_error!(format!("Channel Error: {}", e.to_string()), 500);
-            Err(werr)
+            respond_with_error(werr).await
         }
     }
 }
 
-async fn deserialize_request(req: &mut Request<AppState>) -> Result<WasmModule, WessError> {
-    /* TODO: In update case, WasmModule dont need have all params, just element that will update */
-    req.body_json::<WasmModule>()
-        .await
-        .map_err(|e| log_error!(format!("Inavlid Json: {}", e), 400))
-        .and_then(|wm| match Module::validate(&Engine::default(), &wm.wasm) {
-            Ok(_) => Ok(wm),
+fn get_id(req: &Request<AppState>) -> Result<String, WessError> {
+    req.param("id")
+        .map_err(|e| log_error!(e.to_string(), 400).code(ErrorCode::InvalidId))
+        .and_then(validate_id)
+}
+
+fn idempotency_key(req: &Request<AppState>) -> Option<String> {
+    req.header("Idempotency-Key").map(|h| h.last().to_string())
+}
+
+/// # Reads the `If-Match` header, joining repeated headers into one list.
+fn if_match(req: &Request<AppState>) -> Option<String> {
+    req.header("If-Match").map(|values| {
+        values
+            .iter()
+            .map(|v| v.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    })
+}
+
+async fn deserialize_request(
+    req: &mut Request<AppState>,
+    identity: &Identity,
+) -> Result<WasmModule, WessError> {
+    let uploader = if CONFIG.auth.enabled {
+        Some(identity.name.clone())
+    } else {
+        req.header(UPLOADER_HEADER).map(|h| h.last().to_string())
+    };
+    let signature = req.header(SIGNATURE_HEADER).map(|h| h.last().to_string());
+    let signer = req.header(SIGNER_HEADER).map(|h| h.last().to_string());
+    let content_type = req.content_type();
+
+    let body = match content_type.as_ref().map(|mime| mime.essence()) {
+        Some("application/wasm") => {
+            let metadata = req.header(METADATA_HEADER).map(|h| h.last().to_string());
+            read_body(req)
+                .await
+                .and_then(|wasm| from_wasm(wasm, metadata.as_deref()))
+        }
+        Some("text/wat") => {
+            let metadata = req.header(METADATA_HEADER).map(|h| h.last().to_string());
+            read_body(req)
+                .await
+                .and_then(|wat| from_wat(wat, metadata.as_deref()))
+        }
+        Some("multipart/form-data") => {
+            let boundary = content_type
+                .as_ref()
+                .and_then(|mime| mime.param("boundary"))
+                .map(|boundary| boundary.to_string())
+                .ok_or_else(|| {
+                    log_error!("Missing Multipart Boundary".to_string(), 400)
+                        .code(ErrorCode::InvalidBody)
+                })?;
+            from_multipart(read_body(req).await?, &boundary).await
+        }
+        _ => req.body_json::<WasmModule>().await.map_err(|e| {
+            log_error!(format!("Inavlid Json: {}", e), 400).code(ErrorCode::InvalidBody)
+        }),
+    };
+
+    body.and_then(compile).and_then(
+        |mut wm| match Module::validate(&Engine::default(), &wm.wasm) {
+            Ok(_) => {
+                wm.metadata.uploader = uploader;
+                sign(wm, signature, signer)
+            }
             Err(e) => {
-                let werr = log_error!(format!("Invalid Wasm: {}", e), 400);
+                let werr =
+                    log_error!(format!("Invalid Wasm: {}", e), 400).code(ErrorCode::InvalidWasm);
                 Err(werr)
             }
-        })
+        },
+    )
+}
+
+/// # Verifies the signature sent with an upload and stores it with the module.
+///
+/// A signature set in the uploaded metadata is never trusted, only the one in the
+/// [`SIGNATURE_HEADER`] header, checked against the key named by [`SIGNER_HEADER`].
+fn sign(
+    mut wm: WasmModule,
+    signature: Option<String>,
+    signer: Option<String>,
+) -> Result<WasmModule, WessError> {
+    wm.metadata.signature = vec![];
+    wm.metadata.signer = None;
+
+    match (signature, signer) {
+        (Some(signature), Some(signer)) => {
+            wm.metadata.signature =
+                signing::verify(&CONFIG.signing, &wm.wasm, &signer, &signature)?;
+            wm.metadata.signer = Some(signer);
+            Ok(wm)
+        }
+        (None, None) => Ok(wm),
+        _ => Err(log_error!(
+            format!(
+                "{} and {} must be sent together",
+                SIGNATURE_HEADER, SIGNER_HEADER
+            ),
+            400
+        )
+        .code(ErrorCode::InvalidSignature)),
+    }
+}
+
+async fn read_body(req: &mut Request<AppState>) -> Result<Vec<u8>, WessError> {
+    req.body_bytes()
+        .await
+        .map_err(|e| log_error!(format!("Invalid Body: {}", e), 400).code(ErrorCode::InvalidBody))
+}
+
+async fn deserialize_patch(req: &mut Request<AppState>) -> Result<MetadataPatch, WessError> {
+    req.body_json::<MetadataPatch>()
+        .await
+        .map_err(|e| log_error!(format!("Inavlid Json: {}", e), 400).code(ErrorCode::InvalidBody))
+}
+
+/// # Answers a create with `201 Created` and the `Location` of the new module.
+///
+/// The location is the `/v1` path of the module, in the tenant of the caller.
+async fn respond_created(id: String) -> Result<Response, Error> {
+    let location = format!("/v1/modules/{}", id);
+    let mut response = respond(
+        serde_json::json!({
+            "id": id
+        }),
+        tide::StatusCode::Created,
+    )
+    .await?;
+    response.insert_header("Location", location);
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_respond_created() {
+        let response = respond_created("sum".to_string()).await.unwrap();
+
+        assert_eq!(response.status(), tide::StatusCode::Created);
+        assert_eq!(response["Location"], "/v1/modules/sum");
+    }
 }
@@ -0,0 +1,226 @@
+//! # Parsers for the bodies accepted by write operations.
+//!
+//! A module can be uploaded in one of the following formats, selected by `Content-Type`:
+//!
+//! - `application/json`: A [`WasmModule`] with the bytecode as an array of numbers.
+//! - `application/wasm`: The raw bytecode, with the [`WasmMetadata`] as JSON in the
+//!   `X-Wess-Metadata` header.
+//! - `text/wat`: The WebAssembly text, with the [`WasmMetadata`] as JSON in the
+//!   `X-Wess-Metadata` header.
+//! - `multipart/form-data`: A `wasm` part with the raw bytecode, or a `wat` part with
+//!   the WebAssembly text, and a `metadata` part with the [`WasmMetadata`] as JSON.
+//!
+//! A JSON body can also carry the text in a `wat` field instead of `wasm`. Either way
+//! the text is compiled by [`compile`] and kept next to the bytecode.
+
+use crate::{
+    database::models::{WasmMetadata, WasmModule},
+    errors::{ErrorCode, WessError},
+};
+use multer::Multipart;
+use std::io::Cursor;
+
+/// The header holding the metadata of a raw `application/wasm` upload.
+pub const METADATA_HEADER: &str = "X-Wess-Metadata";
+
+/// # Builds a [`WasmModule`] from a raw `application/wasm` body.
+///
+/// ## Arguments
+///
+/// * `wasm` - The raw bytecode.
+/// * `metadata` - The value of the [`METADATA_HEADER`] header, if present.
+///
+/// ## Returns
+///
+/// * A `Result` object with the [`WasmModule`],
+///   or a `WessError` object if the metadata is missing or invalid.
+pub fn from_wasm(wasm: Vec<u8>, metadata: Option<&str>) -> Result<WasmModule, WessError> {
+    let metadata = metadata.ok_or_else(|| {
+        log_error!(format!("Missing Header: {}", METADATA_HEADER), 400)
+            .code(ErrorCode::InvalidMetadata)
+    })?;
+
+    Ok(WasmModule::new(wasm, parse_metadata(metadata.as_bytes())?))
+}
+
+/// # Builds a [`WasmModule`] from a `text/wat` body.
+///
+/// ## Arguments
+///
+/// * `wat` - The WebAssembly text, compiled later by [`compile`].
+/// * `metadata` - The value of the [`METADATA_HEADER`] header, if present.
+///
+/// ## Returns
+///
+/// * A `Result` object with the [`WasmModule`],
+///   or a `WessError` object if the body isn't UTF-8 or the metadata is missing or invalid.
+pub fn from_wat(wat: Vec<u8>, metadata: Option<&str>) -> Result<WasmModule, WessError> {
+    let wat = String::from_utf8(wat).map_err(|e| {
+        log_error!(format!("Invalid Wat: {}", e), 400).code(ErrorCode::CompileFailed)
+    })?;
+
+    from_wasm(vec![], metadata).map(|wm| WasmModule {
+        wat: Some(wat),
+        ..wm
+    })
+}
+
+/// # Compiles the WebAssembly text of a [`WasmModule`] into its bytecode.
+///
+/// Modules without text are returned as they are.
+///
+/// ## Arguments
+///
+/// * `wasm_module` - The uploaded [`WasmModule`].
+///
+/// ## Returns
+///
+/// * A `Result` object with the [`WasmModule`] holding both the bytecode and the text,
+///   or a `WessError` object if the text doesn't compile or the bytecode was also sent.
+pub fn compile(mut wasm_module: WasmModule) -> Result<WasmModule, WessError> {
+    if let Some(wat) = &wasm_module.wat {
+        if !wasm_module.wasm.is_empty() {
+            return Err(
+                log_error!("Send either 'wasm' or 'wat', not both".to_string(), 400)
+                    .code(ErrorCode::InvalidBody),
+            );
+        }
+        wasm_module.wasm = wasmer::wat2wasm(wat.as_bytes())
+            .map_err(|e| {
+                log_error!(format!("Invalid Wat: {}", e), 400).code(ErrorCode::CompileFailed)
+            })?
+            .into_owned();
+    }
+
+    Ok(wasm_module)
+}
+
+/// # Builds a [`WasmModule`] from a `multipart/form-data` body.
+///
+/// ## Arguments
+///
+/// * `body` - The whole request body.
+/// * `boundary` - The boundary parameter of the `Content-Type` header.
+///
+/// ## Returns
+///
+/// * A `Result` object with the [`WasmModule`],
+///   or a `WessError` object if a part is missing or invalid.
+pub async fn from_multipart(body: Vec<u8>, boundary: &str) -> Result<WasmModule, WessError> {
+    let mut multipart = Multipart::with_reader(Cursor::new(body), boundary);
+    let mut wasm = None;
+    let mut wat = None;
+    let mut metadata = None;
+
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        log_error!(format!("Invalid Multipart: {}", e), 400).code(ErrorCode::InvalidBody)
+    })? {
+        let name = field.name().map(str::to_string);
+        let bytes = field.bytes().await.map_err(|e| {
+            log_error!(format!("Invalid Multipart: {}", e), 400).code(ErrorCode::InvalidBody)
+        })?;
+
+        match name.as_deref() {
+            Some("wasm") => wasm = Some(bytes.to_vec()),
+            Some("wat") => wat = Some(String::from_utf8_lossy(&bytes).into_owned()),
+            Some("metadata") => metadata = Some(parse_metadata(&bytes)?),
+            _ => {}
+        }
+    }
+
+    match (wasm, wat, metadata) {
+        (_, _, None) => {
+            Err(log_error!("Missing Part: metadata".to_string(), 400)
+                .code(ErrorCode::InvalidMetadata))
+        }
+        (None, None, _) => {
+            Err(log_error!("Missing Part: wasm".to_string(), 400).code(ErrorCode::InvalidBody))
+        }
+        (wasm, wat, Some(metadata)) => Ok(WasmModule {
+            wasm: wasm.unwrap_or_default(),
+            metadata,
+            wat,
+        }),
+    }
+}
+
+fn parse_metadata(json: &[u8]) -> Result<WasmMetadata, WessError> {
+    serde_json::from_slice::<WasmMetadata>(json).map_err(|e| {
+        log_error!(format!("Invalid Metadata: {}", e), 400).code(ErrorCode::InvalidMetadata)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METADATA: &str = r#"{"functionName":"sum","returnType":["I32"],"args":["I32","I32"]}"#;
+
+    #[test]
+    fn test_from_wasm() {
+        let wm = from_wasm(vec![0, 97, 115, 109], Some(METADATA)).unwrap();
+
+        assert_eq!(wm.wasm, vec![0, 97, 115, 109]);
+        assert_eq!(wm.metadata.function_name, "sum");
+        assert_eq!(from_wasm(vec![], None).unwrap_err().status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_from_multipart() {
+        let boundary = "wess-boundary";
+        let mut body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"metadata\"\r\n\r\n{m}\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"wasm\"; filename=\"sum.wasm\"\r\n\
+             Content-Type: application/wasm\r\n\r\n",
+            b = boundary,
+            m = METADATA
+        )
+        .into_bytes();
+        body.extend_from_slice(&[0, 97, 115, 109]);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+        let wm = from_multipart(body, boundary).await.unwrap();
+
+        assert_eq!(wm.wasm, vec![0, 97, 115, 109]);
+        assert_eq!(wm.metadata.args.len(), 2);
+    }
+
+    #[test]
+    fn test_compile_wat() {
+        let wat = r#"(module (func (export "sum") (param i32 i32) (result i32)
+            local.get 0 local.get 1 i32.add))"#;
+
+        let wm = compile(from_wat(wat.as_bytes().to_vec(), Some(METADATA)).unwrap()).unwrap();
+
+        assert!(wm.wasm.starts_with(&[0, 97, 115, 109]));
+        assert_eq!(wm.wat.as_deref(), Some(wat));
+    }
+
+    #[test]
+    fn test_compile_invalid_wat() {
+        let wm = WasmModule {
+            wat: Some("(module (func".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(compile(wm).unwrap_err().status, 400);
+
+        let wm = WasmModule {
+            wasm: vec![0, 97, 115, 109],
+            wat: Some("(module)".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(compile(wm).unwrap_err().status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_from_multipart_without_wasm() {
+        let body = format!(
+            "--b\r\nContent-Disposition: form-data; name=\"metadata\"\r\n\r\n{}\r\n--b--\r\n",
+            METADATA
+        );
+
+        let err = from_multipart(body.into_bytes(), "b").await.unwrap_err();
+
+        assert_eq!(err.status, 400);
+    }
+}
@@ -0,0 +1,450 @@
+//! # The `tls` module serves the API over HTTPS
+//!
+//! This module contains the following main components:
+//!
+//! - [`TlsListener`]: A tide listener terminating TLS before handing requests to the server.
+//! - [`TlsAcceptor`]: Builds the rustls configuration from `[server.tls]`, and builds it
+//!   again when the certificate, the key or the client CAs change on disk.
+//! - [`ClientCert`]: The client certificate of a request, attached to it with mTLS.
+//!
+//! With `client_ca_path`, clients may authenticate with a certificate signed by one
+//! of its CAs. The subject of the certificate is looked up in `[server.tls.clients]`
+//! to find the caller it stands for, see [`ClientCert::identity`].
+
+use crate::{
+    auth::{Identity, Source},
+    config::TlsConfig,
+    errors::WessError,
+};
+use async_std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    prelude::*,
+    task,
+};
+use futures_rustls::server::TlsStream;
+use log::{error, info};
+use rustls::{
+    crypto::ring::default_provider,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    fs::{self, File},
+    io::BufReader,
+    pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+use tide::{
+    listener::{ListenInfo, Listener, ToListener},
+    Server,
+};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// How long a client may take to complete the TLS handshake before its connection is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// # The client certificate a request was made with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCert {
+    /// The subject of the certificate, e.g. `CN=ci, O=Acme`.
+    pub subject: String,
+}
+
+impl ClientCert {
+    fn from_der(der: &CertificateDer) -> Option<Self> {
+        X509Certificate::from_der(der.as_ref())
+            .ok()
+            .map(|(_, cert)| Self {
+                subject: cert.subject().to_string(),
+            })
+    }
+
+    /// # Finds the caller the certificate stands for.
+    ///
+    /// ## Returns
+    ///
+    /// * The [`Identity`] of the subject in `[server.tls.clients]`, or `None` if it
+    ///   isn't listed.
+    pub fn identity(&self, tls: &TlsConfig) -> Option<Identity> {
+        tls.clients.get(&self.subject).map(|client| Identity {
+            id: Source::Cert.id(&self.subject),
+            name: client.name.clone(),
+            role: client.role,
+            tenant: client.tenant.clone(),
+        })
+    }
+}
+
+/// # Builds the TLS configuration of the server, reloading it when its files change.
+pub struct TlsAcceptor {
+    config: TlsConfig,
+    current: RwLock<(futures_rustls::TlsAcceptor, Vec<Option<SystemTime>>)>,
+    /// When the files were last modified at the last reload, loaded or not.
+    tried: RwLock<Vec<Option<SystemTime>>>,
+}
+
+impl TlsAcceptor {
+    /// # Creates a new instance of [`TlsAcceptor`].
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object, or a `WessError` object if the certificate, the key or the
+    ///   client CAs can't be read.
+    pub fn new(config: TlsConfig) -> Result<Self, WessError> {
+        let modified = modified(&config);
+        let acceptor = acceptor(&config)?;
+        Ok(Self {
+            config,
+            current: RwLock::new((acceptor, modified.clone())),
+            tried: RwLock::new(modified),
+        })
+    }
+
+    /// # Gets the acceptor of new connections, reading the files again if they changed.
+    ///
+    /// A change that doesn't load, like a certificate written before its key, is
+    /// logged and the previous configuration is kept. It is only tried again once
+    /// the files change again, not on every connection.
+    pub fn acceptor(&self) -> futures_rustls::TlsAcceptor {
+        let modified = modified(&self.config);
+        if modified != *self.tried.read().unwrap() {
+            let mut tried = self.tried.write().unwrap();
+            // Another connection may have reloaded the files while this one waited
+            if modified != *tried {
+                match acceptor(&self.config) {
+                    Ok(acceptor) => {
+                        info!("Reload TLS certificate {}", self.config.cert_path);
+                        *self.current.write().unwrap() = (acceptor, modified.clone());
+                    }
+                    Err(e) => error!("Keep the previous TLS certificate: {}", e.msg),
+                }
+                *tried = modified;
+            }
+        }
+        self.current.read().unwrap().0.clone()
+    }
+}
+
+/// # A tide listener serving HTTPS.
+pub struct TlsListener<State> {
+    addr: String,
+    acceptor: Arc<TlsAcceptor>,
+    listener: Option<TcpListener>,
+    server: Option<Server<State>>,
+}
+
+impl<State> TlsListener<State> {
+    /// # Creates a new instance of [`TlsListener`].
+    ///
+    /// ## Arguments
+    ///
+    /// * `addr` - The address to listen to.
+    /// * `acceptor` - The [`TlsAcceptor`] holding the certificate of the server.
+    pub fn new(addr: &str, acceptor: TlsAcceptor) -> Self {
+        Self {
+            addr: addr.to_string(),
+            acceptor: Arc::new(acceptor),
+            listener: None,
+            server: None,
+        }
+    }
+}
+
+impl<State: Clone + Send + Sync + 'static> ToListener<State> for TlsListener<State> {
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self::Listener> {
+        Ok(self)
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Listener<State> for TlsListener<State> {
+    async fn bind(&mut self, server: Server<State>) -> io::Result<()> {
+        self.listener = Some(TcpListener::bind(&self.addr).await?);
+        self.server = Some(server);
+        Ok(())
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        let server = self
+            .server
+            .take()
+            .expect("`Listener::bind` must be called before `Listener::accept`");
+        let listener = self
+            .listener
+            .take()
+            .expect("`Listener::bind` must be called before `Listener::accept`");
+
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            match stream {
+                Ok(stream) => {
+                    let acceptor = self.acceptor.acceptor();
+                    task::spawn(serve(server.clone(), acceptor, stream));
+                }
+                Err(e) => {
+                    error!("Accept Error: {}", e);
+                    task::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        vec![ListenInfo::new(self.to_string(), "tcp".to_string(), true)]
+    }
+}
+
+impl<State> Debug for TlsListener<State> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsListener")
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+impl<State> Display for TlsListener<State> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "https://{}", self.addr)
+    }
+}
+
+/// # Serves the requests of one connection, after the TLS handshake.
+async fn serve<State: Clone + Send + Sync + 'static>(
+    server: Server<State>,
+    acceptor: futures_rustls::TlsAcceptor,
+    stream: TcpStream,
+) {
+    let local_addr = stream.local_addr().ok();
+    let peer_addr = stream.peer_addr().ok();
+
+    // A client that never finishes the handshake would hold its connection forever
+    let stream = match io::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(stream) => stream,
+        Err(e) => return error!("TLS Handshake Error: {}", e),
+    };
+    let client_cert = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(ClientCert::from_der);
+
+    let stream = Stream(Arc::new(Mutex::new(stream)));
+    let result = async_h1::accept(stream, |mut req| async {
+        req.set_local_addr(local_addr.map(|a: SocketAddr| a.to_string()));
+        req.set_peer_addr(peer_addr.map(|a: SocketAddr| a.to_string()));
+        if let Some(client_cert) = client_cert.clone() {
+            req.ext_mut().insert(client_cert);
+        }
+        server.respond(req).await
+    })
+    .await;
+
+    if let Err(e) = result {
+        error!("HTTP Error: {}", e);
+    }
+}
+
+/// # A TLS stream shared by the reads and writes of a connection.
+#[derive(Clone)]
+struct Stream(Arc<Mutex<TlsStream<TcpStream>>>);
+
+impl Read for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_read(cx, buf)
+    }
+}
+
+impl Write for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.0.lock().unwrap()).poll_close(cx)
+    }
+}
+
+/// # Builds the rustls configuration of the server from `[server.tls]`.
+fn acceptor(config: &TlsConfig) -> Result<futures_rustls::TlsAcceptor, WessError> {
+    let provider = Arc::new(default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid)?;
+
+    let builder = match &config.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certs(path)? {
+                roots.add(cert).map_err(invalid)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if config.require_client_cert {
+                verifier.build()
+            } else {
+                verifier.allow_unauthenticated().build()
+            };
+            builder.with_client_cert_verifier(verifier.map_err(invalid)?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder
+        .with_single_cert(certs(&config.cert_path)?, key(&config.key_path)?)
+        .map_err(invalid)?;
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(futures_rustls::TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// # Reads the certificates of a PEM file.
+fn certs(path: &str) -> Result<Vec<CertificateDer<'static>>, WessError> {
+    let file = File::open(path).map_err(|e| invalid(format!("{}: {}", path, e)))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid(format!("{}: {}", path, e)))?;
+
+    if certs.is_empty() {
+        return Err(invalid(format!("{}: no certificate", path)));
+    }
+    Ok(certs)
+}
+
+/// # Reads the private key of a PEM file.
+fn key(path: &str) -> Result<PrivateKeyDer<'static>, WessError> {
+    let file = File::open(path).map_err(|e| invalid(format!("{}: {}", path, e)))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| invalid(format!("{}: {}", path, e)))?
+        .ok_or_else(|| invalid(format!("{}: no private key", path)))
+}
+
+/// # Gets when each file of the configuration was last modified.
+fn modified(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    [
+        Some(&config.cert_path),
+        Some(&config.key_path),
+        config.client_ca_path.as_ref(),
+    ]
+    .iter()
+    .flatten()
+    .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+    .collect()
+}
+
+fn invalid(e: impl Display) -> WessError {
+    log_error!(format!("Invalid TLS Config: {}", e), 500)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::Role, config::TlsClient};
+    use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair};
+    use std::{
+        env,
+        path::{Path, PathBuf},
+    };
+
+    fn write_cert(name: &str, common_name: &str) -> (PathBuf, PathBuf) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        let cert = params.self_signed(&key).unwrap();
+
+        let dir = env::temp_dir();
+        let cert_path = dir.join(format!("wess-tls-{}.pem", name));
+        let key_path = dir.join(format!("wess-tls-{}.key", name));
+        fs::write(&cert_path, cert.pem()).unwrap();
+        fs::write(&key_path, key.serialize_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    fn config(cert_path: &Path, key_path: &Path) -> TlsConfig {
+        TlsConfig {
+            cert_path: cert_path.to_string_lossy().to_string(),
+            key_path: key_path.to_string_lossy().to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn touch(path: &Path, secs: u64) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_load_and_reload() {
+        let (cert_path, key_path) = write_cert("reload", "wess");
+        let mut config = config(&cert_path, &key_path);
+        let acceptor = TlsAcceptor::new(config.clone()).unwrap();
+        let loaded = modified(&config);
+
+        // A broken certificate keeps the previous one
+        fs::write(&cert_path, "not a certificate").unwrap();
+        touch(&cert_path, 10);
+        let _ = acceptor.acceptor();
+        assert_eq!(acceptor.current.read().unwrap().1, loaded);
+        assert_eq!(*acceptor.tried.read().unwrap(), modified(&config));
+        assert!(TlsAcceptor::new(config.clone()).is_err());
+
+        let _ = write_cert("reload", "wess-renewed");
+        touch(&cert_path, 20);
+        let _ = acceptor.acceptor();
+        assert_eq!(acceptor.current.read().unwrap().1, modified(&config));
+        assert_ne!(acceptor.current.read().unwrap().1, loaded);
+
+        config.client_ca_path = Some("missing.pem".to_string());
+        assert!(TlsAcceptor::new(config).is_err());
+    }
+
+    #[test]
+    fn test_client_identity() {
+        let (cert_path, _) = write_cert("client", "ci");
+        let der = certs(&cert_path.to_string_lossy()).unwrap().remove(0);
+        let client_cert = ClientCert::from_der(&der).unwrap();
+        assert_eq!(client_cert.subject, "CN=ci");
+
+        let mut tls = TlsConfig::default();
+        assert_eq!(client_cert.identity(&tls), None);
+
+        tls.clients.insert(
+            "CN=ci".to_string(),
+            TlsClient {
+                name: "ci".to_string(),
+                role: Role::Publisher,
+                tenant: Some("search".to_string()),
+            },
+        );
+        let identity = client_cert.identity(&tls).unwrap();
+        assert_eq!(identity.id, "cert:CN=ci");
+        assert_eq!(identity.role, Role::Publisher);
+        assert_eq!(identity.tenant_name(), "search");
+    }
+}
@@ -0,0 +1,227 @@
+//! # The `tenants` module keeps track of what each tenant stores
+//!
+//! Every module belongs to the tenant of the caller that created it, and is stored
+//! under a key prefixed by that tenant, see [`module_key`]. A caller only ever sees
+//! the modules of its own tenant.
+//!
+//! This module contains the following main components:
+//!
+//! - [`Usage`]: The number of modules a tenant stores and the size of their bytecode.
+//! - [`Tenants`]: The usage of every tenant, checked against its [`Quota`] before each write.
+//! - [`is_valid`]: Checks the name of a tenant.
+//!
+//! [`module_key`]: crate::database::schema::module_key
+
+use crate::{
+    config::Quota,
+    database::{schema::split_key, ModuleStore},
+    errors::{ErrorCode, WessError},
+    metrics::constants::{TENANT_BYTES, TENANT_MODULES},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+/// The maximum length of the name of a tenant.
+pub const MAX_TENANT_LEN: usize = 64;
+
+/// The number of modules read at once when loading the usage of every tenant.
+const LOAD_PAGE_SIZE: usize = 1000;
+
+/// # What a tenant stores.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    /// The number of modules.
+    pub modules: usize,
+    /// The size of the bytecode of every module, in bytes.
+    pub bytes: u64,
+}
+
+/// # The usage of every tenant, shared between the workers.
+///
+/// Only the Writer changes it, right after each write, so it never drifts from the database.
+#[derive(Clone, Debug, Default)]
+pub struct Tenants {
+    usage: Arc<RwLock<HashMap<String, Usage>>>,
+}
+
+impl Tenants {
+    /// # Creates a new instance of [`Tenants`] with the usage of every stored module.
+    ///
+    /// ## Arguments
+    ///
+    /// * `db` - The [`ModuleStore`] holding the modules.
+    pub fn load<S: ModuleStore>(db: &S) -> Self {
+        let tenants = Self::default();
+        let mut after: Option<String> = None;
+
+        loop {
+            let page = db.scan("", after.as_deref(), LOAD_PAGE_SIZE, &|_| true);
+            for (key, wasm_module) in &page {
+                let (tenant, _) = split_key(key);
+                tenants.record(tenant, None, Some(wasm_module.wasm.len() as u64));
+            }
+            match page.last() {
+                Some((key, _)) if page.len() == LOAD_PAGE_SIZE => after = Some(key.clone()),
+                _ => return tenants,
+            }
+        }
+    }
+
+    /// # Gets the usage of a tenant.
+    pub fn usage(&self, tenant: &str) -> Usage {
+        self.usage
+            .read()
+            .unwrap()
+            .get(tenant)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// # Checks that a write keeps a tenant within its quota.
+    ///
+    /// ## Arguments
+    ///
+    /// * `tenant` - The tenant of the module.
+    /// * `quota` - The [`Quota`] of the tenant.
+    /// * `before` - The size of the bytecode the write replaces, `None` for a new module.
+    /// * `after` - The size of the bytecode the write stores.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object, or a `WessError` object with status 403 if the tenant would
+    ///   store more modules or bytes than its quota allows.
+    pub fn check(
+        &self,
+        tenant: &str,
+        quota: &Quota,
+        before: Option<u64>,
+        after: u64,
+    ) -> Result<(), WessError> {
+        let usage = self.usage(tenant);
+
+        if let Some(max) = quota.max_modules {
+            if before.is_none() && usage.modules >= max {
+                return Err(exceeded(format!("{} can store {} modules", tenant, max)));
+            }
+        }
+        if let Some(max) = quota.max_bytes {
+            if usage.bytes - before.unwrap_or_default().min(usage.bytes) + after > max {
+                return Err(exceeded(format!("{} can store {} bytes", tenant, max)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// # Records a write to the database.
+    ///
+    /// ## Arguments
+    ///
+    /// * `tenant` - The tenant of the module.
+    /// * `before` - The size of the bytecode before the write, `None` for a new module.
+    /// * `after` - The size of the bytecode after the write, `None` for a deleted module.
+    pub fn record(&self, tenant: &str, before: Option<u64>, after: Option<u64>) {
+        let mut usage = self.usage.write().unwrap();
+        let entry = usage.entry(tenant.to_string()).or_default();
+
+        if before.is_some() {
+            entry.modules = entry.modules.saturating_sub(1);
+        }
+        if after.is_some() {
+            entry.modules += 1;
+        }
+        entry.bytes =
+            entry.bytes.saturating_sub(before.unwrap_or_default()) + after.unwrap_or_default();
+
+        TENANT_MODULES
+            .with_label_values(&[tenant])
+            .set(entry.modules as i64);
+        TENANT_BYTES
+            .with_label_values(&[tenant])
+            .set(entry.bytes as i64);
+    }
+}
+
+/// # Checks the name of a tenant.
+///
+/// Like module IDs, names are 1 to [`MAX_TENANT_LEN`] characters of lowercase ASCII
+/// letters, digits, `-` and `_`, so they can't hold the separator of module keys.
+pub fn is_valid(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && tenant.len() <= MAX_TENANT_LEN
+        && tenant
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+fn exceeded(msg: String) -> WessError {
+    log_error!(format!("Quota Exceeded: {}", msg), 403).code(ErrorCode::QuotaExceeded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{models::WasmModule, schema::module_key, MemoryDB};
+
+    #[test]
+    fn test_load_and_record() {
+        let db = MemoryDB::new();
+        let wasm_module = WasmModule {
+            wasm: vec![0; 8],
+            ..Default::default()
+        };
+        for key in ["search/a", "search/b", "billing/a"] {
+            let _ = db.add(key, wasm_module.clone()).unwrap();
+        }
+
+        let tenants = Tenants::load(&db);
+        assert_eq!(
+            tenants.usage("search"),
+            Usage {
+                modules: 2,
+                bytes: 16
+            }
+        );
+        assert_eq!(tenants.usage("other"), Usage::default());
+
+        tenants.record("search", Some(8), Some(4));
+        tenants.record("billing", Some(8), None);
+        assert_eq!(tenants.usage("search").bytes, 12);
+        assert_eq!(tenants.usage("billing"), Usage::default());
+        assert!(db.get(&module_key("billing", "a")).is_some());
+    }
+
+    #[test]
+    fn test_check_quota() {
+        let tenants = Tenants::default();
+        tenants.record("search", None, Some(60));
+        let quota = Quota {
+            max_modules: Some(1),
+            max_bytes: Some(100),
+            ..Default::default()
+        };
+
+        let err = tenants.check("search", &quota, None, 10).unwrap_err();
+        assert_eq!(err.status, 403);
+        assert_eq!(err.code, ErrorCode::QuotaExceeded);
+
+        assert!(tenants.check("search", &quota, Some(60), 100).is_ok());
+        assert!(tenants.check("search", &quota, Some(60), 101).is_err());
+        assert!(tenants.check("billing", &quota, None, 100).is_ok());
+        assert!(tenants
+            .check("search", &Quota::default(), None, 1000)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_valid_names() {
+        assert!(is_valid("search"));
+        assert!(is_valid("team-a_2"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("search/a"));
+        assert!(!is_valid("Search"));
+        assert!(!is_valid(&"a".repeat(MAX_TENANT_LEN + 1)));
+    }
+}
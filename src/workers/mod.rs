@@ -6,7 +6,8 @@
 //! - [`runner`]: Runs a WebAssembly function.
 //! - [`writer`]: Writes a WebAssembly function to the database.
 //!
-//! Each worker runs on its own asynchronous task.
+//! Each worker runs on its own asynchronous task. Jobs wait for a worker until a deadline
+//! at most, see [`queue`].
 //!
 //! The workers depend on the following modules:
 //!
@@ -15,6 +16,7 @@
 //! - [`tokio::sync`]: Provides the concurrency primitives used to communicate between the workers.
 //!
 
+pub mod queue;
 pub mod reader;
 pub mod runner;
 pub mod writer;
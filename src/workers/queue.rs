@@ -0,0 +1,214 @@
+//! # The `queue` module bounds the time a job waits for a worker
+//!
+//! This module contains the following main components:
+//!
+//! - [`enqueue`]: Sends a job to a worker, or gives up with 503 once its deadline passes.
+//! - [`Priority`]: Whether a job may be served after the jobs of run requests.
+//! - [`Queue`]: Two channels to one worker, the high priority one always served first.
+//!
+//! A job that can't be queued before its deadline, `queue_timeout_ms` of its worker in
+//! `wess.toml`, is shed: the request answers 503 with [`ErrorCode::Overloaded`] right
+//! away instead of waiting for the worker.
+
+use crate::{
+    errors::{ErrorCode, ErrorDetails, WessError},
+    metrics::constants::SHED_JOBS_TOTAL,
+};
+use async_std::future::timeout;
+use std::time::{Duration, Instant};
+use tokio::{
+    select,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+/// The seconds a shed request is told to wait before retrying.
+const RETRY_AFTER: u64 = 1;
+
+/// # The priority of a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Admin, health and read requests, never queued behind run requests.
+    High,
+    /// The jobs of run requests.
+    Low,
+}
+
+/// # Sends a job to a worker before a deadline.
+///
+/// ## Arguments
+///
+/// * `tx` - The channel of the worker.
+/// * `job` - The job to send.
+/// * `deadline` - When to give up waiting for room in the channel.
+/// * `worker` - The name of the worker, used in errors and metrics.
+///
+/// ## Returns
+///
+/// * A `Result` object, or a `WessError` object with status 503 if the channel stays
+///   full until the deadline, or 500 if the worker stopped.
+pub async fn enqueue<T>(
+    tx: &Sender<T>,
+    job: T,
+    deadline: Instant,
+    worker: &str,
+) -> Result<(), WessError> {
+    let wait = deadline.saturating_duration_since(Instant::now());
+
+    // The timer of async-std, as the handlers of tide don't run on a Tokio runtime
+    match timeout(wait, tx.send(job)).await {
+        Ok(Ok(())) => Ok(()),
+        Err(_) => Err(shed(worker, "enqueue")),
+        Ok(Err(_)) => Err(log_error!(
+            format!("Channel Error: the {} stopped", worker),
+            500
+        )),
+    }
+}
+
+/// # Sheds a job that waited too long for a worker.
+///
+/// ## Arguments
+///
+/// * `worker` - The name of the worker.
+/// * `reason` - `enqueue` if the channel was full, `start` if the worker got to the job too late.
+///
+/// ## Returns
+///
+/// * A `WessError` object with status 503 and a `Retry-After`.
+pub fn shed(worker: &str, reason: &str) -> WessError {
+    SHED_JOBS_TOTAL.with_label_values(&[worker, reason]).inc();
+    log_error!(format!("Overloaded: the {} is busy", worker), 503)
+        .code(ErrorCode::Overloaded)
+        .details(ErrorDetails {
+            retry_after: Some(RETRY_AFTER),
+            ..Default::default()
+        })
+}
+
+/// # Two channels to one worker, one per [`Priority`].
+#[derive(Debug)]
+pub struct Queue<T> {
+    high: Sender<T>,
+    low: Sender<T>,
+    timeout: Duration,
+    worker: &'static str,
+}
+
+impl<T> Clone for Queue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            high: self.high.clone(),
+            low: self.low.clone(),
+            timeout: self.timeout,
+            worker: self.worker,
+        }
+    }
+}
+
+impl<T> Queue<T> {
+    /// # Creates a new [`Queue`] and the [`QueueReceiver`] of its worker.
+    ///
+    /// ## Arguments
+    ///
+    /// * `size` - The size of each channel.
+    /// * `timeout` - How long a job may wait for room in its channel.
+    /// * `worker` - The name of the worker, used in errors and metrics.
+    pub fn channel(
+        size: usize,
+        timeout: Duration,
+        worker: &'static str,
+    ) -> (Self, QueueReceiver<T>) {
+        let (high, high_rx) = mpsc::channel(size);
+        let (low, low_rx) = mpsc::channel(size);
+        (
+            Self {
+                high,
+                low,
+                timeout,
+                worker,
+            },
+            QueueReceiver {
+                high: high_rx,
+                low: low_rx,
+            },
+        )
+    }
+
+    /// # Sends a job to the worker within the timeout of the queue, see [`enqueue`].
+    pub async fn send(&self, job: T, priority: Priority) -> Result<(), WessError> {
+        let tx = match priority {
+            Priority::High => &self.high,
+            Priority::Low => &self.low,
+        };
+        enqueue(tx, job, Instant::now() + self.timeout, self.worker).await
+    }
+
+    /// # Returns the room left in both channels.
+    pub fn capacity(&self) -> usize {
+        self.high.capacity() + self.low.capacity()
+    }
+}
+
+/// # The receiving end of a [`Queue`].
+#[derive(Debug)]
+pub struct QueueReceiver<T> {
+    high: Receiver<T>,
+    low: Receiver<T>,
+}
+
+impl<T> QueueReceiver<T> {
+    /// # Receives the next job, the high priority ones first.
+    ///
+    /// ## Returns
+    ///
+    /// * The next job, or `None` once every [`Queue`] was dropped.
+    pub async fn recv(&mut self) -> Option<T> {
+        select! {
+            biased;
+
+            Some(job) = self.high.recv() => Some(job),
+            Some(job) = self.low.recv() => Some(job),
+            else => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_high_priority_first() {
+        let (queue, mut rx) = Queue::channel(2, Duration::from_secs(1), "reader");
+
+        queue.send("run", Priority::Low).await.unwrap();
+        queue.send("admin", Priority::High).await.unwrap();
+        assert_eq!(rx.recv().await, Some("admin"));
+        assert_eq!(rx.recv().await, Some("run"));
+
+        drop(queue);
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_shed_when_full() {
+        let (queue, _rx) = Queue::channel(1, Duration::from_millis(20), "reader");
+
+        queue.send(1, Priority::Low).await.unwrap();
+        let err = queue.send(2, Priority::Low).await.unwrap_err();
+        assert_eq!(err.status, 503);
+        assert_eq!(err.code, ErrorCode::Overloaded);
+        assert_eq!(err.details.unwrap().retry_after, Some(RETRY_AFTER));
+
+        assert!(queue.send(3, Priority::High).await.is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_shed_outside_tokio() {
+        let (queue, _rx) = Queue::channel(1, Duration::from_millis(20), "runner");
+
+        queue.send(1, Priority::Low).await.unwrap();
+        let err = queue.send(2, Priority::Low).await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::Overloaded);
+    }
+}
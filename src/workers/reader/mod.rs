@@ -12,48 +12,73 @@
 //!
 //! The `reader` module provides an asynchronous interface for reading data from the database
 //! and uses an in-memory cache to improve performance for frequently accessed data.
+//!
+//! Modules are read by their key, prefixed by their tenant. Counts and lists only
+//! cover the modules of one tenant.
 
 pub mod cache;
 pub mod models;
 
 use self::{
     cache::Cache,
-    models::{ReadJob, ReadResponse},
+    models::{ListQuery, ModulePage, ReadJob, ReadOps, ReadResponse},
+};
+use crate::{
+    auth::{
+        acl::{is_allowed, Permission},
+        Identity,
+    },
+    config::CONFIG,
+    database::{
+        models::ModuleSummary,
+        schema::{module_key, split_key, tenant_prefix},
+        ModuleStore,
+    },
+    errors::ErrorCode,
+    tenants::Tenants,
+    workers::queue::{Queue, QueueReceiver},
 };
-use crate::{config::CONFIG, database::RocksDB};
 use std::sync::Arc;
 use tokio::{
     select,
-    sync::{
-        mpsc::{self, Receiver, Sender},
-        Mutex,
-    },
+    sync::{mpsc::Receiver, Mutex},
 };
 
 /// Worker responsible for reading values from the database.
-pub struct Reader {
-    /// Channel receiver that receives read requests.
-    rx: Receiver<ReadJob>,
+pub struct Reader<S: ModuleStore> {
+    /// Receives read requests, those of run requests after the others.
+    rx: QueueReceiver<ReadJob>,
     /// Database instance to read values from.
-    db: RocksDB,
+    db: S,
     /// Cache instance for reading values from the memory cache.
     cache: Cache,
     rx_writer: Receiver<String>,
+    /// The usage of every tenant, kept up to date by the Writer.
+    tenants: Tenants,
 }
 
-impl Reader {
+impl<S: ModuleStore> Reader<S> {
     /// # Creates a new instance of [`Reader`].
     ///
     /// ## Arguments
     ///
-    /// * `db` - The [`RocksDB`] instance to read values from.
+    /// * `db` - The [`ModuleStore`] instance to read values from.
+    /// * `rx_writer` - The keys of the modules written by the Writer, dropped from the cache.
+    /// * `tenants` - The usage of every tenant, used to count their modules.
     ///
     /// ## Returns
     ///
-    /// A tuple containing a channel sender and an [`Arc<Mutex<Reader>>`] instance.
-    pub fn new(db: RocksDB, rx_writer: Receiver<String>) -> (Sender<ReadJob>, Arc<Mutex<Reader>>) {
-        let channel_size = CONFIG.reader.channel_size;
-        let (tx, rx) = mpsc::channel::<ReadJob>(channel_size);
+    /// A tuple containing a [`Queue`] and an [`Arc<Mutex<Reader>>`] instance.
+    pub fn new(
+        db: S,
+        rx_writer: Receiver<String>,
+        tenants: Tenants,
+    ) -> (Queue<ReadJob>, Arc<Mutex<Reader<S>>>) {
+        let (tx, rx) = Queue::channel(
+            CONFIG.reader.channel_size,
+            CONFIG.reader.queue_timeout,
+            "reader",
+        );
         let cache = Cache::new();
         (
             tx,
@@ -62,6 +87,7 @@ impl Reader {
                 rx_writer,
                 db,
                 cache,
+                tenants,
             })),
         )
     }
@@ -70,21 +96,35 @@ impl Reader {
     pub async fn run(&mut self) {
         loop {
             select! {
+                // Cache invalidations go first, so a read never sees a module older than
+                // a write that was already acknowledged.
+                biased;
+
                 Some(id) = self.rx_writer.recv() => {
                     self.cache.del(id)
                 },
                 Some(job) = self.rx.recv() => {
-                    let option_id = job.id.clone();
                     let tx = job.tx;
-                    //
-                    match option_id {
-                        None => {
-                            let r = self.db.all().len().clone();
+
+                    match job.read_op {
+                        ReadOps::Ping => {
+                            tokio::spawn(async move {
+                                tx.send(ReadResponse::Pong)
+                            });
+                        },
+                        ReadOps::Count(tenant) => {
+                            let r = self.tenants.usage(&tenant).modules;
                             tokio::spawn(async move {
                                 tx.send(ReadResponse::Size(r))
                             });
                         },
-                        Some(id) => {
+                        ReadOps::List(identity, query) => {
+                            let r = self.list(&identity, &query);
+                            tokio::spawn(async move {
+                                tx.send(ReadResponse::Page(r))
+                            });
+                        },
+                        ReadOps::Get(id) => {
                             let db = &self.db;
                             let f = |i: &str| db.get(i);
                             let cache_result = self.cache.get(&id, f);
@@ -92,13 +132,13 @@ impl Reader {
                             match cache_result {
                                 Some(wasm_module) => {
                                     tokio::spawn(async move {
-                                        tx.send(ReadResponse::Module(wasm_module))
+                                        tx.send(ReadResponse::Module(Box::new(wasm_module)))
                                     });
                                 }
                                 None => {
                                     tokio::spawn(
                                         async move {
-                                        let werr = log_error!("Not found".to_string(), 404);
+                                        let werr = log_error!("Not found".to_string(), 404).code(ErrorCode::ModuleNotFound);
                                         tx.send(ReadResponse::Fail(werr)) },
                                     );
                                 }
@@ -109,4 +149,167 @@ impl Reader {
             }
         }
     }
+    /// # Reads one page of module summaries.
+    ///
+    /// Asks the database for one module more than the page size, so the cursor
+    /// of the next page is only set when there is a next page. Modules the caller
+    /// may not run are skipped.
+    ///
+    /// ## Arguments
+    ///
+    /// * `identity` - The caller, whose tenant is listed.
+    /// * `query` - The cursor, page size and filters of the page.
+    ///
+    /// ## Returns
+    ///
+    /// A [`ModulePage`] with the summaries of the modules in the page.
+    fn list(&self, identity: &Identity, query: &ListQuery) -> ModulePage {
+        let tenant = identity.tenant_name();
+        let size = query.page_size();
+        let after = query
+            .cursor
+            .as_ref()
+            .map(|cursor| module_key(tenant, cursor));
+        let mut modules = self
+            .db
+            .scan(&tenant_prefix(tenant), after.as_deref(), size + 1, &|wm| {
+                query.matches(wm) && is_allowed(identity, &wm.metadata, Permission::Run)
+            });
+
+        let next = if modules.len() > size {
+            modules.truncate(size);
+            modules.last().map(|(key, _)| split_key(key).1.to_string())
+        } else {
+            None
+        };
+
+        ModulePage {
+            modules: modules
+                .into_iter()
+                .map(|(key, wm)| ModuleSummary::new(split_key(&key).1.to_string(), &wm))
+                .collect(),
+            next,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::Role,
+        database::{models::WasmModule, schema::DEFAULT_TENANT, MemoryDB},
+        workers::queue::Priority,
+    };
+    use tokio::sync::{mpsc, oneshot};
+
+    #[tokio::test]
+    async fn test_list_modules_on_memory_db() {
+        let db = MemoryDB::new();
+        for id in ["a", "b", "c"] {
+            let mut wasm_module = WasmModule::default();
+            wasm_module.metadata.function_name = id.to_string();
+            let _ = db
+                .add(&module_key(DEFAULT_TENANT, id), wasm_module)
+                .unwrap();
+        }
+        let _ = db.add("other/a", WasmModule::default()).unwrap();
+        let (_, rx_writer) = mpsc::channel::<String>(1);
+        let (reader_tx, reader) = Reader::new(db.clone(), rx_writer, Tenants::load(&db));
+        tokio::spawn(async move { reader.lock().await.run().await });
+
+        let query = ListQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let (tx, rx) = oneshot::channel();
+        reader_tx
+            .send(
+                ReadJob::new(tx, ReadOps::List(Identity::anonymous(), query)),
+                Priority::High,
+            )
+            .await
+            .unwrap();
+        let page = match rx.await.unwrap() {
+            ReadResponse::Page(page) => page,
+            r => panic!("unexpected response: {:?}", r),
+        };
+        assert_eq!(page.modules.len(), 2);
+        assert_eq!(page.next, Some("b".to_string()));
+
+        let query = ListQuery {
+            cursor: page.next,
+            ..Default::default()
+        };
+        let (tx, rx) = oneshot::channel();
+        reader_tx
+            .send(
+                ReadJob::new(tx, ReadOps::List(Identity::anonymous(), query)),
+                Priority::High,
+            )
+            .await
+            .unwrap();
+        let page = match rx.await.unwrap() {
+            ReadResponse::Page(page) => page,
+            r => panic!("unexpected response: {:?}", r),
+        };
+        assert_eq!(page.modules[0].function_name, "c");
+        assert_eq!(page.next, None);
+
+        let (tx, rx) = oneshot::channel();
+        reader_tx
+            .send(
+                ReadJob::new(tx, ReadOps::Count(DEFAULT_TENANT.to_string())),
+                Priority::High,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(rx.await.unwrap(), ReadResponse::Size(3)));
+
+        let (tx, rx) = oneshot::channel();
+        reader_tx
+            .send(
+                ReadJob::new(tx, ReadOps::Count("other".to_string())),
+                Priority::Low,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(rx.await.unwrap(), ReadResponse::Size(1)));
+    }
+
+    #[tokio::test]
+    async fn test_list_skips_modules_the_caller_cant_run() {
+        let db = MemoryDB::new();
+        for (id, owner) in [("a", "alice"), ("b", "bob")] {
+            let mut wasm_module = WasmModule::default();
+            wasm_module.metadata.owner = Some(owner.to_string());
+            let _ = db
+                .add(&module_key(DEFAULT_TENANT, id), wasm_module)
+                .unwrap();
+        }
+        let (_, rx_writer) = mpsc::channel::<String>(1);
+        let (reader_tx, reader) = Reader::new(db.clone(), rx_writer, Tenants::load(&db));
+        tokio::spawn(async move { reader.lock().await.run().await });
+
+        let bob = Identity {
+            id: "bob".to_string(),
+            name: "bob".to_string(),
+            role: Role::Invoker,
+            tenant: None,
+        };
+        let (tx, rx) = oneshot::channel();
+        reader_tx
+            .send(
+                ReadJob::new(tx, ReadOps::List(bob, ListQuery::default())),
+                Priority::High,
+            )
+            .await
+            .unwrap();
+        let page = match rx.await.unwrap() {
+            ReadResponse::Page(page) => page,
+            r => panic!("unexpected response: {:?}", r),
+        };
+        assert_eq!(page.modules.len(), 1);
+        assert_eq!(page.modules[0].id, "b");
+    }
 }
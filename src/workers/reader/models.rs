@@ -2,32 +2,143 @@
 //!
 //! This module contains the following types:
 //!
-//! - [`ReadJob`]: A struct representing a read job, containing a channel to send the read response and the ID of the wasm function to be read.
+//! - [`ReadJob`]: A struct representing a read job, containing a channel to send the read response and the read operation to perform.
+//! - [`ReadOps`]: An enum representing a read operation type. It can be get, count or list.
+//! - [`ListQuery`]: A struct representing the pagination and filters of a list operation.
+//! - [`ModulePage`]: A struct representing one page of module summaries.
 //! - [`ReadResponse`]: An enum representing the response of a read operation. It can either contain the retrieved wasm function, or a message indicating that the function was not found.
 //!
 //! The `models` module depends on the following modules:
 //!
 //! - [`WasmModule`]: Represents a WebAssembly function.
 
-use crate::{database::models::WasmModule, errors::WessError};
-use serde::Serialize;
+use crate::{
+    auth::Identity,
+    database::models::{ModuleSummary, WasmModule},
+    errors::WessError,
+};
+use serde::{Deserialize, Serialize};
 use tokio::sync::oneshot::Sender;
 
+/// The number of modules in a page when the request doesn't set a limit.
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// The largest number of modules a single page can hold.
+pub const MAX_PAGE_SIZE: usize = 500;
+
 #[derive(Debug)]
 pub struct ReadJob {
     pub tx: Sender<ReadResponse>,
-    pub id: Option<String>,
+    pub read_op: ReadOps,
 }
 
 impl ReadJob {
-    pub fn new(tx: Sender<ReadResponse>, id: Option<String>) -> Self {
-        Self { tx, id }
+    pub fn new(tx: Sender<ReadResponse>, read_op: ReadOps) -> Self {
+        Self { tx, read_op }
+    }
+}
+
+/// # Read Operation Type
+#[derive(Debug)]
+pub enum ReadOps {
+    /// Gets a module by its key, see [`module_key`].
+    ///
+    /// [`module_key`]: crate::database::schema::module_key
+    Get(String),
+    /// Counts the modules of a tenant.
+    Count(String),
+    /// Lists a page of the summaries of the modules the caller may run, in the
+    /// tenant of the caller.
+    List(Identity, ListQuery),
+    /// Checks that the Reader is serving, answered with [`ReadResponse::Pong`].
+    Ping,
+}
+
+/// # The query string of a list request.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ListQuery {
+    /// The id of the last module of the previous page, without its tenant.
+    pub cursor: Option<String>,
+    /// The maximum number of modules in the page.
+    pub limit: Option<usize>,
+    /// Only lists modules exporting this function.
+    #[serde(rename = "functionName")]
+    pub function_name: Option<String>,
+    /// Only lists modules with this label.
+    pub label: Option<String>,
+}
+
+impl ListQuery {
+    /// # Returns the page size, between 1 and [`MAX_PAGE_SIZE`].
+    pub fn page_size(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// # Checks whether a module passes the filters of the query.
+    pub fn matches(&self, wasm_module: &WasmModule) -> bool {
+        let metadata = &wasm_module.metadata;
+        self.function_name
+            .as_ref()
+            .is_none_or(|name| &metadata.function_name == name)
+            && self
+                .label
+                .as_ref()
+                .is_none_or(|label| metadata.labels.contains(label))
     }
 }
 
+/// # A page of module summaries.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct ModulePage {
+    pub modules: Vec<ModuleSummary>,
+    /// The cursor of the next page, `None` on the last page.
+    pub next: Option<String>,
+}
+
 #[derive(Serialize, Debug)]
 pub enum ReadResponse {
-    Module(WasmModule),
+    Module(Box<WasmModule>),
     Fail(WessError),
     Size(usize),
+    Page(ModulePage),
+    /// The answer to [`ReadOps::Ping`].
+    Pong,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_query_filters() {
+        let mut wasm_module = WasmModule::default();
+        wasm_module.metadata.function_name = "sum".to_string();
+        wasm_module.metadata.labels = vec!["math".to_string()];
+
+        let query = ListQuery {
+            function_name: Some("sum".to_string()),
+            label: Some("math".to_string()),
+            ..Default::default()
+        };
+        assert!(query.matches(&wasm_module));
+
+        let query = ListQuery {
+            label: Some("text".to_string()),
+            ..Default::default()
+        };
+        assert!(!query.matches(&wasm_module));
+    }
+
+    #[test]
+    fn test_list_query_page_size() {
+        assert_eq!(ListQuery::default().page_size(), DEFAULT_PAGE_SIZE);
+
+        let query = ListQuery {
+            limit: Some(MAX_PAGE_SIZE + 1),
+            ..Default::default()
+        };
+        assert_eq!(query.page_size(), MAX_PAGE_SIZE);
+    }
 }
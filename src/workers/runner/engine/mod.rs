@@ -12,17 +12,19 @@
 //! - [`RunnerError`]: An enum representing the possible errors that can occur during the execution of a run job.
 
 use crate::{
-    database::models::WasmModule,
-    errors::WessError,
+    database::{models::WasmModule, schema::split_key},
+    errors::{ErrorCode, ErrorDetails, WasmFrame, WessError},
     metrics::constants::{WASM_COMPILER_TIME, WASM_EXECUTION_TIME},
 };
-use std::time::Instant;
-use wasmer::{imports, Instance, Module, Store, Value};
+use std::time::{Duration, Instant};
+use wasmer::{imports, Instance, Module, RuntimeError, Store, Value};
+use wasmer_types::TrapCode;
 
 /// A runtime environment for executing WebAssembly functions.
 pub struct Runtime {
     wasm_module: WasmModule,
     id: String,
+    execution_time: Duration,
 }
 
 impl Runtime {
@@ -31,8 +33,18 @@ impl Runtime {
     /// ## Arguments
     ///
     /// * `wasm_module` - A [`WasmModule`] object that represents the WebAssembly function.
+    /// * `id` - The key of the module in the database, prefixed by its tenant.
     pub fn new(wasm_module: WasmModule, id: String) -> Self {
-        Self { wasm_module, id }
+        Self {
+            wasm_module,
+            id,
+            execution_time: Duration::ZERO,
+        }
+    }
+
+    /// # Gets the time the last call of the function ran for, even if it trapped.
+    pub fn execution_time(&self) -> Duration {
+        self.execution_time
     }
 
     /// # Executes a WebAssembly function.
@@ -53,21 +65,24 @@ impl Runtime {
             match Module::from_binary_unchecked(&store, &self.wasm_module.wasm) {
                 Ok(m) => m,
                 Err(e) => {
-                    let werr = log_error!(format!("Compiling Error: {}", e.to_string()), 500);
+                    let werr = log_error!(format!("Compiling Error: {}", e.to_string()), 500)
+                        .code(ErrorCode::CompileFailed);
                     return Err(werr);
                 }
             }
         };
         let duration = start.elapsed();
+        let (tenant, id) = split_key(&self.id);
         WASM_COMPILER_TIME
-            .with_label_values(&[self.id.as_str()])
+            .with_label_values(&[tenant, id])
             .observe(duration.as_secs_f64());
 
         let import_object = imports! {};
         let instance = match Instance::new(&mut store, &module, &import_object) {
             Ok(i) => i,
             Err(e) => {
-                let werr = log_error!(format!("InitializingError: {}", e.to_string()), 500);
+                let werr = log_error!(format!("InitializingError: {}", e.to_string()), 500)
+                    .code(ErrorCode::InstantiationFailed);
                 return Err(werr);
             }
         };
@@ -85,24 +100,124 @@ impl Runtime {
                         e.to_string()
                     ),
                     500
-                );
+                )
+                .code(ErrorCode::FunctionNotFound);
                 return Err(werr);
             }
         };
 
         let start = Instant::now();
-        let result = match wasm_function.call(&mut store, wasm_args) {
+        let result = wasm_function.call(&mut store, wasm_args);
+        self.execution_time = start.elapsed();
+        let result = match result {
             Ok(r) => r,
-            Err(e) => {
-                let werr = log_error!(format!("Function Execution Error: {}", e.to_string()), 500);
-                return Err(werr);
-            }
+            Err(e) => return Err(execution_error(e)),
         };
-        let duration = start.elapsed();
+        let duration = self.execution_time;
         WASM_EXECUTION_TIME
-            .with_label_values(&[self.wasm_module.metadata.function_name.as_str()])
+            .with_label_values(&[tenant, self.wasm_module.metadata.function_name.as_str()])
             .observe(duration.as_secs_f64());
 
         Ok(result)
     }
 }
+
+/// # Converts the error of a function call into a [`WessError`].
+///
+/// Guest traps answer with status 422 and [`ErrorCode::Trap`], along with the trap code
+/// and the WebAssembly backtrace. Any other failure is an internal error.
+fn execution_error(e: RuntimeError) -> WessError {
+    let backtrace = e
+        .trace()
+        .iter()
+        .map(|frame| WasmFrame {
+            function: frame.function_name().map(str::to_string),
+            func_index: frame.func_index(),
+            offset: frame.module_offset(),
+        })
+        .collect();
+    let message = e.message();
+
+    match e.to_trap() {
+        Some(trap) => log_error!(format!("Trap: {}", message), 422)
+            .code(ErrorCode::Trap)
+            .details(ErrorDetails {
+                trap: Some(trap_name(trap).to_string()),
+                backtrace: Some(backtrace),
+                ..Default::default()
+            }),
+        None => log_error!(format!("Function Execution Error: {}", message), 500),
+    }
+}
+
+/// # Gets the name of a trap code, as it is sent to clients.
+pub fn trap_name(trap: TrapCode) -> &'static str {
+    match trap {
+        TrapCode::StackOverflow => "stack_overflow",
+        TrapCode::HeapAccessOutOfBounds => "out_of_bounds_memory",
+        TrapCode::HeapMisaligned => "misaligned_memory",
+        TrapCode::TableAccessOutOfBounds => "out_of_bounds_table",
+        TrapCode::IndirectCallToNull => "null_indirect_call",
+        TrapCode::BadSignature => "bad_signature",
+        TrapCode::IntegerOverflow => "integer_overflow",
+        TrapCode::IntegerDivisionByZero => "division_by_zero",
+        TrapCode::BadConversionToInteger => "invalid_conversion",
+        TrapCode::UnreachableCodeReached => "unreachable",
+        TrapCode::UnalignedAtomic => "unaligned_atomic",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::WasmMetadata;
+
+    fn runtime(wat: &str, function_name: &str) -> Runtime {
+        let wasm = wasmer::wat2wasm(wat.as_bytes()).unwrap().into_owned();
+        let metadata = WasmMetadata::new(function_name.to_string(), vec![], vec![]);
+        Runtime::new(WasmModule::new(wasm, metadata), "test".to_string())
+    }
+
+    #[test]
+    fn test_trap_with_backtrace() {
+        let mut rt = runtime(
+            r#"(module
+                (func $fail unreachable)
+                (func $entry (export "entry") call $fail))"#,
+            "entry",
+        );
+
+        let err = rt.run(&[]).unwrap_err();
+        let details = err.details.unwrap();
+        let functions: Vec<_> = details
+            .backtrace
+            .unwrap()
+            .into_iter()
+            .map(|frame| frame.function)
+            .collect();
+
+        assert_eq!(err.status, 422);
+        assert_eq!(err.code, ErrorCode::Trap);
+        assert_eq!(details.trap.as_deref(), Some("unreachable"));
+        assert_eq!(
+            functions,
+            vec![Some("fail".to_string()), Some("entry".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let mut rt = runtime(
+            r#"(module (func (export "div") (param i32 i32) (result i32)
+                local.get 0 local.get 1 i32.div_s))"#,
+            "div",
+        );
+
+        let err = rt.run(&[Value::I32(1), Value::I32(0)]).unwrap_err();
+
+        assert_eq!(
+            err.details.unwrap().trap.as_deref(),
+            Some("division_by_zero")
+        );
+    }
+}
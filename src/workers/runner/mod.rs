@@ -22,10 +22,12 @@ use self::{
 };
 use crate::{
     config::CONFIG,
-    database::{models::WasmModule, RocksDB},
-    errors::WessError,
+    database::{models::WasmModule, schema::split_key, ModuleStore},
+    errors::{ErrorCode, WessError},
+    limits::CpuTime,
+    workers::queue::shed,
 };
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 use tokio::sync::{
     mpsc::{self, Receiver, Sender},
     Mutex,
@@ -33,25 +35,27 @@ use tokio::sync::{
 use wasmer::Value;
 
 /// An async executor for running WebAssembly functions.
-pub struct Runner {
+pub struct Runner<S: ModuleStore> {
     rx: Receiver<RunJob>,
-    db: RocksDB,
+    db: S,
+    cpu: CpuTime,
 }
 
-impl Runner {
+impl<S: ModuleStore> Runner<S> {
     /// # Creates a new instance of the [`Runner`] struct.
     ///
     /// ## Arguments
     ///
-    /// * `db` - A [`RocksDB`] object that represents the database.
+    /// * `db` - A [`ModuleStore`] object that represents the database.
+    /// * `cpu` - The [`CpuTime`] each run is added to, under the tenant of its module.
     ///
     /// ## Returns
     ///
     /// * A tuple containing a [`Sender<RunJob>`] and an [`Arc<Mutex<Runner>>`].
-    pub fn new(db: RocksDB) -> (Sender<RunJob>, Arc<Mutex<Runner>>) {
+    pub fn new(db: S, cpu: CpuTime) -> (Sender<RunJob>, Arc<Mutex<Runner<S>>>) {
         let channel_size = CONFIG.runner.channel_size;
         let (tx, rx) = mpsc::channel::<RunJob>(channel_size);
-        (tx, Arc::new(Mutex::new(Runner { rx, db })))
+        (tx, Arc::new(Mutex::new(Runner { rx, db, cpu })))
     }
 
     /// # Starts the [`Runner`].
@@ -63,6 +67,15 @@ impl Runner {
             let db = &self.db;
             let id = job.id;
             //
+            if job
+                .deadline
+                .is_some_and(|deadline| Instant::now() > deadline)
+            {
+                tokio::spawn(
+                    async move { responder.send(RunResponse::fail(shed("runner", "start"))) },
+                );
+                continue;
+            }
             match db.get(id.as_str()) {
                 Some(wasm_module) => match self.run_function(&args, wasm_module, id).await {
                     Ok(result) => {
@@ -74,7 +87,8 @@ impl Runner {
                 },
                 None => {
                     tokio::spawn(async move {
-                        let werr = log_error!("Wasm module not found".to_string(), 404);
+                        let werr = log_error!("Wasm module not found".to_string(), 404)
+                            .code(ErrorCode::ModuleNotFound);
                         responder.send(RunResponse::fail(werr))
                     });
                 }
@@ -101,8 +115,12 @@ impl Runner {
         wasm_module: WasmModule,
         id: String,
     ) -> Result<RunResponse, WessError> {
+        let (tenant, _) = split_key(&id);
+        let tenant = tenant.to_string();
         let mut runtime = Runtime::new(wasm_module.clone(), id);
-        match runtime.run(args) {
+        let result = runtime.run(args);
+        self.cpu.record(&tenant, runtime.execution_time());
+        match result {
             Ok(r) => Ok(RunResponse::new(r)),
             Err(werr) => Err(werr),
         }
@@ -10,6 +10,7 @@
 //!
 //! - [`RunRequest`]: Represents a request to run a WebAssembly function.
 
+use std::time::Instant;
 use tokio::sync::oneshot::Sender;
 use wasmer::Value;
 
@@ -21,6 +22,8 @@ pub struct RunJob {
     pub responder: Sender<RunResponse>,
     pub args: Vec<Value>,
     pub id: String,
+    /// When the Runner sheds the job instead of starting it, `None` to always run it.
+    pub deadline: Option<Instant>,
 }
 
 impl RunJob {
@@ -29,8 +32,15 @@ impl RunJob {
             responder,
             args,
             id,
+            deadline: None,
         }
     }
+
+    /// # Sets the time the job must start by.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
 }
 
 /// # Run Response Type
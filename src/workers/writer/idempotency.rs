@@ -0,0 +1,49 @@
+//! # The `idempotency` module remembers the modules created for each `Idempotency-Key`
+//!
+//! This module contains the following main components:
+//!
+//! - [`IdempotencyKeys`]: A bounded map from idempotency key to the module it created.
+//!
+//! A retried `POST /` with a key that was already used gets the module created the
+//! first time instead of a duplicate. Only the most recent [`KEYS_CAPACITY`] keys are
+//! kept, and they are lost when the process exits.
+
+use std::collections::{HashMap, VecDeque};
+
+/// The number of idempotency keys that are remembered.
+pub const KEYS_CAPACITY: usize = 4096;
+
+/// # The modules created for the most recent idempotency keys
+#[derive(Default)]
+pub struct IdempotencyKeys {
+    /// The ID and the bytecode hash of the module created for each key.
+    created: HashMap<String, (String, String)>,
+    order: VecDeque<String>,
+}
+
+impl IdempotencyKeys {
+    /// # Creates an empty [`IdempotencyKeys`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Gets the module created for a key.
+    ///
+    /// ## Returns
+    ///
+    /// * An `Option` with the ID and the bytecode hash of the module, or `None` if the key is unknown.
+    pub fn get(&self, key: &str) -> Option<&(String, String)> {
+        self.created.get(key)
+    }
+
+    /// # Remembers the module created for a key, forgetting the oldest key when full.
+    pub fn put(&mut self, key: String, id: String, hash: String) {
+        if self.order.len() >= KEYS_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.created.remove(&oldest);
+            }
+        }
+        self.order.push_back(key.clone());
+        self.created.insert(key, (id, hash));
+    }
+}
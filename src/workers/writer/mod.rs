@@ -3,36 +3,74 @@
 //! This module contains the following main components:
 //!
 //! - [`Writer`]: A struct representing the async executor.
+//! - [`status`]: The status of write jobs sent without waiting for their outcome.
+//! - [`idempotency`]: The modules created for each `Idempotency-Key`.
+//!
+//! Modules are written under the key of their ID in the tenant of the caller, see
+//! [`module_key`], after checking that the write keeps the tenant within its quota.
+//! Every job is recorded in the audit trail with its outcome, see [`AuditLog`].
 //!
 //! The `writer` module depends on the following modules:
 //!
 //! - [`models`]: A module that contains the models for wrap data by channels.
 
+pub mod idempotency;
 pub mod models;
+pub mod status;
 
-use self::models::{WriteJob, WriteOps};
-use crate::{config::CONFIG, database::RocksDB};
-use std::sync::Arc;
-use tokio::{
-    spawn,
-    sync::{
-        mpsc::{self, Receiver, Sender},
-        Mutex,
+use self::{
+    idempotency::IdempotencyKeys,
+    models::{WriteJob, WriteOps, WriteResponse},
+};
+use crate::{
+    audit::{AuditEntry, AuditLog, Operation},
+    auth::{
+        acl::{authorize, authorize_create, Permission},
+        Identity,
     },
+    config::CONFIG,
+    database::{
+        crypto::cipher,
+        models::WasmModule,
+        schema::{module_key, split_key, DEFAULT_TENANT},
+        ModuleStore,
+    },
+    errors::{ErrorCode, WessError},
+    tenants::Tenants,
+};
+use log::info;
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender},
+    Mutex,
 };
 
 /// An async executor for writing data into the database.
-pub struct Writer {
+pub struct Writer<S: ModuleStore> {
     tx: Sender<String>,
     rx: Receiver<WriteJob>,
-    db: RocksDB,
+    db: S,
+    idempotency: IdempotencyKeys,
+    tenants: Tenants,
+    audit: AuditLog,
 }
 
-impl Writer {
+impl<S: ModuleStore> Writer<S> {
     // # Creates a new instance of [`Writer`] with the given `db` instance.
     ///
+    /// The Writer keeps the usage of `tenants` up to date with every write, and records
+    /// every job in `audit`.
+    ///
     /// Returns a tuple containing a [`Sender<WriteJob>`] and an [`Arc<Mutex<Writer>>`] instance.
-    pub fn new(db: RocksDB, tx_reader: Sender<String>) -> (Sender<WriteJob>, Arc<Mutex<Writer>>) {
+    pub fn new(
+        db: S,
+        tx_reader: Sender<String>,
+        tenants: Tenants,
+        audit: AuditLog,
+    ) -> (Sender<WriteJob>, Arc<Mutex<Writer<S>>>) {
         let channel_size = CONFIG.writer.channel_size;
         let (tx, rx) = mpsc::channel::<WriteJob>(channel_size);
         (
@@ -41,40 +79,606 @@ impl Writer {
                 tx: tx_reader,
                 rx,
                 db,
+                idempotency: IdempotencyKeys::new(),
+                tenants,
+                audit,
             })),
         )
     }
 
     /// # Runs the async executor for writing data into the database.
+    ///
+    /// Once a job is committed, the reader is told to drop the module from its cache
+    /// before the outcome is sent back, so a client that waits for the outcome always
+    /// reads its own write.
     pub async fn run(&mut self) {
         while let Some(job) = self.rx.recv().await {
-            let id = job.id.clone();
-            let send_reader = self.tx.clone();
+            let WriteJob {
+                write_op,
+                write_module,
+                metadata_patch,
+                id,
+                if_match,
+                idempotency_key,
+                caller,
+                tx,
+            } = job;
+            let caller = caller.as_ref();
+            let tenant = caller.map_or(DEFAULT_TENANT, Identity::tenant_name);
+            let key = module_key(tenant, &id);
+            let mut deleted = None;
+
+            let r =
+                match write_op {
+                    WriteOps::Create => self
+                        .create(
+                            &key,
+                            write_module.expect("not found item"),
+                            idempotency_key,
+                            caller,
+                        )
+                        .map(WriteResponse::Created),
+                    WriteOps::Put if if_match.is_none() && self.db.get(&key).is_none() => self
+                        .create(&key, write_module.expect("not found item"), None, caller)
+                        .map(WriteResponse::Created),
+                    WriteOps::Put | WriteOps::Update => self
+                        .current(&key, if_match.as_deref(), caller)
+                        .and_then(|current| {
+                            let wasm_module = stamp(&current, write_module.unwrap());
+                            self.replace(&key, &current, wasm_module)
+                        })
+                        .map(WriteResponse::Done),
+                    WriteOps::Patch => self
+                        .current(&key, if_match.as_deref(), caller)
+                        .and_then(|current| {
+                            let mut wasm_module = stamp(&current, current.clone());
+                            wasm_module
+                                .metadata
+                                .apply(metadata_patch.unwrap_or_default());
+                            self.replace(&key, &current, wasm_module)
+                        })
+                        .map(WriteResponse::Done),
+                    WriteOps::Delete => self
+                        .current(&key, if_match.as_deref(), caller)
+                        .and_then(|current| {
+                            self.db.del(&key)?;
+                            self.tenants.record(tenant, Some(size(&current)), None);
+                            deleted = Some(current);
+                            Ok(id.clone())
+                        })
+                        .map(WriteResponse::Done),
+                    WriteOps::Reencrypt => cipher()
+                        .and_then(|cipher| self.db.reencrypt(cipher))
+                        .map(|count| {
+                            info!(target: "wess::tx", "REENCRYPT {count} records");
+                            WriteResponse::Done(id.clone())
+                        }),
+                };
+            self.audit
+                .record(self.audit_entry(caller, &write_op, &id, &r, deleted));
 
-            match job.write_op {
-                WriteOps::Create => {
-                    if let Err(e) = self.db.add(&id, job.write_module.expect("not found item")) {
-                        log_error!(e.to_string(), e.status.into());
+            let response = match r {
+                // The Reader caches decoded modules, re-encrypting them changes none
+                Ok(response) if matches!(write_op, WriteOps::Reencrypt) => response,
+                Ok(response) => {
+                    if let Err(e) = self.tx.send(key).await {
+                        log_error!(format!("Channel Error: {}", e), 500);
                     }
+                    response
                 }
-                WriteOps::Update => match self.db.upd(&id, job.write_module.unwrap()) {
-                    Ok(id) => {
-                        spawn(async move { send_reader.send(id).await });
-                    }
-                    Err(e) => {
-                        log_error!(e.to_string(), e.status.into());
-                    }
-                },
+                Err(e) => WriteResponse::Fail(e),
+            };
 
-                WriteOps::Delete => match self.db.del(&id) {
-                    Ok(id) => {
-                        spawn(async move { send_reader.send(id).await });
-                    }
-                    Err(e) => {
-                        log_error!(e.to_string(), e.status.into());
-                    }
-                },
+            if let Some(tx) = tx {
+                let _ = tx.send(response);
+            }
+        }
+    }
+
+    /// # Describes a job in the audit trail.
+    ///
+    /// ## Arguments
+    ///
+    /// * `caller` - The caller of the job, anonymous when unset.
+    /// * `write_op` - The operation of the job.
+    /// * `id` - The ID of the module the job applies to.
+    /// * `r` - The outcome of the job.
+    /// * `deleted` - The module a delete removed, stored modules are read back otherwise.
+    fn audit_entry(
+        &self,
+        caller: Option<&Identity>,
+        write_op: &WriteOps,
+        id: &str,
+        r: &Result<WriteResponse, WessError>,
+        deleted: Option<WasmModule>,
+    ) -> AuditEntry {
+        let anonymous = Identity::anonymous();
+        let caller = caller.unwrap_or(&anonymous);
+        let module = match (write_op, r) {
+            (WriteOps::Reencrypt, _) => None,
+            // An idempotent create answers with the module created by the first request
+            (_, Ok(WriteResponse::Created(created))) => Some(created.as_str()),
+            _ => Some(id),
+        };
+
+        let mut entry = AuditEntry::new(caller, Operation::from(write_op), module).outcome(r);
+        let written = match (r, module) {
+            (Ok(_), Some(module)) => {
+                deleted.or_else(|| self.db.get(&module_key(caller.tenant_name(), module)))
+            }
+            _ => None,
+        };
+        if let Some(wasm_module) = written {
+            entry.stored(&wasm_module);
+        }
+        entry
+    }
+
+    /// # Creates a module, unless its idempotency key already created one.
+    ///
+    /// ## Arguments
+    ///
+    /// * `key` - The key of the new module, prefixed by its tenant.
+    /// * `wasm_module` - The module to be stored.
+    /// * `idempotency_key` - The `Idempotency-Key` header of the request, if any.
+    /// * `caller` - The caller of the request, who becomes the owner of the module.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the ID of the new module, or of the module created by
+    ///   an earlier request with the same key, or a `WessError` object with status 403
+    ///   if the caller can't create modules or the tenant is over its quota, or 422 if
+    ///   that request uploaded a different bytecode.
+    fn create(
+        &mut self,
+        key: &str,
+        mut wasm_module: WasmModule,
+        idempotency_key: Option<String>,
+        caller: Option<&Identity>,
+    ) -> Result<String, WessError> {
+        if let Some(caller) = caller {
+            authorize_create(caller)?;
+        }
+        let (tenant, id) = split_key(key);
+        let hash = sha256::digest(wasm_module.to_binary());
+        let idempotency_key =
+            idempotency_key.map(|idempotency_key| module_key(tenant, &idempotency_key));
+
+        if let Some((created, created_hash)) = idempotency_key
+            .as_ref()
+            .and_then(|key| self.idempotency.get(key))
+        {
+            if *created_hash != hash {
+                return Err(log_error!(
+                    "Idempotency-Key was already used for a different module".to_string(),
+                    422
+                )
+                .code(ErrorCode::IdempotencyConflict));
             }
+            return Ok(created.clone());
         }
+
+        let size = size(&wasm_module);
+        self.tenants
+            .check(tenant, CONFIG.tenants.quota(tenant), None, size)?;
+
+        wasm_module.metadata.owner = caller.map(|caller| caller.id.clone());
+        wasm_module.metadata.revision = 1;
+        wasm_module.metadata.created_at = now();
+        wasm_module.metadata.updated_at = wasm_module.metadata.created_at;
+
+        self.db.add(key, wasm_module)?;
+        self.tenants.record(tenant, None, Some(size));
+        if let Some(idempotency_key) = idempotency_key {
+            self.idempotency.put(idempotency_key, id.to_string(), hash);
+        }
+        Ok(id.to_string())
+    }
+
+    /// # Replaces a stored module, if the tenant stays within its quota.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the ID of the module, or a `WessError` object with
+    ///   status 403 if the tenant would go over its quota.
+    fn replace(
+        &self,
+        key: &str,
+        current: &WasmModule,
+        wasm_module: WasmModule,
+    ) -> Result<String, WessError> {
+        let (tenant, id) = split_key(key);
+        let (before, after) = (size(current), size(&wasm_module));
+        self.tenants
+            .check(tenant, CONFIG.tenants.quota(tenant), Some(before), after)?;
+
+        self.db.upd(key, wasm_module)?;
+        self.tenants.record(tenant, Some(before), Some(after));
+        Ok(id.to_string())
+    }
+
+    /// # Gets the stored module a write applies to, checking its `If-Match` precondition
+    /// and that the caller may manage it.
+    ///
+    /// Jobs are handled one at a time, so nothing can change the module between this
+    /// check and the write that follows it.
+    ///
+    /// ## Arguments
+    ///
+    /// * `key` - The key of the module, prefixed by its tenant.
+    /// * `if_match` - The value of the `If-Match` header of the request, if any.
+    /// * `caller` - The caller of the request, if it must be checked.
+    ///
+    /// ## Returns
+    ///
+    /// * A `Result` object with the stored module, or a `WessError` object with status 404
+    ///   if it doesn't exist, 403 if the caller can't manage it or 412 if its ETag doesn't match.
+    fn current(
+        &self,
+        key: &str,
+        if_match: Option<&str>,
+        caller: Option<&Identity>,
+    ) -> Result<WasmModule, WessError> {
+        let (_, id) = split_key(key);
+        let current = self.db.get(key);
+
+        if let (Some(wasm_module), Some(caller)) = (&current, caller) {
+            authorize(caller, &wasm_module.metadata, Permission::Manage)?;
+        }
+
+        match (current, if_match) {
+            (Some(wasm_module), Some(tags)) if !matches_etag(tags, &wasm_module.etag()) => {
+                Err(log_error!(
+                    format!("Precondition Failed: {} is at {}", id, wasm_module.etag()),
+                    412
+                ))
+            }
+            (Some(wasm_module), _) => Ok(wasm_module),
+            (None, Some(_)) => Err(log_error!(
+                format!("Precondition Failed: {} not found", id),
+                412
+            )),
+            (None, None) => {
+                Err(log_error!("Not found".to_string(), 404).code(ErrorCode::ModuleNotFound))
+            }
+        }
+    }
+}
+
+/// # Stamps a new version of a module with the next revision and the current time.
+///
+/// The owner and ACL of the module are kept, only a patch changes the ACL.
+fn stamp(current: &WasmModule, mut wasm_module: WasmModule) -> WasmModule {
+    wasm_module.metadata.owner = current.metadata.owner.clone();
+    wasm_module.metadata.acl = current.metadata.acl.clone();
+    wasm_module.metadata.revision = current.metadata.revision + 1;
+    wasm_module.metadata.created_at = current.metadata.created_at;
+    wasm_module.metadata.updated_at = now();
+    wasm_module
+}
+
+/// # Returns the size of the bytecode of a module, as counted against the quota of its tenant.
+fn size(wasm_module: &WasmModule) -> u64 {
+    wasm_module.wasm.len() as u64
+}
+
+/// # Checks whether an `If-Match` header value matches an ETag.
+///
+/// ## Arguments
+///
+/// * `if_match` - A comma separated list of ETags, or `*` to match any.
+/// * `etag` - The ETag of the stored module.
+fn matches_etag(if_match: &str, etag: &str) -> bool {
+    if_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// # Returns the current time in seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audit::Outcome,
+        auth::Role,
+        database::{
+            models::{Acl, MetadataPatch},
+            MemoryDB,
+        },
+    };
+    use tokio::{spawn, sync::oneshot};
+
+    fn stored_key(id: &str) -> String {
+        module_key(DEFAULT_TENANT, id)
+    }
+
+    fn audit(db: &MemoryDB) -> AuditLog {
+        AuditLog::new(Arc::new(db.clone()), None).unwrap()
+    }
+
+    async fn write(writer_tx: &Sender<WriteJob>, job: WriteJob) -> WriteResponse {
+        let (tx, rx) = oneshot::channel();
+        writer_tx.send(job.with_responder(tx)).await.unwrap();
+        rx.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_write_ops_on_memory_db() {
+        let db = MemoryDB::new();
+        let audit_log = audit(&db);
+        let (tx_reader, mut rx_reader) = mpsc::channel::<String>(16);
+        let (writer_tx, writer) =
+            Writer::new(db.clone(), tx_reader, Tenants::default(), audit_log.clone());
+        spawn(async move { writer.lock().await.run().await });
+
+        let mut updated = WasmModule::default();
+        updated.metadata.function_name = "updated".to_string();
+        let id = "writer_key".to_string();
+
+        let job = WriteJob::new(Some(WasmModule::default()), id.clone(), WriteOps::Create);
+        assert!(matches!(
+            write(&writer_tx, job).await,
+            WriteResponse::Created(_)
+        ));
+        assert_eq!(rx_reader.recv().await, Some(stored_key(&id)));
+
+        let job = WriteJob::new(Some(updated.clone()), id.clone(), WriteOps::Update);
+        assert!(matches!(
+            write(&writer_tx, job).await,
+            WriteResponse::Done(_)
+        ));
+        assert_eq!(rx_reader.recv().await, Some(stored_key(&id)));
+
+        let stored = db.get(&stored_key(&id)).unwrap();
+        assert_eq!(
+            stored.metadata.function_name,
+            updated.metadata.function_name
+        );
+        assert!(stored.metadata.created_at > 0);
+        assert!(stored.metadata.updated_at >= stored.metadata.created_at);
+
+        let patch = MetadataPatch {
+            name: Some("Updated".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            write(&writer_tx, WriteJob::patch(id.clone(), patch)).await,
+            WriteResponse::Done(_)
+        ));
+
+        let patched = db.get(&stored_key(&id)).unwrap();
+        assert_eq!(patched.metadata.name, Some("Updated".to_string()));
+        assert_eq!(patched.wasm, stored.wasm);
+        assert_eq!(patched.metadata.created_at, stored.metadata.created_at);
+
+        assert_eq!(patched.metadata.revision, 3);
+
+        let job = WriteJob::new(None, id.clone(), WriteOps::Delete).if_match(Some("\"2\"".into()));
+        match write(&writer_tx, job).await {
+            WriteResponse::Fail(e) => assert_eq!(e.status, 412),
+            r => panic!("unexpected response: {:?}", r),
+        }
+
+        let job = WriteJob::new(None, id.clone(), WriteOps::Delete).if_match(Some(patched.etag()));
+        assert!(matches!(
+            write(&writer_tx, job).await,
+            WriteResponse::Done(_)
+        ));
+        assert_eq!(db.get(&stored_key(&id)), None);
+
+        match write(
+            &writer_tx,
+            WriteJob::patch(id.clone(), MetadataPatch::default()),
+        )
+        .await
+        {
+            WriteResponse::Fail(e) => assert_eq!(e.status, 404),
+            r => panic!("unexpected response: {:?}", r),
+        }
+
+        let entries = audit_log.query(&Default::default()).unwrap().entries;
+        let trail: Vec<_> = entries.iter().map(|e| (e.operation, e.outcome)).collect();
+        assert_eq!(
+            trail,
+            vec![
+                (Operation::Create, Outcome::Success),
+                (Operation::Update, Outcome::Success),
+                (Operation::Patch, Outcome::Success),
+                (Operation::Delete, Outcome::Failure),
+                (Operation::Delete, Outcome::Success),
+                (Operation::Patch, Outcome::Failure),
+            ]
+        );
+        assert_eq!(entries[0].revision, Some(1));
+        assert_eq!(entries[3].status, Some(412));
+        assert_eq!(entries[4].revision, Some(3));
+        assert_eq!(entries[4].hash, Some(sha256::digest(patched.to_binary())));
+        assert_eq!(entries[5].code, Some(ErrorCode::ModuleNotFound));
+        assert!(entries.iter().all(|e| e.caller == "anonymous"));
+    }
+
+    #[tokio::test]
+    async fn test_put_and_idempotent_create() {
+        let db = MemoryDB::new();
+        let (tx_reader, _rx_reader) = mpsc::channel::<String>(16);
+        let (writer_tx, writer) =
+            Writer::new(db.clone(), tx_reader, Tenants::default(), audit(&db));
+        spawn(async move { writer.lock().await.run().await });
+
+        let job = WriteJob::new(Some(WasmModule::default()), "sum".into(), WriteOps::Put);
+        assert!(matches!(
+            write(&writer_tx, job).await,
+            WriteResponse::Created(_)
+        ));
+        let job = WriteJob::new(Some(WasmModule::default()), "sum".into(), WriteOps::Put);
+        assert!(matches!(
+            write(&writer_tx, job).await,
+            WriteResponse::Done(_)
+        ));
+        assert_eq!(db.get(&stored_key("sum")).unwrap().metadata.revision, 2);
+
+        let key = Some("retry".to_string());
+        let job = WriteJob::new(
+            Some(WasmModule::default()),
+            "first".into(),
+            WriteOps::Create,
+        )
+        .idempotency_key(key.clone());
+        assert!(matches!(
+            write(&writer_tx, job).await,
+            WriteResponse::Created(_)
+        ));
+        let job = WriteJob::new(
+            Some(WasmModule::default()),
+            "second".into(),
+            WriteOps::Create,
+        )
+        .idempotency_key(key.clone());
+        match write(&writer_tx, job).await {
+            WriteResponse::Created(id) => assert_eq!(id, "first"),
+            r => panic!("unexpected response: {:?}", r),
+        }
+        assert_eq!(db.get(&stored_key("second")), None);
+
+        let other = WasmModule {
+            wasm: vec![0, 97, 115, 109],
+            ..Default::default()
+        };
+        let job = WriteJob::new(Some(other), "third".into(), WriteOps::Create).idempotency_key(key);
+        match write(&writer_tx, job).await {
+            WriteResponse::Fail(e) => assert_eq!(e.status, 422),
+            r => panic!("unexpected response: {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_owner_and_acl() {
+        let db = MemoryDB::new();
+        let (tx_reader, _rx_reader) = mpsc::channel::<String>(16);
+        let (writer_tx, writer) =
+            Writer::new(db.clone(), tx_reader, Tenants::default(), audit(&db));
+        spawn(async move { writer.lock().await.run().await });
+
+        let identity = |id: &str, role: Role| Identity {
+            id: id.to_string(),
+            name: id.to_string(),
+            role,
+            tenant: None,
+        };
+        let alice = identity("alice", Role::Publisher);
+        let bob = identity("bob", Role::Invoker);
+        let id = "owned".to_string();
+
+        let job = WriteJob::new(
+            Some(WasmModule::default()),
+            "other".into(),
+            WriteOps::Create,
+        )
+        .caller(Some(bob.clone()));
+        match write(&writer_tx, job).await {
+            WriteResponse::Fail(e) => assert_eq!(e.status, 403),
+            r => panic!("unexpected response: {:?}", r),
+        }
+
+        let job = WriteJob::new(Some(WasmModule::default()), id.clone(), WriteOps::Create)
+            .caller(Some(alice.clone()));
+        assert!(matches!(
+            write(&writer_tx, job).await,
+            WriteResponse::Created(_)
+        ));
+        assert_eq!(
+            db.get(&stored_key(&id)).unwrap().metadata.owner,
+            Some("alice".to_string())
+        );
+
+        let job = WriteJob::new(None, id.clone(), WriteOps::Delete).caller(Some(bob.clone()));
+        match write(&writer_tx, job).await {
+            WriteResponse::Fail(e) => assert_eq!(e.code, ErrorCode::Forbidden),
+            r => panic!("unexpected response: {:?}", r),
+        }
+
+        let patch = MetadataPatch {
+            acl: Some(Acl {
+                manage: vec!["bob".to_string()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let job = WriteJob::patch(id.clone(), patch).caller(Some(alice));
+        assert!(matches!(
+            write(&writer_tx, job).await,
+            WriteResponse::Done(_)
+        ));
+
+        let job = WriteJob::new(Some(WasmModule::default()), id.clone(), WriteOps::Update)
+            .caller(Some(bob));
+        assert!(matches!(
+            write(&writer_tx, job).await,
+            WriteResponse::Done(_)
+        ));
+        let stored = db.get(&stored_key(&id)).unwrap();
+        assert_eq!(stored.metadata.owner, Some("alice".to_string()));
+        assert_eq!(stored.metadata.acl.manage, vec!["bob".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_tenants() {
+        let db = MemoryDB::new();
+        let tenants = Tenants::default();
+        let (tx_reader, mut rx_reader) = mpsc::channel::<String>(16);
+        let (writer_tx, writer) = Writer::new(db.clone(), tx_reader, tenants.clone(), audit(&db));
+        spawn(async move { writer.lock().await.run().await });
+
+        let identity = |tenant: &str| Identity {
+            id: "alice".to_string(),
+            name: "alice".to_string(),
+            role: Role::Admin,
+            tenant: Some(tenant.to_string()),
+        };
+        let wasm_module = WasmModule {
+            wasm: vec![0, 97, 115, 109],
+            ..Default::default()
+        };
+
+        let job = WriteJob::new(Some(wasm_module), "sum".into(), WriteOps::Create)
+            .caller(Some(identity("search")));
+        match write(&writer_tx, job).await {
+            WriteResponse::Created(id) => assert_eq!(id, "sum"),
+            r => panic!("unexpected response: {:?}", r),
+        }
+        assert_eq!(rx_reader.recv().await, Some("search/sum".to_string()));
+        assert!(db.get("search/sum").is_some());
+        assert_eq!(tenants.usage("search").modules, 1);
+        assert_eq!(tenants.usage("search").bytes, 4);
+
+        let job =
+            WriteJob::new(None, "sum".into(), WriteOps::Delete).caller(Some(identity("billing")));
+        match write(&writer_tx, job).await {
+            WriteResponse::Fail(e) => assert_eq!(e.status, 404),
+            r => panic!("unexpected response: {:?}", r),
+        }
+
+        let job =
+            WriteJob::new(None, "sum".into(), WriteOps::Delete).caller(Some(identity("search")));
+        assert!(matches!(
+            write(&writer_tx, job).await,
+            WriteResponse::Done(_)
+        ));
+        assert_eq!(tenants.usage("search").modules, 0);
+    }
+
+    #[test]
+    fn test_matches_etag() {
+        assert!(matches_etag("\"1\", \"2\"", "\"2\""));
+        assert!(matches_etag("*", "\"7\""));
+        assert!(!matches_etag("\"1\"", "\"2\""));
     }
 }
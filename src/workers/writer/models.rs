@@ -3,19 +3,35 @@
 //! This module contains the following types:
 //!
 //! - [`WriteJob`]: A struct representing a write job, containing a [`WasmModule`], a write operation type [`WriteOps`], and an ID.
-//! - [`WriteOps`]: An enum representing a write operation type. It can be either create, update or delete.
+//! - [`WriteOps`]: An enum representing a write operation type. It can be create, put, update, patch, delete or reencrypt.
+//! - [`WriteResponse`]: An enum representing the outcome of a write job, sent back once it is committed.
 //!
 //! The `models` module depends on the following modules:
 //!
 //! - [`WasmModule`]: Represents a write request type.
 
-use crate::database::models::WasmModule;
+use crate::{
+    auth::Identity,
+    database::models::{MetadataPatch, WasmModule},
+    errors::WessError,
+};
+use serde::Serialize;
+use tokio::sync::oneshot::Sender;
 
 /// # Write Job Type
+#[derive(Debug)]
 pub struct WriteJob {
     pub write_op: WriteOps,
     pub write_module: Option<WasmModule>,
+    pub metadata_patch: Option<MetadataPatch>,
     pub id: String,
+    /// The `If-Match` header of the request, checked against the stored module.
+    pub if_match: Option<String>,
+    /// The `Idempotency-Key` header of a create request.
+    pub idempotency_key: Option<String>,
+    /// The caller of the request, checked against the owner and ACL of the stored module.
+    pub caller: Option<Identity>,
+    pub tx: Option<Sender<WriteResponse>>,
 }
 
 impl WriteJob {
@@ -33,16 +49,105 @@ impl WriteJob {
     pub fn new(write_module: Option<WasmModule>, id: String, write_op: WriteOps) -> Self {
         Self {
             write_module,
+            metadata_patch: None,
             write_op,
             id,
+            if_match: None,
+            idempotency_key: None,
+            caller: None,
+            tx: None,
         }
     }
+
+    /// # Creates a new [`WriteOps::Patch`] job, updating only the metadata of a module.
+    ///
+    /// ## Arguments
+    ///
+    /// * `id` - The ID of the record to be patched.
+    /// * `metadata_patch` - The [`MetadataPatch`] to be applied to the stored metadata.
+    ///
+    /// ## Returns
+    ///
+    /// A new [`WriteJob`] instance without a [`WasmModule`].
+    pub fn patch(id: String, metadata_patch: MetadataPatch) -> Self {
+        Self {
+            write_module: None,
+            metadata_patch: Some(metadata_patch),
+            write_op: WriteOps::Patch,
+            id,
+            if_match: None,
+            idempotency_key: None,
+            caller: None,
+            tx: None,
+        }
+    }
+
+    /// # Sets the `If-Match` precondition of the job.
+    ///
+    /// The [`Writer`] fails the job with status 412 unless the stored module has one
+    /// of the given ETags.
+    ///
+    /// [`Writer`]: super::Writer
+    pub fn if_match(mut self, if_match: Option<String>) -> Self {
+        self.if_match = if_match;
+        self
+    }
+
+    /// # Sets the `Idempotency-Key` of a [`WriteOps::Create`] job.
+    ///
+    /// The [`Writer`] answers a job whose key already created a module with that
+    /// module instead of creating another one.
+    ///
+    /// [`Writer`]: super::Writer
+    pub fn idempotency_key(mut self, idempotency_key: Option<String>) -> Self {
+        self.idempotency_key = idempotency_key;
+        self
+    }
+
+    /// # Sets the caller of the job.
+    ///
+    /// The [`Writer`] makes the caller the owner of the modules it creates, and fails
+    /// the job with status 403 if the caller can't manage the module it changes.
+    /// Jobs without a caller, sent when authentication is disabled, create modules
+    /// without an owner.
+    ///
+    /// [`Writer`]: super::Writer
+    pub fn caller(mut self, caller: Option<Identity>) -> Self {
+        self.caller = caller;
+        self
+    }
+
+    /// # Sets the channel the [`Writer`] sends the outcome of the job to.
+    ///
+    /// [`Writer`]: super::Writer
+    pub fn with_responder(mut self, tx: Sender<WriteResponse>) -> Self {
+        self.tx = Some(tx);
+        self
+    }
 }
 
 /// # Write Operation Type
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum WriteOps {
     Create,
+    /// Creates the module under the given ID, or replaces it if it exists.
+    Put,
     Update,
+    Patch,
     Delete,
+    /// Rewrites every stored record not encrypted with the current key, see
+    /// [`ModuleStore::reencrypt`]. Only admins may send it.
+    ///
+    /// [`ModuleStore::reencrypt`]: crate::database::ModuleStore::reencrypt
+    Reencrypt,
+}
+
+/// # The outcome of a [`WriteJob`]
+#[derive(Serialize, Debug)]
+pub enum WriteResponse {
+    /// The job created a module, with its ID.
+    Created(String),
+    /// The job was committed, with the ID of the written module.
+    Done(String),
+    Fail(WessError),
 }
@@ -0,0 +1,122 @@
+//! # The `status` module keeps the status of fire-and-forget write jobs
+//!
+//! This module contains the following main components:
+//!
+//! - [`WriteStatus`]: An enum representing the status of a write job.
+//! - [`WriteStatuses`]: A bounded, shared map from job ID to [`WriteStatus`].
+//!
+//! Only the most recent [`STATUS_CAPACITY`] jobs are kept, older ones are forgotten
+//! in the order they were tracked.
+
+use crate::errors::{ErrorCode, WessError};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+/// The number of write jobs whose status is kept.
+pub const STATUS_CAPACITY: usize = 4096;
+
+/// # The status of a write job
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum WriteStatus {
+    /// The job is queued or being written.
+    Pending { id: String },
+    /// The job was committed.
+    Done { id: String },
+    /// The job failed.
+    Failed {
+        id: String,
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+impl WriteStatus {
+    /// # Creates the final status of a job from the error it failed with.
+    pub fn failed(id: String, error: &WessError) -> Self {
+        WriteStatus::Failed {
+            id,
+            code: error.code,
+            message: error.msg.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Statuses {
+    by_job: HashMap<String, WriteStatus>,
+    order: VecDeque<String>,
+}
+
+/// # The status of the most recent fire-and-forget write jobs
+#[derive(Clone, Default)]
+pub struct WriteStatuses {
+    inner: Arc<Mutex<Statuses>>,
+}
+
+impl WriteStatuses {
+    /// # Creates an empty [`WriteStatuses`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Sets the status of a job, tracking it if it is new.
+    ///
+    /// ## Arguments
+    ///
+    /// * `job` - The ID of the job.
+    /// * `status` - The new [`WriteStatus`] of the job.
+    pub fn set(&self, job: &str, status: WriteStatus) {
+        let mut statuses = self.inner.lock().unwrap();
+
+        if !statuses.by_job.contains_key(job) {
+            if statuses.order.len() >= STATUS_CAPACITY {
+                if let Some(oldest) = statuses.order.pop_front() {
+                    statuses.by_job.remove(&oldest);
+                }
+            }
+            statuses.order.push_back(job.to_string());
+        }
+        statuses.by_job.insert(job.to_string(), status);
+    }
+
+    /// # Gets the status of a job.
+    ///
+    /// ## Returns
+    ///
+    /// * An `Option` with the [`WriteStatus`], or `None` if the job is unknown or forgotten.
+    pub fn get(&self, job: &str) -> Option<WriteStatus> {
+        self.inner.lock().unwrap().by_job.get(job).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_get() {
+        let statuses = WriteStatuses::new();
+        let id = "module".to_string();
+
+        statuses.set("job", WriteStatus::Pending { id: id.clone() });
+        statuses.set("job", WriteStatus::Done { id: id.clone() });
+
+        assert_eq!(statuses.get("job"), Some(WriteStatus::Done { id }));
+        assert_eq!(statuses.get("unknown"), None);
+    }
+
+    #[test]
+    fn test_forget_oldest() {
+        let statuses = WriteStatuses::new();
+        for job in 0..=STATUS_CAPACITY {
+            statuses.set(&job.to_string(), WriteStatus::Pending { id: String::new() });
+        }
+
+        assert_eq!(statuses.get("0"), None);
+        assert!(statuses.get(&STATUS_CAPACITY.to_string()).is_some());
+    }
+}
@@ -13,7 +13,7 @@ Feature: Test WasmModule CRUD Operations
 
     Scenario: Create a WasmModule
         When sending the wasm "the_answer" to create a new WasmModule
-        And the response status code is "202"
+        And the response status code is "201"
         And the response body matches the default UUID
         And the ID is saved in "the_answer_created"
         Then Wess must log the "CREATE" operation with the ID "the_answer_created"
@@ -21,12 +21,12 @@ Feature: Test WasmModule CRUD Operations
 
     Scenario: Updating a WasmModule
         When sending the wasm "fibonacci" to update the ID "the_answer_created"
-        And the response status code is "202"
+        And the response status code is "200"
         Then Wess must log the "UPDATE" operation with the ID "the_answer_created"
         And log must matches the pattern "(\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}) \| (ERROR|WARN|INFO|DEBUG|TRACE) (wess|wess::tx|wess::err) \| (src\/\S+\.rs:\d+) - (.+)"
 
     Scenario: Delete a WasmModule
         When sending the ID "the_answer_created" to delete
-        And the response status code is "202"
+        And the response status code is "204"
         Then Wess must log the "DELETE" operation with the ID "the_answer_created"
         And log must matches the pattern "(\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}) \| (ERROR|WARN|INFO|DEBUG|TRACE) (wess|wess::tx|wess::err) \| (src\/\S+\.rs:\d+) - (.+)"
@@ -8,7 +8,7 @@ def modify_wess_toml(stage, url, port):
     wess_toml_path = "wess.toml"
     with open(wess_toml_path, "r") as toml_file:
         wess_toml = toml.load(toml_file)
-        wess_toml["database"]["db"] = stage
+        wess_toml["database"]["stage"] = stage
         wess_toml["server"]["address"] = url
         wess_toml["server"]["port"] = port
     with open(wess_toml_path, "w") as toml_file: